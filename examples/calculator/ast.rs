@@ -0,0 +1,343 @@
+/*
+# Abstract syntax tree
+
+The shunting yard produces a flat list of tokens in RPN, which is great for
+evaluation but not for anything that needs to look at the *structure* of an
+expression (pretty printing, simplification, analysis...).
+
+Turning RPN into a tree is the same stack walk the VM compiler does (see
+`bytecode.rs`), except that instead of pushing code we push sub trees.
+*/
+
+use std::fmt;
+
+use crate::numeric::Numeric;
+use crate::token::{Operator, Span, Spanned, Token};
+use crate::units;
+use crate::{Error, EvalError};
+
+#[derive(Debug, Clone, PartialEq)]
+/// The different kinds of nodes in the tree
+pub enum ExprKind {
+    Number(f64),
+    /// A number with a unit, `20 km/h`, only units mode evaluates them
+    Quantity {
+        value: f64,
+        unit: String,
+    },
+    Variable(String),
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Unary {
+        op: Operator,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: Operator,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// `cond ? then : otherwise`
+    Conditional {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A node of the tree along with the part of the source it was parsed from.
+///
+/// Brackets are not part of the tree, so the span of `(1 + 2)` covers `1 + 2`.
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// The leaf for a literal from the tokenizer, with or without a unit.
+    pub fn literal(literal: String, span: Span) -> Result<Expr, Spanned<Error>> {
+        let (number, unit) = units::split(&literal);
+        let num = f64::literal(number, &()).map_err(|e| {
+            let error = match e {
+                EvalError::Overflow => Error::LiteralOverflow(literal.clone()),
+                _ => Error::BadNumber(literal.clone()),
+            };
+            Spanned::new(error, span)
+        })?;
+
+        let kind = match unit {
+            Some(unit) => ExprKind::Quantity {
+                value: num,
+                unit: unit.to_string(),
+            },
+            None => ExprKind::Number(num),
+        };
+        Ok(Expr::new(kind, span))
+    }
+
+    /// Build the tree out of the RPN produced by `Calculator::expression`.
+    pub fn from_rpn(rpn: Vec<Spanned<Token>>) -> Result<Expr, Spanned<Error>> {
+        let mut stack: Vec<Expr> = vec![];
+
+        for token in rpn {
+            // an operand missing for this token is reported at the token
+            let missing = || Spanned::new(Error::MissingOperand, token.span);
+
+            match token.node {
+                Token::Number(literal) => stack.push(Expr::literal(literal, token.span)?),
+                Token::Ident(name) => stack.push(Expr::new(ExprKind::Variable(name), token.span)),
+                Token::Call(name, count) => {
+                    let first = stack.len().checked_sub(count).ok_or_else(missing)?;
+                    let args = stack.split_off(first);
+
+                    stack.push(Expr::new(ExprKind::Call { name, args }, token.span));
+                }
+                Token::Op(Operator::Cond) => {
+                    let otherwise = stack.pop().ok_or_else(missing)?;
+                    let then = stack.pop().ok_or_else(missing)?;
+                    let cond = stack.pop().ok_or_else(missing)?;
+                    let span = cond.span.to(otherwise.span);
+
+                    stack.push(Expr::new(
+                        ExprKind::Conditional {
+                            cond: Box::new(cond),
+                            then: Box::new(then),
+                            otherwise: Box::new(otherwise),
+                        },
+                        span,
+                    ));
+                }
+                Token::Op(op) if op.is_unary() => {
+                    let operand = stack.pop().ok_or_else(missing)?;
+                    let span = token.span.to(operand.span);
+
+                    stack.push(Expr::new(
+                        ExprKind::Unary {
+                            op,
+                            operand: Box::new(operand),
+                        },
+                        span,
+                    ));
+                }
+                Token::Op(op) => {
+                    // the right hand side was pushed last
+                    let rhs = stack.pop().ok_or_else(missing)?;
+                    let lhs = stack.pop().ok_or_else(missing)?;
+                    let span = lhs.span.to(rhs.span);
+
+                    stack.push(Expr::new(
+                        ExprKind::Binary {
+                            op,
+                            lhs: Box::new(lhs),
+                            rhs: Box::new(rhs),
+                        },
+                        span,
+                    ));
+                }
+                // the tree is of an expression, conversions apply to a line
+                Token::Convert(_) => {
+                    return Err(Spanned::new(Error::MisplacedConversion, token.span))
+                }
+                Token::Bracket(_)
+                | Token::Assign
+                | Token::Func(_)
+                | Token::Comma
+                | Token::Question
+                | Token::Colon => {}
+            }
+        }
+
+        let expr = stack
+            .pop()
+            .ok_or_else(|| Spanned::new(Error::MissingOperand, Span::new(0, 0)))?;
+
+        // more than one tree left means two operands with nothing joining them,
+        // the operator is missing right before the second one.
+        if !stack.is_empty() {
+            let span = stack.get(1).unwrap_or(&expr).span;
+            return Err(Spanned::new(Error::MissingOperator, span));
+        }
+
+        Ok(expr)
+    }
+
+    /// Turn the tree back into RPN, to evaluate or compile it (see
+    /// `bytecode.rs`). Operators get the span of their whole node.
+    pub fn rpn(&self) -> Vec<Spanned<Token>> {
+        let mut rpn = vec![];
+        self.push_rpn(&mut rpn);
+        rpn
+    }
+
+    fn push_rpn(&self, rpn: &mut Vec<Spanned<Token>>) {
+        let token = match &self.kind {
+            ExprKind::Number(num) => Token::Number(num.to_string()),
+            ExprKind::Quantity { value, unit } => Token::Number(format!("{} {}", value, unit)),
+            ExprKind::Variable(name) => Token::Ident(name.clone()),
+            ExprKind::Call { name, args } => {
+                for arg in args {
+                    arg.push_rpn(rpn);
+                }
+                Token::Call(name.clone(), args.len())
+            }
+            ExprKind::Unary { op, operand } => {
+                operand.push_rpn(rpn);
+                Token::Op(*op)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                lhs.push_rpn(rpn);
+                rhs.push_rpn(rpn);
+                Token::Op(*op)
+            }
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => {
+                cond.push_rpn(rpn);
+                then.push_rpn(rpn);
+                otherwise.push_rpn(rpn);
+                Token::Op(Operator::Cond)
+            }
+        };
+
+        rpn.push(Spanned::new(token, self.span));
+    }
+}
+
+/// Prints the tree as an s-expression: `-2 ^ 2 + 1` is `(+ (- (^ 2 2)) 1)`.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Number(num) => write!(f, "{}", num),
+            ExprKind::Quantity { value, unit } => write!(f, "{} {}", value, unit),
+            ExprKind::Variable(name) => write!(f, "{}", name),
+            ExprKind::Call { name, args } => {
+                write!(f, "({}", name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            // postfix operators come last, `!` would read as the logical not
+            ExprKind::Unary { op, operand } if op.is_postfix() => {
+                write!(f, "({} {})", operand, op)
+            }
+            ExprKind::Unary { op, operand } => write!(f, "({} {})", op, operand),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({} {} {})", op, lhs, rhs),
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => write!(f, "(? {} {} {})", cond, then, otherwise),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::token::{Span, Spanned, Token};
+    use crate::Calculator;
+
+    fn parse(expr: &str) -> super::Expr {
+        Calculator::parse(Calculator::tokenize(expr).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn builds_the_tree() {
+        assert_eq!(parse("1 + 2 * 3").to_string(), "(+ 1 (* 2 3))");
+        assert_eq!(parse("(1 + 2) * 3").to_string(), "(* (+ 1 2) 3)");
+        assert_eq!(parse("8 - 3 - 2").to_string(), "(- (- 8 3) 2)");
+        assert_eq!(parse("2 ^ 3 ^ 2").to_string(), "(^ 2 (^ 3 2))");
+        assert_eq!(parse("-2 ^ 2 + 1").to_string(), "(+ (- (^ 2 2)) 1)");
+        assert_eq!(parse("x * 2 + y").to_string(), "(+ (* x 2) y)");
+        assert_eq!(
+            parse("max(1, x + 2, -y) * 2").to_string(),
+            "(* (max 1 (+ x 2) (- y)) 2)"
+        );
+        assert_eq!(
+            parse("a > 1 && !b || c == 2").to_string(),
+            "(|| (&& (> a 1) (! b)) (== c 2))"
+        );
+        assert_eq!(
+            parse("a ? b : c ? d : e + 1").to_string(),
+            "(? a b (? c d (+ e 1)))"
+        );
+        assert_eq!(
+            parse("a | b xor c & d == e << 1 + 1").to_string(),
+            "(| a (xor b (& c (== d (<< e (+ 1 1))))))"
+        );
+        assert_eq!(parse("~a & 0xf").to_string(), "(& (~ a) 15)");
+        assert_eq!(parse("a ? b ? c : d : e").to_string(), "(? a (? b c d) e)");
+    }
+
+    #[test]
+    fn records_spans() {
+        let expr = parse("12 + -(3.5 * 4)");
+        assert_eq!(expr.span, Span::new(0, 14));
+
+        match expr.kind {
+            super::ExprKind::Binary { lhs, rhs, .. } => {
+                assert_eq!(lhs.span, Span::new(0, 2));
+                // the unary minus starts at its operator
+                assert_eq!(rhs.span, Span::new(5, 14));
+            }
+            _ => panic!("expected a binary node"),
+        }
+
+        // a call covers its name and its brackets
+        assert_eq!(parse("1 + sqrt(4)").span, Span::new(0, 11));
+    }
+
+    #[test]
+    fn back_to_rpn() {
+        for expr in [
+            "-2 ^ 2 + max(1, x, y * 3)",
+            "a ? b : c ? 0xf : ~e",
+            "!(a < 2.5)",
+        ] {
+            let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap()).unwrap();
+            let nodes = |rpn: Vec<Spanned<Token>>| {
+                rpn.into_iter()
+                    .map(|t| t.node)
+                    .filter(|t| !matches!(t, Token::Question | Token::Colon))
+                    .map(|t| match t {
+                        // literals are printed back in decimal
+                        Token::Number(n) => Token::Number(n.replace("0xf", "15")),
+                        t => t,
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(nodes(parse(expr).rpn()), nodes(rpn), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn rejects_incomplete_trees() {
+        let parse = |expr| Calculator::parse(Calculator::tokenize(expr).unwrap());
+
+        let error = parse("2 +").unwrap_err();
+        assert!(matches!(error.first().node, crate::Error::MissingOperand));
+        assert_eq!(error.first().span, Span::new(2, 3));
+
+        let error = parse("").unwrap_err();
+        assert!(matches!(error.first().node, crate::Error::MissingOperand));
+
+        let error = parse("2 3").unwrap_err();
+        assert!(matches!(error.first().node, crate::Error::MissingOperator));
+        assert_eq!(error.first().span, Span::new(2, 3));
+
+        // a float can't hold it, there's no number for the leaf
+        let error = parse("x + 1e999").unwrap_err();
+        assert!(matches!(&error.first().node, crate::Error::LiteralOverflow(n) if n == "1e999"));
+        assert_eq!(error.first().span, Span::new(4, 9));
+    }
+}
@@ -0,0 +1,710 @@
+/*
+# Arbitrary precision integers
+
+A signed integer stored as its sign plus its magnitude in base 2^32 "limbs"
+(least significant first). Zero is an empty magnitude and is never negative,
+and the magnitude never has leading (most significant) zero limbs.
+
+Addition, subtraction and multiplication are the schoolbook algorithms. The
+division is Knuth's "Algorithm D" (The Art of Computer Programming, vol 2,
+4.3.1), which is the schoolbook long division with a smart guess of every
+quotient digit.
+
+- See: https://en.wikipedia.org/wiki/Arbitrary-precision_arithmetic
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+const BASE: u64 = 1 << 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt::default()
+    }
+
+    pub fn one() -> Self {
+        BigInt::from(1)
+    }
+
+    fn from_magnitude(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        trim(&mut magnitude);
+        let negative = negative && !magnitude.is_empty();
+        BigInt {
+            negative,
+            magnitude,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_magnitude(false, self.magnitude.clone())
+    }
+
+    /// How many bits are needed to write the magnitude (0 for zero).
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(top) => self.magnitude.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    /// Division truncating towards zero, like the `/` and `%` of the primitive
+    /// integers: the remainder has the sign of `self`. Returns `None` when
+    /// dividing by zero.
+    pub fn divmod(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+
+        let (q, r) = divmod_magnitude(&self.magnitude, &divisor.magnitude);
+
+        Some((
+            BigInt::from_magnitude(self.negative != divisor.negative, q),
+            BigInt::from_magnitude(self.negative, r),
+        ))
+    }
+
+    /// Greatest common divisor (always positive, `gcd(0, 0)` is 0).
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+
+        while !b.is_zero() {
+            let (_, r) = a.divmod(&b).unwrap();
+            a = b;
+            b = r;
+        }
+
+        a
+    }
+
+    /// `self ^ exponent` by repeated squaring
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::one();
+        let mut base = self.clone();
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+
+        result
+    }
+
+    /// The `n`th root of `self`, if it is an integer. Newton's method from
+    /// above, on integers: it stops at the floor of the root.
+    pub fn exact_root(&self, n: u32) -> Option<BigInt> {
+        if self.negative || n == 0 {
+            return None;
+        }
+
+        // a root of 2 or more needs more than `n` bits
+        if self.bits() <= n as u64 {
+            return (self.is_zero() || *self == BigInt::one()).then(|| self.clone());
+        }
+
+        let degree = BigInt::from(n as i64);
+        let mut root = BigInt::one().shl(self.bits().div_ceil(n as u64));
+
+        loop {
+            // ((n - 1) root + self / root^(n - 1)) / n
+            let (quotient, _) = self.divmod(&root.pow(n - 1)).unwrap();
+            let sum = &(&BigInt::from(n as i64 - 1) * &root) + &quotient;
+            let (next, _) = sum.divmod(&degree).unwrap();
+
+            if next >= root {
+                break;
+            }
+            root = next;
+        }
+
+        (root.pow(n) == *self).then_some(root)
+    }
+
+    /// Multiply by `2^bits`.
+    pub fn shl(&self, bits: u64) -> BigInt {
+        let limbs = (bits / 32) as usize;
+        let mut magnitude = vec![0; limbs];
+        magnitude.extend(shl_bits(&self.magnitude, (bits % 32) as u32));
+        BigInt::from_magnitude(self.negative, magnitude)
+    }
+
+    /// Divide the magnitude by `2^bits` (truncating), keeping the sign.
+    pub fn shr(&self, bits: u64) -> BigInt {
+        let limbs = (bits / 32) as usize;
+
+        if limbs >= self.magnitude.len() {
+            return BigInt::zero();
+        }
+
+        let magnitude = shr_bits(&self.magnitude[limbs..], (bits % 32) as u32);
+        BigInt::from_magnitude(self.negative, magnitude)
+    }
+
+    /// The closest `f64`, give or take the last bit. Too big magnitudes are
+    /// infinite.
+    pub fn to_f64(&self) -> f64 {
+        // keep the 64 most significant bits and scale them back
+        let shift = self.bits().saturating_sub(64);
+        let top = self.abs().shr(shift);
+
+        let mut value = 0.0;
+        for limb in top.magnitude.iter().rev() {
+            value = value * BASE as f64 + *limb as f64;
+        }
+
+        let value = value * 2f64.powi(shift.min(i32::MAX as u64) as i32);
+
+        if self.negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// The value if it fits in an `i64`.
+    pub fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self.to_i128()?).ok()
+    }
+
+    /// The value if it fits in an `i128`.
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.bits() > 128 {
+            return None;
+        }
+
+        let mut magnitude: u128 = 0;
+        for limb in self.magnitude.iter().rev() {
+            magnitude = (magnitude << 32) | *limb as u128;
+        }
+
+        if self.negative {
+            0i128.checked_sub_unsigned(magnitude)
+        } else {
+            i128::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn from_i128(value: i128) -> Self {
+        let magnitude = value.unsigned_abs();
+        let limbs = (0..4).map(|i| (magnitude >> (32 * i)) as u32).collect();
+        BigInt::from_magnitude(value < 0, limbs)
+    }
+
+    /// Parse unsigned digits in base `radix` (2 to 36), like `ff` in base 16.
+    pub fn from_str_radix(digits: &str, radix: u32) -> Result<BigInt, ParseBigIntError> {
+        if digits.is_empty() {
+            return Err(ParseBigIntError);
+        }
+
+        let mut magnitude = vec![];
+        for c in digits.chars() {
+            let digit = c.to_digit(radix).ok_or(ParseBigIntError)?;
+            mul_add_small(&mut magnitude, radix, digit);
+        }
+
+        Ok(BigInt::from_magnitude(false, magnitude))
+    }
+
+    /// The exact value of a finite `f64` (all of them are integers times a
+    /// power of two), split as `mantissa * 2^exponent`.
+    pub fn from_f64_parts(value: f64) -> Option<(BigInt, i32)> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        let bits = value.to_bits();
+        let negative = bits >> 63 == 1;
+        let exponent = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = bits & ((1 << 52) - 1);
+
+        // subnormal numbers have no implicit leading 1
+        let (mantissa, exponent) = match exponent {
+            0 => (fraction, -1074),
+            _ => (fraction | (1 << 52), exponent - 1075),
+        };
+
+        let mantissa =
+            BigInt::from_magnitude(negative, vec![mantissa as u32, (mantissa >> 32) as u32]);
+
+        Some((mantissa, exponent))
+    }
+
+    fn add_signed(&self, other: &BigInt, other_negative: bool) -> BigInt {
+        if self.negative == other_negative {
+            return BigInt::from_magnitude(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+
+        // different signs: subtract the smaller magnitude from the bigger one
+        match cmp_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::from_magnitude(
+                other_negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::from_magnitude(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        BigInt::from_i128(value as i128)
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        self.add_signed(rhs, rhs.negative)
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self.add_signed(rhs, !rhs.negative && !rhs.is_zero())
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt::from_magnitude(
+            self.negative != rhs.negative,
+            mul_magnitude(&self.magnitude, &rhs.magnitude),
+        )
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_magnitude(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+/// Parses an optionally signed string of decimal digits.
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+
+        // feed the digits 9 at a time, the most that fit in a limb
+        let mut magnitude = vec![];
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            let scale = 10u32.pow(chunk.len() as u32);
+            mul_add_small(&mut magnitude, scale, chunk.parse().unwrap());
+        }
+
+        Ok(BigInt::from_magnitude(negative, magnitude))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // peel off 9 decimal digits at a time, least significant first
+        let mut chunks = vec![];
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (q, r) = div_small(&magnitude, 1_000_000_000);
+            chunks.push(r);
+            magnitude = q;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn trim(magnitude: &mut Vec<u32>) {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0;
+
+    for (i, limb) in long.iter().enumerate() {
+        let sum = *limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+
+    result.push(carry as u32);
+    trim(&mut result);
+    result
+}
+
+/// `a - b`, where `a >= b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+
+    for (i, limb) in a.iter().enumerate() {
+        let diff = *limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        result.push(diff as u32);
+        borrow = (diff < 0) as i64;
+    }
+
+    trim(&mut result);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+
+    let mut result = vec![0u32; a.len() + b.len()];
+
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0;
+
+        for (j, y) in b.iter().enumerate() {
+            let product = *x as u64 * *y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+
+        result[i + b.len()] = carry as u32;
+    }
+
+    trim(&mut result);
+    result
+}
+
+/// `magnitude = magnitude * factor + addend`
+fn mul_add_small(magnitude: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+
+    for limb in magnitude.iter_mut() {
+        let value = *limb as u64 * factor as u64 + carry;
+        *limb = value as u32;
+        carry = value >> 32;
+    }
+
+    magnitude.push(carry as u32);
+    trim(magnitude);
+}
+
+fn div_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+
+    for i in (0..a.len()).rev() {
+        let value = (remainder << 32) | a[i] as u64;
+        quotient[i] = (value / divisor as u64) as u32;
+        remainder = value % divisor as u64;
+    }
+
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+/// Shift left by less than a limb. The result has one extra limb (which may
+/// be zero) so the caller can rely on its length.
+fn shl_bits(a: &[u32], bits: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u32;
+
+    for limb in a {
+        result.push((limb << bits) | carry);
+        carry = if bits == 0 { 0 } else { limb >> (32 - bits) };
+    }
+
+    result.push(carry);
+    result
+}
+
+/// Shift right by less than a limb.
+fn shr_bits(a: &[u32], bits: u32) -> Vec<u32> {
+    let mut result = vec![0u32; a.len()];
+
+    for i in 0..a.len() {
+        let high = match a.get(i + 1) {
+            Some(next) if bits > 0 => next << (32 - bits),
+            _ => 0,
+        };
+        result[i] = (a[i] >> bits) | high;
+    }
+
+    trim(&mut result);
+    result
+}
+
+/// Knuth's algorithm D. Divides the magnitudes `u` by `v` (not zero).
+fn divmod_magnitude(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_magnitude(u, v) == Ordering::Less {
+        return (vec![], u.to_vec());
+    }
+
+    if v.len() == 1 {
+        let (q, r) = div_small(u, v[0]);
+        let mut r = vec![r];
+        trim(&mut r);
+        return (q, r);
+    }
+
+    // Normalize: shift both so the top bit of the divisor is set. That keeps
+    // the guessed quotient digits at most 2 away from the real ones.
+    let shift = v[v.len() - 1].leading_zeros();
+    let mut v = shl_bits(v, shift);
+    v.pop();
+    let mut u = shl_bits(u, shift);
+
+    let n = v.len();
+    let m = u.len() - n - 1;
+    let mut q = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        // guess the quotient digit out of the top two limbs
+        let top = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = top / v[n - 1] as u64;
+        let mut rhat = top % v[n - 1] as u64;
+
+        while qhat >= BASE || qhat * v[n - 2] as u64 > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += v[n - 1] as u64;
+
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        // u[j..j + n + 1] -= qhat * v
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = qhat * v[i] as u64 + carry;
+            carry = product >> 32;
+
+            let diff = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            u[i + j] = diff as u32;
+            borrow = (diff < 0) as i64;
+        }
+
+        let diff = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = diff as u32;
+
+        // the guess was one too big (rare), add the divisor back
+        if diff < 0 {
+            qhat -= 1;
+
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+
+        q[j] = qhat as u32;
+    }
+
+    trim(&mut q);
+    let remainder = shr_bits(&u[..n], shift);
+    (q, remainder)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BigInt, ParseBigIntError};
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_prints() {
+        for s in [
+            "0",
+            "7",
+            "-42",
+            "4294967296",
+            "123456789012345678901234567890",
+        ] {
+            assert_eq!(big(s).to_string(), s);
+        }
+
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(big("+0012").to_string(), "12");
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!((&a - &a), BigInt::zero());
+        assert_eq!((-&a).to_string(), "-123456789012345678901234567890");
+    }
+
+    #[test]
+    fn division_truncates_towards_zero() {
+        let check = |a: i64, b: i64| {
+            let (q, r) = BigInt::from(a).divmod(&BigInt::from(b)).unwrap();
+            assert_eq!((q, r), (BigInt::from(a / b), BigInt::from(a % b)));
+        };
+
+        check(7, 2);
+        check(-7, 2);
+        check(7, -2);
+        check(-7, -2);
+        check(i64::MAX, 3);
+
+        assert_eq!(BigInt::one().divmod(&BigInt::zero()), None);
+    }
+
+    #[test]
+    fn long_division() {
+        // (a * b + c) / b == a remainder c, for a c smaller than b
+        let a = big("98765432109876543210987654321098765432109876543210");
+        let b = big("18446744073709551617000000000000000000001");
+        let c = big("18446744073709551616999999999999999999999");
+
+        let (q, r) = (&(&a * &b) + &c).divmod(&b).unwrap();
+        assert_eq!(q, a);
+        assert_eq!(r, c);
+    }
+
+    #[test]
+    fn gcd_and_shifts() {
+        assert_eq!(BigInt::from(84).gcd(&BigInt::from(-36)), BigInt::from(12));
+        assert_eq!(BigInt::from(0).gcd(&BigInt::from(5)), BigInt::from(5));
+        assert_eq!(
+            BigInt::one().shl(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(BigInt::one().shl(100).shr(99), BigInt::from(2));
+        assert_eq!(BigInt::one().shl(100).bits(), 101);
+        assert_eq!(BigInt::from(2).pow(100), BigInt::one().shl(100));
+        assert_eq!(BigInt::from(-3).pow(3), BigInt::from(-27));
+    }
+
+    #[test]
+    fn exact_roots() {
+        let square = big("123456789012345678901234567890").pow(2);
+        assert_eq!(
+            square.exact_root(2),
+            Some(big("123456789012345678901234567890"))
+        );
+        assert_eq!((&square + &BigInt::one()).exact_root(2), None);
+        assert_eq!(
+            BigInt::from(1 << 30).exact_root(3),
+            Some(BigInt::from(1024))
+        );
+        assert_eq!(BigInt::from(80).exact_root(4), None);
+        assert_eq!(BigInt::from(1).exact_root(1000), Some(BigInt::one()));
+        assert_eq!(BigInt::from(0).exact_root(2), Some(BigInt::zero()));
+        assert_eq!(BigInt::from(-8).exact_root(3), None);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(BigInt::from(-12345).to_f64(), -12345.0);
+        assert_eq!(BigInt::one().shl(200).to_f64(), 2f64.powi(200));
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::one().shl(63).to_i64(), None);
+        assert_eq!(BigInt::from_i128(i128::MIN).to_i128(), Some(i128::MIN));
+        assert_eq!(BigInt::from_i128(u64::MAX as i128).to_i64(), None);
+        assert_eq!(
+            BigInt::from_str_radix("ffffffffffffffffff", 16).map(|n| n.to_string()),
+            Ok("4722366482869645213695".to_string())
+        );
+        assert_eq!(BigInt::from_str_radix("1012", 2), Err(ParseBigIntError));
+
+        let (mantissa, exponent) = BigInt::from_f64_parts(0.375).unwrap();
+        assert_eq!(mantissa.to_f64() * 2f64.powi(exponent), 0.375);
+    }
+}
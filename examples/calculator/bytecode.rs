@@ -0,0 +1,808 @@
+/*
+# Bytecode
+
+Walking the RPN tokens every time an expression runs means parsing the
+literals again, looking the variables and the functions up by name and
+carrying a span around with every value. That's fine for a line typed in the
+REPL, not for a formula evaluated over millions of rows.
+
+`Program::compile` does all of that once and produces instructions for a
+small stack machine (`Vm`):
+
+- literals are parsed into a table of constants, `Const(i)` pushes one;
+- variables get a slot, `Load(i)` pushes the value of slot `i` of the row
+  the program runs on (see `Program::slots`);
+- functions are resolved to their index in the registry, the others (the
+  functions of a script, see `script.rs`) are called by name through
+  `Vm::run_calling`;
+- `&&`, `||` and `? :` become jumps, so the side that isn't needed is never
+  evaluated;
+- the unit of a `to` conversion is parsed into a table of units, `Convert(i)`
+  converts the value on top of the stack to unit `i`.
+
+The spans are kept on the side and only looked at when something fails, so
+errors still point at the part of the expression that is wrong. The tests
+check all of it against the tree walking evaluator the VM replaced.
+
+- See: https://craftinginterpreters.com/a-bytecode-virtual-machine.html
+*/
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::env::Environment;
+use crate::functions::{self, FUNCTIONS};
+use crate::numeric::{self, Numeric};
+use crate::token::{Operator, Span, Spanned, Token};
+use crate::trace::Observer;
+use crate::units::{self, Unit};
+use crate::{EvalError, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// An instruction of the stack machine. Jumps are relative to the next
+/// instruction, and always go forward.
+pub enum Instruction {
+    /// Push a constant, by index
+    Const(u32),
+    /// Push the value of a variable, by slot
+    Load(u32),
+    /// Pop one value and push the result of a prefix operator
+    Unary(Operator),
+    /// Pop two values and push the result of a binary operator, except for
+    /// `&&`, `||` and `? :`
+    Binary(Operator),
+    /// Pop the arguments and push the result of a function, by index in
+    /// the registry and number of arguments
+    Call(u16, u16),
+    /// `Call` for a function that isn't in the registry, by index in the
+    /// names of the program and number of arguments
+    Invoke(u32, u16),
+    /// Pop a boolean and skip that many instructions if it's false
+    JumpIfFalse(u32),
+    /// Skip that many instructions
+    Jump(u32),
+    /// The first half of `&&` and `||`: when the boolean on top of the stack
+    /// decides the result (`false` for `&&`, `true` for `||`) leave it there
+    /// and skip the right hand side, otherwise pop it
+    ShortCircuit(Operator, u32),
+    /// The right hand side of `&&` and `||` must be a boolean
+    ExpectBool,
+    /// Pop a number and push it converted to a unit, by index
+    Convert(u32),
+    /// Fail with an error found while compiling, like a literal that doesn't
+    /// fit. It's only reported if the instruction is reached.
+    Fail(u32),
+}
+
+/// The parts of the source an instruction refers to: the token it comes
+/// from and its operands, in order.
+#[derive(Debug, Clone)]
+struct Site {
+    at: Span,
+    operands: Vec<Span>,
+}
+
+impl Site {
+    fn new(at: Span, operands: Vec<Span>) -> Self {
+        Site { at, operands }
+    }
+
+    fn operand(&self, i: usize) -> Span {
+        self.operands.get(i).copied().unwrap_or(self.at)
+    }
+}
+
+/// A compiled expression, `N` is the kind of numbers it computes with.
+pub struct Program<N> {
+    code: Vec<Instruction>,
+    /// Side table of `code`, only read when an instruction fails
+    sites: Vec<Site>,
+    constants: Vec<N>,
+    failures: Vec<EvalError>,
+    slots: Vec<String>,
+    /// Functions the registry doesn't have, see `Vm::run_calling`
+    callees: Vec<String>,
+    units: Vec<Unit>,
+}
+
+/// The empty program, it stands for an expression that didn't compile and
+/// fails with a missing operand if it's ever run.
+impl<N> Default for Program<N> {
+    fn default() -> Self {
+        Program {
+            code: vec![],
+            sites: vec![],
+            constants: vec![],
+            failures: vec![],
+            slots: vec![],
+            callees: vec![],
+            units: vec![],
+        }
+    }
+}
+
+/// A piece of program computing one sub expression. Jumps being relative,
+/// pieces can be glued together as they are.
+struct Fragment {
+    code: Vec<Instruction>,
+    sites: Vec<Site>,
+    span: Span,
+}
+
+impl Fragment {
+    fn new(instruction: Instruction, site: Site, span: Span) -> Self {
+        Fragment {
+            code: vec![instruction],
+            sites: vec![site],
+            span,
+        }
+    }
+
+    fn push(&mut self, instruction: Instruction, site: Site) {
+        self.code.push(instruction);
+        self.sites.push(site);
+    }
+
+    fn append(&mut self, other: Fragment) {
+        self.code.extend(other.code);
+        self.sites.extend(other.sites);
+    }
+
+    fn len(&self) -> u32 {
+        self.code.len() as u32
+    }
+}
+
+impl<N: Numeric> Program<N> {
+    /// Compile an expression in RPN (see `Calculator::expression`). `context`
+    /// holds the settings of the backend, like for `Calculator::execute_with`.
+    ///
+    /// Malformed RPN is reported here, before anything runs.
+    pub fn compile(
+        rpn: &[Spanned<Token>],
+        context: &N::Context,
+    ) -> Result<Program<N>, Spanned<EvalError>> {
+        let mut program = Program::default();
+
+        // the same literal (negated or not) or variable only gets one
+        // constant or slot
+        let mut constants: HashMap<(&str, bool), u32> = HashMap::new();
+        let mut slots: HashMap<&str, u32> = HashMap::new();
+
+        let mut stack: Vec<Fragment> = vec![];
+        let mut tokens = rpn.iter().peekable();
+
+        while let Some(token) = tokens.next() {
+            // a minus right before a literal is part of it, so `-128` is an
+            // `i8` even though `128` isn't
+            let negated = match (&token.node, tokens.peek()) {
+                (Token::Number(_), Some(next)) if next.node == Token::Op(Operator::Neg) => {
+                    tokens.next().map(|neg| neg.span)
+                }
+                _ => None,
+            };
+            let span = negated.map_or(token.span, |neg| neg.to(token.span));
+            let site = |operands| Site::new(span, operands);
+            let pop = |stack: &mut Vec<Fragment>| {
+                stack
+                    .pop()
+                    .ok_or_else(|| Spanned::new(EvalError::StackUnderflow, span))
+            };
+
+            let fragment = match &token.node {
+                Token::Number(literal) => {
+                    let key = (literal.as_str(), negated.is_some());
+                    let instruction = match constants.get(&key) {
+                        Some(&i) => Instruction::Const(i),
+                        None => match numeric::parse(literal, negated.is_some(), context) {
+                            Ok(value) => {
+                                let i = program.constants.len() as u32;
+                                program.constants.push(value);
+                                constants.insert(key, i);
+                                Instruction::Const(i)
+                            }
+                            Err(e) => {
+                                program.failures.push(e);
+                                Instruction::Fail(program.failures.len() as u32 - 1)
+                            }
+                        },
+                    };
+
+                    Fragment::new(instruction, site(vec![]), span)
+                }
+                Token::Ident(name) => {
+                    let slot = *slots.entry(name).or_insert_with(|| {
+                        program.slots.push(name.clone());
+                        program.slots.len() as u32 - 1
+                    });
+
+                    Fragment::new(Instruction::Load(slot), site(vec![]), span)
+                }
+                Token::Call(name, count) => {
+                    let instruction = match FUNCTIONS.iter().position(|f| f.name == name) {
+                        Some(index) => Instruction::Call(index as u16, *count as u16),
+                        None => {
+                            program.callees.push(name.clone());
+                            Instruction::Invoke(program.callees.len() as u32 - 1, *count as u16)
+                        }
+                    };
+                    let first = stack
+                        .len()
+                        .checked_sub(*count)
+                        .ok_or_else(|| Spanned::new(EvalError::StackUnderflow, span))?;
+
+                    let mut fragment = Fragment {
+                        code: vec![],
+                        sites: vec![],
+                        span,
+                    };
+                    let mut operands = vec![];
+                    for arg in stack.split_off(first) {
+                        operands.push(arg.span);
+                        fragment.append(arg);
+                    }
+
+                    fragment.push(instruction, site(operands));
+                    fragment
+                }
+                // cond JumpIfFalse(then + 1) then Jump(otherwise) otherwise
+                Token::Op(Operator::Cond) => {
+                    let otherwise = pop(&mut stack)?;
+                    let then = pop(&mut stack)?;
+                    let mut fragment = pop(&mut stack)?;
+                    let cond = fragment.span;
+
+                    fragment.push(Instruction::JumpIfFalse(then.len() + 1), site(vec![cond]));
+                    fragment.append(then);
+                    fragment.push(Instruction::Jump(otherwise.len()), site(vec![]));
+                    fragment.span = cond.to(otherwise.span);
+                    fragment.append(otherwise);
+                    fragment
+                }
+                Token::Op(op) if op.is_unary() => {
+                    let mut fragment = pop(&mut stack)?;
+                    let operand = fragment.span;
+
+                    fragment.push(Instruction::Unary(*op), site(vec![operand]));
+                    fragment.span = span.to(operand);
+                    fragment
+                }
+                // lhs ShortCircuit(rhs + 1) rhs ExpectBool
+                Token::Op(op @ (Operator::And | Operator::Or)) => {
+                    let rhs = pop(&mut stack)?;
+                    let mut fragment = pop(&mut stack)?;
+                    let (l, r) = (fragment.span, rhs.span);
+
+                    fragment.push(Instruction::ShortCircuit(*op, rhs.len() + 1), site(vec![l]));
+                    fragment.append(rhs);
+                    fragment.push(Instruction::ExpectBool, site(vec![r]));
+                    fragment.span = l.to(r);
+                    fragment
+                }
+                Token::Op(op) => {
+                    let rhs = pop(&mut stack)?;
+                    let mut fragment = pop(&mut stack)?;
+                    let (l, r) = (fragment.span, rhs.span);
+
+                    fragment.append(rhs);
+                    fragment.push(Instruction::Binary(*op), site(vec![l, r]));
+                    fragment.span = l.to(r);
+                    fragment
+                }
+                Token::Convert(unit) => {
+                    let mut fragment = pop(&mut stack)?;
+                    let operand = fragment.span;
+
+                    let instruction = match units::parse(unit) {
+                        Ok(unit) => {
+                            program.units.push(unit);
+                            Instruction::Convert(program.units.len() as u32 - 1)
+                        }
+                        Err(e) => {
+                            program.failures.push(e);
+                            Instruction::Fail(program.failures.len() as u32 - 1)
+                        }
+                    };
+
+                    fragment.push(instruction, site(vec![operand]));
+                    fragment.span = operand.to(span);
+                    fragment
+                }
+                Token::Bracket(_)
+                | Token::Assign
+                | Token::Comma
+                | Token::Func(_)
+                | Token::Question
+                | Token::Colon => continue,
+            };
+
+            stack.push(fragment);
+        }
+
+        let fragment = stack
+            .pop()
+            .ok_or_else(|| Spanned::new(EvalError::StackUnderflow, Span::new(0, 0)))?;
+
+        if !stack.is_empty() {
+            // point at the second value, the first one that has no operator
+            // joining it to the ones before
+            let extra = stack.get(1).map(|f| f.span).unwrap_or(fragment.span);
+            let error = EvalError::LeftoverOperands(stack.len() + 1);
+            return Err(Spanned::new(error, extra));
+        }
+
+        program.code = fragment.code;
+        program.sites = fragment.sites;
+
+        Ok(program)
+    }
+
+    /// The variables the program reads, in slot order. A row given to `run`
+    /// holds their values in this order.
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    /// Run with the variables of `env`. This allocates a stack, use a `Vm`
+    /// to run many rows.
+    pub fn run_in(&self, env: &Environment<Value<N>>) -> Result<Value<N>, Spanned<EvalError>> {
+        Vm::new().run(self, |slot| env.get(&self.slots[slot]))
+    }
+}
+
+/// The stack machine. It keeps its stack between runs, so running a program
+/// over many rows doesn't allocate.
+pub struct Vm<N> {
+    stack: Vec<Value<N>>,
+}
+
+impl<N: Numeric> Default for Vm<N> {
+    fn default() -> Self {
+        Vm { stack: vec![] }
+    }
+}
+
+impl<N: Numeric> Vm<N> {
+    pub fn new() -> Self {
+        Vm::default()
+    }
+
+    /// Run `program`, `load` gives the value of a variable by slot (see
+    /// `Program::slots`), for a row of data that's `|i| row.get(i).cloned()`.
+    /// A variable `load` doesn't know is reported when it is reached, so
+    /// `x == 0 || y` doesn't need `y` when `x` is `0`.
+    pub fn run(
+        &mut self,
+        program: &Program<N>,
+        load: impl Fn(usize) -> Option<Value<N>>,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        self.run_with(program, load, &mut ())
+    }
+
+    /// `run`, showing `observer` the stack after every instruction (see
+    /// `trace.rs`).
+    pub fn run_with(
+        &mut self,
+        program: &Program<N>,
+        load: impl Fn(usize) -> Option<Value<N>>,
+        observer: &mut impl Observer,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        let unknown = |name: &str, _, at| {
+            Err(Spanned::new(
+                EvalError::UnknownFunction(name.to_string()),
+                at,
+            ))
+        };
+        self.execute(program, load, unknown, observer)
+    }
+
+    /// `run`, with `invoke` computing the functions that aren't in the
+    /// registry. It gets the name of the function, the arguments and the
+    /// span of the call. Like a variable, a function `invoke` doesn't know
+    /// is only reported when it is reached.
+    pub fn run_calling(
+        &mut self,
+        program: &Program<N>,
+        load: impl Fn(usize) -> Option<Value<N>>,
+        invoke: impl FnMut(&str, Vec<Value<N>>, Span) -> Result<Value<N>, Spanned<EvalError>>,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        self.execute(program, load, invoke, &mut ())
+    }
+
+    fn execute(
+        &mut self,
+        program: &Program<N>,
+        load: impl Fn(usize) -> Option<Value<N>>,
+        mut invoke: impl FnMut(&str, Vec<Value<N>>, Span) -> Result<Value<N>, Spanned<EvalError>>,
+        observer: &mut impl Observer,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        let stack = &mut self.stack;
+        stack.clear();
+
+        let mut pc = 0;
+
+        while let Some(&instruction) = program.code.get(pc) {
+            let site = &program.sites[pc];
+            let at = |e| Spanned::new(e, site.at);
+            // a type error is reported at the operand that has the wrong type
+            let operand = |i: usize| move |e| Spanned::new(e, site.operand(i));
+
+            let at_pc = pc;
+            pc += 1;
+
+            match instruction {
+                Instruction::Const(i) => {
+                    stack.push(Value::Number(program.constants[i as usize].clone()))
+                }
+                Instruction::Load(slot) => {
+                    let name = &program.slots[slot as usize];
+                    let value = match load(slot as usize) {
+                        Some(value) => value,
+                        None => Value::constant(name)
+                            .ok_or_else(|| at(EvalError::UnknownVariable(name.clone())))?
+                            .map_err(at)?,
+                    };
+                    stack.push(value);
+                }
+                Instruction::Unary(op) => {
+                    let v = Self::pop(stack, site)?;
+
+                    let value = match op {
+                        Operator::Not => Value::Bool(!v.boolean().map_err(operand(0))?),
+                        _ => {
+                            let v = v.number().map_err(operand(0))?;
+                            Value::Number(numeric::unary(op, &v).map_err(at)?)
+                        }
+                    };
+                    stack.push(value);
+                }
+                Instruction::Binary(op) => {
+                    let r = Self::pop(stack, site)?;
+                    let l = Self::pop(stack, site)?;
+
+                    let value = match op {
+                        Operator::Eq | Operator::Ne => {
+                            Value::Bool(op.compares(l.compare(&r).map_err(operand(1))?))
+                        }
+                        Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge => {
+                            let l = l.number().map_err(operand(0))?;
+                            let r = r.number().map_err(operand(1))?;
+                            Value::Bool(op.compares(l.compare(&r).map_err(at)?))
+                        }
+                        _ => {
+                            let l = l.number().map_err(operand(0))?;
+                            let r = r.number().map_err(operand(1))?;
+
+                            let value = numeric::apply(op, &l, &r).map_err(|e| match e {
+                                // point at the divisor, that's the part that is wrong
+                                EvalError::DivisionByZero => operand(1)(e),
+                                _ => at(e),
+                            })?;
+                            Value::Number(value)
+                        }
+                    };
+                    stack.push(value);
+                }
+                Instruction::Call(index, count) => {
+                    let first = stack
+                        .len()
+                        .checked_sub(count as usize)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+
+                    let args = stack
+                        .drain(first..)
+                        .enumerate()
+                        .map(|(i, arg)| arg.number().map_err(operand(i)))
+                        .collect::<Result<Vec<N>, _>>()?;
+
+                    let function = &functions::FUNCTIONS[index as usize];
+                    stack.push(Value::Number(N::call(function, &args).map_err(at)?));
+                }
+                Instruction::Invoke(index, count) => {
+                    let first = stack
+                        .len()
+                        .checked_sub(count as usize)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+
+                    let args = stack.drain(first..).collect();
+                    let name = &program.callees[index as usize];
+                    stack.push(invoke(name, args, site.at)?);
+                }
+                Instruction::JumpIfFalse(offset) => {
+                    let cond = Self::pop(stack, site)?;
+
+                    if !cond.boolean().map_err(operand(0))? {
+                        pc += offset as usize;
+                    }
+                }
+                Instruction::Jump(offset) => pc += offset as usize,
+                Instruction::ShortCircuit(op, offset) => {
+                    let decided = Self::pop(stack, site)?.boolean().map_err(operand(0))?;
+
+                    if decided == (op == Operator::Or) {
+                        stack.push(Value::Bool(decided));
+                        pc += offset as usize;
+                    }
+                }
+                Instruction::ExpectBool => {
+                    let value = Self::pop(stack, site)?.boolean().map_err(operand(0))?;
+                    stack.push(Value::Bool(value));
+                }
+                Instruction::Convert(i) => {
+                    let value = Self::pop(stack, site)?.number().map_err(operand(0))?;
+                    let unit = &program.units[i as usize];
+                    stack.push(Value::Number(value.convert(unit).map_err(at)?));
+                }
+                Instruction::Fail(i) => return Err(at(program.failures[i as usize].clone())),
+            }
+
+            observer.step(program, at_pc, stack);
+        }
+
+        Self::pop(stack, &Site::new(Span::new(0, 0), vec![]))
+    }
+
+    /// Compiled programs never run out of operands, but a stack underflow is
+    /// still reported rather than panicking.
+    fn pop(stack: &mut Vec<Value<N>>, site: &Site) -> Result<Value<N>, Spanned<EvalError>> {
+        stack
+            .pop()
+            .ok_or_else(|| Spanned::new(EvalError::StackUnderflow, site.at))
+    }
+}
+
+impl<N> Program<N> {
+    /// The instruction at `pc`, as the listing shows it.
+    pub fn line(&self, pc: usize) -> Line<'_, N> {
+        Line(self, pc)
+    }
+}
+
+/// See `Program::line`
+pub struct Line<'a, N>(&'a Program<N>, usize);
+
+/// A listing of the program, one instruction per line with the constants
+/// and the variables spelled out.
+impl<N: fmt::Display> fmt::Display for Program<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for pc in 0..self.code.len() {
+            if pc > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "{:>4}  {}", pc, self.line(pc))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Line<'_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Line(program, pc) = *self;
+
+        // jumps print where they land
+        let target = |offset: u32| pc + 1 + offset as usize;
+
+        match &program.code[pc] {
+            Instruction::Const(i) => write!(f, "const {}", program.constants[*i as usize]),
+            Instruction::Load(slot) => write!(f, "load {}", program.slots[*slot as usize]),
+            Instruction::Unary(op) => write!(f, "unary {}", op),
+            Instruction::Binary(op) => write!(f, "binary {}", op),
+            Instruction::Call(index, count) => {
+                write!(f, "call {}/{}", FUNCTIONS[*index as usize].name, count)
+            }
+            Instruction::Invoke(index, count) => {
+                write!(f, "call {}/{}", program.callees[*index as usize], count)
+            }
+            Instruction::JumpIfFalse(offset) => write!(f, "jump-if-false {}", target(*offset)),
+            Instruction::Jump(offset) => write!(f, "jump {}", target(*offset)),
+            Instruction::ShortCircuit(op, offset) => {
+                write!(f, "short-circuit {} {}", op, target(*offset))
+            }
+            Instruction::ExpectBool => write!(f, "expect-bool"),
+            Instruction::Convert(i) => write!(f, "convert {}", program.units[*i as usize].name),
+            Instruction::Fail(i) => write!(f, "fail \"{}\"", program.failures[*i as usize]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Instruction, Program, Vm};
+    use crate::env::Environment;
+    use crate::functions::Arity;
+    use crate::rational::Rational;
+    use crate::token::{Operator, Span, Spanned};
+    use crate::{Calculator, EvalError, Value};
+
+    fn compile(expr: &str) -> Program<f64> {
+        let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap()).unwrap();
+        Program::compile(&rpn, &()).unwrap()
+    }
+
+    #[test]
+    fn compiles_once_runs_many() {
+        let program = compile("temp > 30 && humidity < 0.4 ? 1 : 0");
+        assert_eq!(program.slots(), ["temp", "humidity"]);
+
+        let mut vm = Vm::new();
+        let rows = [(35.0, 0.3, 1.0), (35.0, 0.5, 0.0), (20.0, 0.1, 0.0)];
+
+        for (temp, humidity, alert) in rows {
+            let row = [Value::Number(temp), Value::Number(humidity)];
+            let load = |slot: usize| row.get(slot).cloned();
+            assert_eq!(vm.run(&program, load), Ok(Value::Number(alert)));
+        }
+    }
+
+    #[test]
+    fn shares_constants_and_slots() {
+        let program = compile("x * x + 2 * x + 2");
+
+        assert_eq!(program.slots(), ["x"]);
+        assert_eq!(program.constants, [2.0]);
+        assert_eq!(
+            program.code,
+            [
+                Instruction::Load(0),
+                Instruction::Load(0),
+                Instruction::Binary(Operator::Mul),
+                Instruction::Const(0),
+                Instruction::Load(0),
+                Instruction::Binary(Operator::Mul),
+                Instruction::Binary(Operator::Add),
+                Instruction::Const(0),
+                Instruction::Binary(Operator::Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn listing() {
+        // the minus is folded into the literal
+        let program = compile("a || b > 1 ? max(a, 2) : -3");
+
+        assert_eq!(
+            program.to_string(),
+            "   0  load a\n   \
+                1  short-circuit || 6\n   \
+                2  load b\n   \
+                3  const 1\n   \
+                4  binary >\n   \
+                5  expect-bool\n   \
+                6  jump-if-false 11\n   \
+                7  load a\n   \
+                8  const 2\n   \
+                9  call max/2\n  \
+               10  jump 12\n  \
+               11  const -3"
+        );
+    }
+
+    /// What the VM computes, errors and their spans included.
+    #[test]
+    fn runs() {
+        let mut env = Environment::new();
+        env.set("x", Value::Number(0.0));
+        env.set("y", Value::Number(2.5));
+        env.set("t", Value::Bool(true));
+
+        let number = |n| Ok(Value::Number(n));
+        let boolean = |b| Ok(Value::Bool(b));
+        let error = |e, start, end| Err((e, Span::new(start, end)));
+        let wrong = |expected, found, start, end| {
+            error(EvalError::WrongType { expected, found }, start, end)
+        };
+
+        for (expr, expected) in [
+            ("1 + 2 * 3 - 4 / 8", number(6.5)),
+            ("-2 ^ 2 ^ 0.5 + +x", number(-2.665144142690225)),
+            ("max(y, 1, -y) * min(3, sqrt(16), abs(-7))", number(7.5)),
+            ("x != 0 && 1 / x > 2", boolean(false)),
+            ("x == 0 || nope", boolean(true)),
+            (
+                "x == 0 && 1 / x > 2",
+                error(EvalError::DivisionByZero, 14, 15),
+            ),
+            ("t ? y : nope", number(2.5)),
+            ("!t ? nope : y + 1", number(3.5)),
+            ("t && y", wrong("boolean", "number", 5, 6)),
+            ("y && t", wrong("boolean", "number", 0, 1)),
+            ("y ? 1 : 2", wrong("boolean", "number", 0, 1)),
+            ("(1 < 2) == t", boolean(true)),
+            ("t == 1", wrong("boolean", "number", 5, 6)),
+            ("1 + t", wrong("number", "boolean", 4, 5)),
+            ("-t", wrong("number", "boolean", 1, 2)),
+            ("!y", wrong("boolean", "number", 1, 2)),
+            ("sqrt(t)", wrong("number", "boolean", 5, 6)),
+            ("1 / (y - 2.5)", error(EvalError::DivisionByZero, 5, 12)),
+            ("10 ^ 400", error(EvalError::Overflow, 3, 4)),
+            ("z + 1", error(EvalError::UnknownVariable("z".into()), 0, 1)),
+            ("fact(4) > 20 ? fact(3) : fact(171)", number(6.0)),
+            ("1 << 2", error(EvalError::NotFixedWidth, 2, 4)),
+            // the operands are computed before their types are checked
+            (
+                "max(1 < 2, 1 << 2)",
+                error(EvalError::NotFixedWidth, 13, 15),
+            ),
+            ("(1 < 2) + -(t + 1)", wrong("number", "boolean", 12, 13)),
+            ("(t ? 1 : 2) + (x > 1 ? 3 : 4 < 5 ? 6 : 7)", number(7.0)),
+        ] {
+            let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap()).unwrap();
+            let program: Program<f64> = Program::compile(&rpn, &()).unwrap();
+
+            assert_eq!(
+                program.run_in(&env).map_err(|e| (e.node, e.span)),
+                expected,
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_rpn() {
+        // the tokens taken as they are, the parser rejects these
+        for (expr, error, span) in [
+            ("2 +", EvalError::StackUnderflow, Span::new(2, 3)),
+            ("", EvalError::StackUnderflow, Span::new(0, 0)),
+            ("2 3 4", EvalError::LeftoverOperands(3), Span::new(2, 3)),
+            ("-", EvalError::StackUnderflow, Span::new(0, 1)),
+        ] {
+            let rpn = Calculator::tokenize(expr).unwrap();
+
+            assert_eq!(
+                Program::<f64>::compile(&rpn, &()).err(),
+                Some(Spanned::new(error, span)),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn rows_and_backends() {
+        let rpn =
+            Calculator::expression(Calculator::tokenize("a / 3 + 1e100000").unwrap()).unwrap();
+        let program: Program<Rational> = Program::compile(&rpn, &()).unwrap();
+
+        // the literal is too big, but that's only an error once it's reached
+        let a = Value::Number(Rational::from_f64(1.0).unwrap());
+        let error = Vm::new().run(&program, |_| Some(a.clone()));
+        assert_eq!(
+            error.map_err(|e| (e.node, e.span)),
+            Err((EvalError::Overflow, Span::new(8, 16)))
+        );
+
+        // and so are functions that aren't in the registry
+        let tokens = Calculator::tokenize("a > 0 ? twice(a, 2) : 0").unwrap();
+        let rpn = Calculator::expression_in(tokens, &mut (), &|_| Some(Arity::Exact(2))).unwrap();
+        let program: Program<f64> = Program::compile(&rpn, &()).unwrap();
+        let load = |_| Some(Value::Number(1.0));
+        assert_eq!(
+            Vm::new().run(&program, load).map_err(|e| (e.node, e.span)),
+            Err((EvalError::UnknownFunction("twice".into()), Span::new(8, 19)))
+        );
+
+        // unless the caller knows them
+        let twice = |name: &str, args: Vec<Value>, _| match (name, &args[..]) {
+            ("twice", [Value::Number(x), _]) => Ok(Value::Number(2.0 * x)),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            Vm::new().run_calling(&program, load, twice),
+            Ok(Value::Number(2.0))
+        );
+        assert_eq!(program.line(6).to_string(), "call twice/2");
+
+        // slots missing from the row are unknown variables
+        let program = compile("a + b");
+        assert_eq!(
+            Vm::new()
+                .run(&program, |slot| [Value::Number(1.0)].get(slot).cloned())
+                .map_err(|e| e.node),
+            Err(EvalError::UnknownVariable("b".into()))
+        );
+    }
+}
@@ -0,0 +1,346 @@
+/*
+# Complex numbers
+
+In complex mode numbers are `Complex`, a pair of `f64`. The imaginary unit
+is written `i`, so `3 + 4i` is a complex number (a product, like `3x`), and
+`pi` and `e` are known like in float mode, for `exp(2 pi i f t)`. They are
+variables like any other: `i` is only the imaginary unit as long as nothing
+else is bound to it, a loop in a script can still count with `i`.
+
+The functions follow the principal branches: `sqrt(-1)` is `i`, `ln(-1)` is
+`πi`. `abs` is the modulus, `arg` the angle in radians, between `-π` and `π`,
+and `conj` the conjugate. Only real numbers have an order, `<`, `min`,
+`max` and the factorial refuse the others. `==` and `!=` compare both parts.
+
+Results are shown in rectangular form, `3 + 4i`, or in polar form,
+`5 ∠ 0.9272952180016122` (the modulus and the angle in radians).
+
+- See: https://en.wikipedia.org/wiki/Complex_number
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::EvalError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `re + im i`
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    /// The imaginary unit
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Self {
+        Complex::new(re, 0.0)
+    }
+
+    pub fn is_real(&self) -> bool {
+        self.im == 0.0
+    }
+
+    /// The real number `self` is, or an error if it has an imaginary part.
+    pub fn to_real(self) -> Result<f64, EvalError> {
+        match self.is_real() {
+            true => Ok(self.re),
+            false => Err(EvalError::NotReal),
+        }
+    }
+
+    /// The modulus `|z|`
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The angle with the positive real axis, between `-π` and `π`
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn mul(&self, rhs: &Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+
+    /// `None` when dividing by zero.
+    pub fn div(&self, rhs: &Complex) -> Option<Complex> {
+        let denominator = rhs.re * rhs.re + rhs.im * rhs.im;
+
+        if denominator == 0.0 {
+            return None;
+        }
+
+        Some(Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) / denominator,
+            (self.im * rhs.re - self.re * rhs.im) / denominator,
+        ))
+    }
+
+    pub fn exp(&self) -> Complex {
+        let modulus = self.re.exp();
+        Complex::new(modulus * self.im.cos(), modulus * self.im.sin())
+    }
+
+    /// The principal logarithm, its imaginary part is `arg(self)`.
+    pub fn ln(&self) -> Complex {
+        Complex::new(self.norm().ln(), self.arg())
+    }
+
+    /// The principal square root, the one with a non negative real part.
+    pub fn sqrt(&self) -> Complex {
+        let norm = self.norm();
+        let re = ((norm + self.re) / 2.0).sqrt();
+        let im = ((norm - self.re) / 2.0).sqrt();
+
+        // on the negative reals the root is on the positive imaginary axis
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    pub fn sin(&self) -> Complex {
+        Complex::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos(&self) -> Complex {
+        Complex::new(
+            self.re.cos() * self.im.cosh(),
+            -self.re.sin() * self.im.sinh(),
+        )
+    }
+
+    /// `self ^ n` by repeated squaring, so `i ^ 2` is exactly `-1`. `None`
+    /// for a negative power of zero.
+    pub fn powi(&self, n: i32) -> Option<Complex> {
+        let mut result = Complex::real(1.0);
+        let mut base = *self;
+        let mut exponent = n.unsigned_abs();
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            base = base.mul(&base);
+            exponent >>= 1;
+        }
+
+        match n < 0 {
+            true => Complex::real(1.0).div(&result),
+            false => Some(result),
+        }
+    }
+
+    /// The principal value of `self ^ rhs`, `exp(rhs ln(self))`. `None` for
+    /// zero to a power without a positive real part.
+    pub fn pow(&self, rhs: &Complex) -> Option<Complex> {
+        if rhs.is_real() && rhs.re.fract() == 0.0 && rhs.re.abs() <= i32::MAX as f64 {
+            return self.powi(rhs.re as i32);
+        }
+
+        if *self == Complex::real(0.0) {
+            return (rhs.re > 0.0).then_some(*self);
+        }
+
+        Some(rhs.mul(&self.ln()).exp())
+    }
+
+    /// `self` shown in polar form
+    pub fn polar(&self) -> Polar {
+        Polar(*self)
+    }
+
+    /// A part much smaller than the other one is rounding noise, like the
+    /// imaginary part of `exp(πi)`, it is shown as `0`.
+    fn shown(&self) -> Complex {
+        let noise = self.norm() * 1e-15;
+        let part = |x: f64| if x.abs() < noise { 0.0 } else { x + 0.0 };
+
+        Complex::new(part(self.re), part(self.im))
+    }
+}
+
+/// Only real numbers are ordered, other numbers are only equal or not.
+impl PartialOrd for Complex {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.is_real() && other.is_real() {
+            return self.re.partial_cmp(&other.re);
+        }
+
+        (self == other).then_some(Ordering::Equal)
+    }
+}
+
+/// `3 + 4i`, `-2i`, `i`
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Complex { re, im } = self.shown();
+
+        // `1i` is `i`
+        let imaginary = |im: f64| match im {
+            1.0 => "i".to_string(),
+            -1.0 => "-i".to_string(),
+            _ => format!("{}i", im),
+        };
+
+        match (re, im) {
+            (_, 0.0) => write!(f, "{}", re),
+            (0.0, _) => write!(f, "{}", imaginary(im)),
+            _ if im < 0.0 => write!(f, "{} - {}", re, imaginary(-im)),
+            _ => write!(f, "{} + {}", re, imaginary(im)),
+        }
+    }
+}
+
+/// See `Complex::polar`
+pub struct Polar(Complex);
+
+/// `5 ∠ 0.9272952180016122`, the modulus and the angle in radians
+impl fmt::Display for Polar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let z = self.0.shown();
+        write!(f, "{} ∠ {}", z.norm(), z.arg() + 0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How complex results are shown
+pub enum Form {
+    Rectangular,
+    Polar,
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Form::Rectangular => write!(f, "rectangular"),
+            Form::Polar => write!(f, "polar"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Complex;
+    use crate::env::Environment;
+    use crate::{Calculator, EvalError, Value};
+
+    fn eval(expr: &str) -> Result<Complex, EvalError> {
+        let statement = Calculator::statement(Calculator::tokenize(expr).unwrap()).unwrap();
+
+        match Calculator::execute(&statement, &mut Environment::new()) {
+            Ok(Value::Number(n)) => Ok(n),
+            Ok(other) => panic!("expected a number, got `{}`", other),
+            Err(e) => Err(e.node),
+        }
+    }
+
+    fn show(expr: &str) -> String {
+        eval(expr).unwrap().to_string()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(show("(1 + 2i)(3 - i)"), "5 + 5i");
+        assert_eq!(show("(1 + 2i) / (3 - 4i)"), "-0.2 + 0.4i");
+        assert_eq!(show("i ^ 2"), "-1");
+        assert_eq!(show("i ^ -1"), "-i");
+        assert_eq!(show("2i ^ 3"), "-2i");
+        assert_eq!(show("-(3 - 4i)"), "-3 + 4i");
+        assert_eq!(show("i ^ i"), "0.20787957635076193");
+        assert_eq!(show("(-8) ^ (1 / 3)"), "1 + 1.732050807568877i");
+        assert_eq!(eval("1 / (i - i)"), Err(EvalError::DivisionByZero));
+        assert_eq!(eval("0 ^ -i"), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(show("sqrt(-1)"), "i");
+        assert_eq!(show("sqrt(-4)"), "2i");
+        assert_eq!(show("sqrt(3 + 4i)"), "2 + i");
+        assert_eq!(show("sqrt(-3 - 4i)"), "1 - 2i");
+        assert_eq!(show("abs(3 - 4i)"), "5");
+        assert_eq!(show("arg(-1)"), "3.141592653589793");
+        assert_eq!(show("arg(-i)"), "-1.5707963267948966");
+        assert_eq!(show("conj(1 + 2i)"), "1 - 2i");
+        assert_eq!(show("ln(-1)"), "3.141592653589793i");
+        // Euler, the imaginary part left by rounding isn't shown
+        assert_eq!(show("exp(i * pi)"), "-1");
+        assert_eq!(show("e ^ (i pi / 2)"), "i");
+        assert_eq!(show("sin(i)"), "1.1752011936438014i");
+        assert_eq!(show("cos(i)"), "1.5430806348152437");
+
+        // the real ones are unchanged
+        assert_eq!(show("max(1, 2, -3) + fact(4) + 50%"), "26.5");
+        assert_eq!(eval("max(1, i)"), Err(EvalError::NotReal));
+        assert_eq!(eval("i!"), Err(EvalError::NotReal));
+    }
+
+    #[test]
+    fn comparisons() {
+        let boolean = |expr: &str| {
+            let statement = Calculator::statement(Calculator::tokenize(expr).unwrap()).unwrap();
+            Calculator::execute::<Complex>(&statement, &mut Environment::new())
+        };
+
+        assert_eq!(boolean("sqrt(-4) == 2i"), Ok(Value::Bool(true)));
+        assert_eq!(boolean("i != conj(i)"), Ok(Value::Bool(true)));
+        assert_eq!(boolean("1 < 2"), Ok(Value::Bool(true)));
+        assert_eq!(
+            boolean("arg(-1) == pi && ln(e) == 1"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            boolean("i < 2i || i >= 2i").map_err(|e| e.node),
+            Err(EvalError::NotReal)
+        );
+        assert_eq!(
+            boolean("1 < i").map_err(|e| e.node),
+            Err(EvalError::NotReal)
+        );
+    }
+
+    #[test]
+    fn the_imaginary_unit_is_a_variable() {
+        let mut env = Environment::new();
+        let mut run = |line: &str| {
+            let statement = Calculator::statement(Calculator::tokenize(line).unwrap()).unwrap();
+            Calculator::execute::<Complex>(&statement, &mut env).map(|v| v.to_string())
+        };
+
+        assert_eq!(run("3 + 4i"), Ok("3 + 4i".into()));
+        assert_eq!(run("let i = 2"), Ok("2".into()));
+        assert_eq!(run("3 + 4i"), Ok("11".into()));
+    }
+
+    #[test]
+    fn forms() {
+        assert_eq!(
+            Complex::new(3.0, 4.0).polar().to_string(),
+            "5 ∠ 0.9272952180016122"
+        );
+        assert_eq!(
+            Complex::real(-2.0).polar().to_string(),
+            "2 ∠ 3.141592653589793"
+        );
+        assert_eq!(Complex::new(0.0, -0.0).to_string(), "0");
+        assert_eq!(Complex::new(-0.5, -1.0).to_string(), "-0.5 - i");
+        assert_eq!(
+            Complex::new(1e-20, 0.0).to_string(),
+            "0.00000000000000000001"
+        );
+    }
+}
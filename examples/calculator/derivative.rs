@@ -0,0 +1,360 @@
+/*
+# Symbolic differentiation
+
+`Expr::derivative` builds the derivative of an expression with respect to one
+of its variables, node by node:
+
+- sums and differences: `(u ± v)' = u' ± v'`;
+- products: `(u v)' = u' v + u v'`;
+- quotients: `(u / v)' = (u' v - u v') / v ^ 2`;
+- powers: `(u ^ n)' = n u ^ (n - 1) u'` for a constant `n`, `(a ^ v)' =
+  a ^ v ln(a) v'` for a constant `a`, and the general rule otherwise;
+- functions, through the chain rule: `sin(u)' = cos(u) u'` and so on.
+
+`min`, `max`, `abs` and conditionals are differentiated piece by piece: the
+derivative of `max(x, y)` is `x >= y ? 1 : 0`. Comparisons, logic, bitwise
+operators and `fact` have no derivative, nor do the complex `arg` and `conj`
+which are nowhere complex differentiable.
+
+Parts that don't depend on the variable are constants, their derivative is `0`
+whatever they are, so `fact(3) * x` can be differentiated. The result is
+simplified (see `simplify.rs`).
+
+- See: https://en.wikipedia.org/wiki/Differentiation_rules
+*/
+
+use crate::ast::{Expr, ExprKind};
+use crate::simplify::Inputs;
+use crate::token::{Operator, Span, Spanned};
+use crate::Error;
+
+impl Expr {
+    /// The derivative with respect to `var`, simplified. New nodes get the
+    /// span of the node they are the derivative of, so errors evaluating the
+    /// result point somewhere in the original expression.
+    pub fn derivative(&self, var: &str) -> Result<Expr, Spanned<Error>> {
+        Ok(derive(self, var)?.simplify(Inputs::Any))
+    }
+
+    /// Does the expression read `var`?
+    pub fn depends_on(&self, var: &str) -> bool {
+        match &self.kind {
+            ExprKind::Number(_) | ExprKind::Quantity { .. } => false,
+            ExprKind::Variable(name) => name == var,
+            ExprKind::Call { args, .. } => args.iter().any(|arg| arg.depends_on(var)),
+            ExprKind::Unary { operand, .. } => operand.depends_on(var),
+            ExprKind::Binary { lhs, rhs, .. } => lhs.depends_on(var) || rhs.depends_on(var),
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => cond.depends_on(var) || then.depends_on(var) || otherwise.depends_on(var),
+        }
+    }
+}
+
+fn derive(expr: &Expr, var: &str) -> Result<Expr, Spanned<Error>> {
+    let build = Build(expr.span);
+
+    if !expr.depends_on(var) {
+        return Ok(build.number(0.0));
+    }
+
+    let not_differentiable = |name: String| Spanned::new(Error::NotDifferentiable(name), expr.span);
+
+    let derivative = match &expr.kind {
+        // a constant doesn't depend on anything
+        ExprKind::Number(_) | ExprKind::Quantity { .. } => build.number(0.0),
+        ExprKind::Variable(_) => build.number(1.0),
+        ExprKind::Unary {
+            op: Operator::Neg,
+            operand,
+        } => build.unary(Operator::Neg, derive(operand, var)?),
+        ExprKind::Unary {
+            op: Operator::Pos,
+            operand,
+        } => derive(operand, var)?,
+        ExprKind::Unary {
+            op: Operator::Percent,
+            operand,
+        } => build.unary(Operator::Percent, derive(operand, var)?),
+        ExprKind::Unary { op, .. } => return Err(not_differentiable(op.to_string())),
+        ExprKind::Binary { op, lhs, rhs } => {
+            let (u, v) = (lhs.as_ref().clone(), rhs.as_ref().clone());
+
+            match op {
+                Operator::Add | Operator::Sub => {
+                    build.binary(*op, derive(lhs, var)?, derive(rhs, var)?)
+                }
+                // only the factors that depend on `var` have a term, a
+                // `0 * u` wouldn't simplify away when `u` may be NaN
+                Operator::Mul if !lhs.depends_on(var) => build.mul(u, derive(rhs, var)?),
+                Operator::Mul if !rhs.depends_on(var) => build.mul(derive(lhs, var)?, v),
+                Operator::Mul => build.add(
+                    build.mul(derive(lhs, var)?, v),
+                    build.mul(u, derive(rhs, var)?),
+                ),
+                Operator::Div if !rhs.depends_on(var) => build.div(derive(lhs, var)?, v),
+                Operator::Div => {
+                    let numerator = match lhs.depends_on(var) {
+                        true => build.sub(
+                            build.mul(derive(lhs, var)?, v.clone()),
+                            build.mul(u, derive(rhs, var)?),
+                        ),
+                        false => build.unary(Operator::Neg, build.mul(u, derive(rhs, var)?)),
+                    };
+                    build.div(numerator, build.pow(v, build.number(2.0)))
+                }
+                Operator::Pow if !rhs.depends_on(var) => {
+                    let n = v.clone();
+                    let power = build.pow(u, build.sub(v, build.number(1.0)));
+                    build.mul(build.mul(n, power), derive(lhs, var)?)
+                }
+                Operator::Pow if !lhs.depends_on(var) => {
+                    let ln = build.call("ln", vec![u]);
+                    build.mul(build.mul(expr.clone(), ln), derive(rhs, var)?)
+                }
+                // u ^ v (v' ln(u) + v u' / u)
+                Operator::Pow => {
+                    let ln = build.call("ln", vec![u.clone()]);
+                    let rate = build.add(
+                        build.mul(derive(rhs, var)?, ln),
+                        build.div(build.mul(v, derive(lhs, var)?), u),
+                    );
+                    build.mul(expr.clone(), rate)
+                }
+                _ => return Err(not_differentiable(op.to_string())),
+            }
+        }
+        ExprKind::Call { name, args } => call(expr, name, args, var)?,
+        ExprKind::Conditional {
+            cond,
+            then,
+            otherwise,
+        } => build.conditional(
+            cond.as_ref().clone(),
+            derive(then, var)?,
+            derive(otherwise, var)?,
+        ),
+    };
+
+    Ok(derivative)
+}
+
+/// The chain rule for the built-in functions.
+fn call(expr: &Expr, name: &str, args: &[Expr], var: &str) -> Result<Expr, Spanned<Error>> {
+    let build = Build(expr.span);
+
+    let outer = match (name, args) {
+        ("sin", [u]) => build.call("cos", vec![u.clone()]),
+        ("cos", [u]) => build.unary(Operator::Neg, build.call("sin", vec![u.clone()])),
+        ("exp", [_]) => expr.clone(),
+        ("ln", [u]) => build.div(build.number(1.0), u.clone()),
+        ("sqrt", [_]) => build.div(
+            build.number(1.0),
+            build.mul(build.number(2.0), expr.clone()),
+        ),
+        // pieces, there's no derivative at 0 but both sides agree on `abs(0)`
+        ("abs", [u]) => {
+            let negative = build.binary(Operator::Lt, u.clone(), build.number(0.0));
+            let slope = build.conditional(negative, build.number(-1.0), build.number(1.0));
+            return Ok(build.mul(slope, derive(u, var)?));
+        }
+        // the derivative of the argument that's picked
+        ("min" | "max", [u]) => return derive(u, var),
+        ("min" | "max", [u, rest @ ..]) => {
+            let op = if name == "min" {
+                Operator::Le
+            } else {
+                Operator::Ge
+            };
+            let others = match rest {
+                [v] => v.clone(),
+                _ => build.call(name, rest.to_vec()),
+            };
+            let picked = build.binary(op, u.clone(), others.clone());
+            return Ok(build.conditional(picked, derive(u, var)?, derive(&others, var)?));
+        }
+        // `conj(z)` changes with `z` differently depending on the direction
+        // `z` moves in, and so does `arg(z)`, there's no one slope to give
+        ("arg" | "conj", _) => {
+            return Err(Spanned::new(
+                Error::NotDifferentiable(name.to_string()),
+                expr.span,
+            ))
+        }
+        _ => {
+            return Err(Spanned::new(
+                Error::NotDifferentiable(name.to_string()),
+                expr.span,
+            ))
+        }
+    };
+
+    // every function left takes a single argument
+    Ok(build.mul(outer, derive(&args[0], var)?))
+}
+
+/// Builds nodes that all get the same span.
+#[derive(Clone, Copy)]
+struct Build(Span);
+
+impl Build {
+    fn number(self, num: f64) -> Expr {
+        Expr::new(ExprKind::Number(num), self.0)
+    }
+
+    fn call(self, name: &str, args: Vec<Expr>) -> Expr {
+        let name = name.to_string();
+        Expr::new(ExprKind::Call { name, args }, self.0)
+    }
+
+    fn unary(self, op: Operator, operand: Expr) -> Expr {
+        let operand = Box::new(operand);
+        Expr::new(ExprKind::Unary { op, operand }, self.0)
+    }
+
+    fn binary(self, op: Operator, lhs: Expr, rhs: Expr) -> Expr {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        Expr::new(ExprKind::Binary { op, lhs, rhs }, self.0)
+    }
+
+    fn add(self, lhs: Expr, rhs: Expr) -> Expr {
+        self.binary(Operator::Add, lhs, rhs)
+    }
+
+    fn sub(self, lhs: Expr, rhs: Expr) -> Expr {
+        self.binary(Operator::Sub, lhs, rhs)
+    }
+
+    fn mul(self, lhs: Expr, rhs: Expr) -> Expr {
+        self.binary(Operator::Mul, lhs, rhs)
+    }
+
+    fn div(self, lhs: Expr, rhs: Expr) -> Expr {
+        self.binary(Operator::Div, lhs, rhs)
+    }
+
+    fn pow(self, lhs: Expr, rhs: Expr) -> Expr {
+        self.binary(Operator::Pow, lhs, rhs)
+    }
+
+    fn conditional(self, cond: Expr, then: Expr, otherwise: Expr) -> Expr {
+        let kind = ExprKind::Conditional {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        };
+        Expr::new(kind, self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::Expr;
+    use crate::bytecode::{Program, Vm};
+    use crate::token::Span;
+    use crate::{Calculator, Error, Value};
+
+    fn parse(expr: &str) -> Expr {
+        Calculator::parse(Calculator::tokenize(expr).unwrap()).unwrap()
+    }
+
+    fn derivative(expr: &str, var: &str) -> String {
+        parse(expr).derivative(var).unwrap().to_string()
+    }
+
+    /// Evaluate with a single variable `x`.
+    fn at(expr: &Expr, x: f64) -> f64 {
+        let program = Program::<f64>::compile(&expr.rpn(), &()).unwrap();
+        match Vm::new().run(&program, |_| Some(Value::Number(x))) {
+            Ok(Value::Number(value)) => value,
+            other => panic!("{} at {}: {:?}", expr, x, other),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        for (expr, derived) in [
+            ("3 * x + 2", "3"),
+            ("x ^ 2", "(* 2 x)"),
+            ("x * x", "(* 2 x)"),
+            ("y * x ^ 3 - x", "(- (* 3 (* y (^ x 2))) 1)"),
+            ("1 / x", "(/ -1 (^ x 2))"),
+            ("x / 4", "0.25"),
+            ("2 ^ x", "(* 0.6931471805599453 (^ 2 x))"),
+            ("sin(2 * x)", "(* 2 (cos (* 2 x)))"),
+            ("cos(x)", "(- (sin x))"),
+            ("exp(x) + ln(x)", "(+ (exp x) (/ 1 x))"),
+            ("sqrt(x)", "(/ 1 (* 2 (sqrt x)))"),
+            ("abs(x)", "(? (< x 0) -1 1)"),
+            (
+                "max(x, 2 * x, 1)",
+                "(? (>= x (max (* 2 x) 1)) 1 (? (>= (* 2 x) 1) 2 0))",
+            ),
+            ("x > 0 ? x ^ 2 : -x", "(? (> x 0) (* 2 x) -1)"),
+            // constants whatever they are
+            ("fact(3) * x", "6"),
+            ("y ^ 2", "0"),
+        ] {
+            assert_eq!(derivative(expr, "x"), derived, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn with_respect_to() {
+        assert_eq!(derivative("x * y + y ^ 2", "y"), "(+ x (* 2 y))");
+        assert_eq!(derivative("x * y + y ^ 2", "z"), "0");
+    }
+
+    #[test]
+    fn not_differentiable() {
+        for (expr, name, span) in [
+            ("fact(x)", "fact", Span::new(0, 7)),
+            ("conj(x)", "conj", Span::new(0, 7)),
+            ("2 * arg(x)", "arg", Span::new(4, 10)),
+            ("1 + (x < 2)", "<", Span::new(5, 10)),
+            ("x & 1", "&", Span::new(0, 5)),
+        ] {
+            let error = parse(expr).derivative("x").unwrap_err();
+            assert!(
+                matches!(&error.node, Error::NotDifferentiable(n) if n == name),
+                "{}",
+                expr
+            );
+            assert_eq!(error.span, span, "{}", expr);
+        }
+    }
+
+    /// The derivative agrees with the slope measured around a few points.
+    #[test]
+    fn matches_finite_differences() {
+        for expr in [
+            "x ^ 3 - 2 * x",
+            "sin(x) * cos(x)",
+            "x ^ x",
+            "exp(-x ^ 2 / 2)",
+            "sqrt(x ^ 2 + 1) / (x + 3)",
+            "ln(x) * x - max(x, 1.7)",
+            "abs(x - 1) + min(x, 2 * x)",
+        ] {
+            let tree = parse(expr);
+            let derived = tree.derivative("x").unwrap();
+
+            for x in [0.3, 1.2, 2.5] {
+                let h = 1e-6;
+                let slope = (at(&tree, x + h) - at(&tree, x - h)) / (2.0 * h);
+                let exact = at(&derived, x);
+
+                assert!(
+                    (slope - exact).abs() < 1e-5,
+                    "{} at {}: {} vs {}",
+                    expr,
+                    x,
+                    slope,
+                    exact
+                );
+            }
+        }
+    }
+}
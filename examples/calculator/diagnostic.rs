@@ -0,0 +1,171 @@
+/*
+# Diagnostics
+
+Errors carry the span of the input they refer to, so we can point at it the
+way rustc does:
+
+```text
+error: division by zero
+ --> 1:5
+  |
+1 | 1 / (2 - 2)
+  |      ^~~~~
+```
+
+A line with several mistakes is reported all at once, one diagnostic after
+the other in the order they appear (see `Errors`).
+*/
+
+use std::fmt;
+
+use crate::token::{Span, Spanned};
+use crate::Error;
+
+/// Render `message` pointing at `span` inside `source`.
+///
+/// Only the line where the span starts is shown; a span running over several
+/// lines is underlined up to the end of its first line.
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let start = span.start.min(source.len());
+    let end = span.end.clamp(start, source.len());
+
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+
+    let line_number = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count();
+    let width = source[start..end.min(line_end)].chars().count().max(1);
+
+    let gutter = " ".repeat(line_number.to_string().len());
+    let underline = format!("{}^{}", " ".repeat(column), "~".repeat(width - 1));
+
+    format!(
+        "error: {message}\n\
+         {gutter}--> {line_number}:{}\n\
+         {gutter} |\n\
+         {line_number} | {}\n\
+         {gutter} | {underline}",
+        column + 1,
+        &source[line_start..line_end],
+    )
+}
+
+impl<E: fmt::Display> Spanned<E> {
+    /// Render the error contained in `self` against the input it came from.
+    pub fn render(&self, source: &str) -> String {
+        render(source, self.span, &self.node.to_string())
+    }
+}
+
+#[derive(Debug)]
+/// Every error found in a line by the tokenizer and the parser, ordered by
+/// where they start. There is always at least one.
+pub struct Errors(Vec<Spanned<Error>>);
+
+impl Errors {
+    pub fn new(mut errors: Vec<Spanned<Error>>) -> Self {
+        assert!(!errors.is_empty(), "no errors to report");
+
+        errors.sort_by_key(|e| e.span.start);
+        Errors(errors)
+    }
+
+    /// `Ok` when nothing went wrong.
+    pub fn check(errors: Vec<Spanned<Error>>) -> Result<(), Errors> {
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Errors::new(errors)),
+        }
+    }
+
+    /// The error that comes first in the line.
+    pub fn first(&self) -> &Spanned<Error> {
+        &self.0[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Spanned<Error>> {
+        self.0.iter()
+    }
+
+    /// Render every error against the line they came from, separated by a
+    /// blank line like rustc does.
+    pub fn render(&self, source: &str) -> String {
+        let rendered: Vec<_> = self.iter().map(|e| e.render(source)).collect();
+        rendered.join("\n\n")
+    }
+}
+
+impl IntoIterator for Errors {
+    type Item = Spanned<Error>;
+    type IntoIter = std::vec::IntoIter<Spanned<Error>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl From<Spanned<Error>> for Errors {
+    fn from(error: Spanned<Error>) -> Self {
+        Errors(vec![error])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render, Errors};
+    use crate::token::{Span, Spanned};
+    use crate::Error;
+
+    #[test]
+    fn underlines_the_span() {
+        assert_eq!(
+            render("1 / (2 - 2)", Span::new(5, 10), "division by zero"),
+            "error: division by zero\n \
+             --> 1:6\n  \
+             |\n\
+             1 | 1 / (2 - 2)\n  \
+             |      ^~~~~"
+        );
+    }
+
+    #[test]
+    fn points_at_the_right_line() {
+        let rendered = render("1 +\n2 $ 3", Span::new(6, 7), "unexpected character `$`");
+        assert!(rendered.ends_with("2 | 2 $ 3\n  |   ^"));
+        assert!(rendered.contains("--> 2:3"));
+    }
+
+    #[test]
+    fn empty_span_at_the_end() {
+        let rendered = render("2 +", Span::new(3, 3), "missing operand");
+        assert!(rendered.ends_with("1 | 2 +\n  |    ^"));
+    }
+
+    #[test]
+    fn renders_every_error() {
+        let errors = Errors::check(vec![
+            Spanned::new(Error::BadToken('$'), Span::new(6, 7)),
+            Spanned::new(Error::MissingOperand, Span::new(4, 5)),
+        ])
+        .unwrap_err();
+
+        assert!(matches!(errors.first().node, Error::MissingOperand));
+        assert_eq!(
+            errors.render("1 + * $"),
+            "error: missing operand\n \
+             --> 1:5\n  \
+             |\n\
+             1 | 1 + * $\n  \
+             |     ^\n\
+             \n\
+             error: unexpected character `$`\n \
+             --> 1:7\n  \
+             |\n\
+             1 | 1 + * $\n  \
+             |       ^"
+        );
+        assert!(Errors::check(vec![]).is_ok());
+    }
+}
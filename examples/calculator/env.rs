@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use crate::Value;
+
+#[derive(Debug)]
+/// The variables an expression is evaluated against. `V` is what they hold,
+/// a `Value` of some kind of number (see `numeric.rs`).
+///
+/// Keeping the bindings out of the parsed expression means the same RPN can be
+/// evaluated against as many environments (records) as we want.
+pub struct Environment<V = Value> {
+    vars: HashMap<String, V>,
+}
+
+impl<V> Default for Environment<V> {
+    fn default() -> Self {
+        Environment {
+            vars: HashMap::new(),
+        }
+    }
+}
+
+impl<V: Clone> Environment<V> {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<V> {
+        self.vars.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.vars.contains_key(name)
+    }
+
+    /// All the bindings, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        self.vars.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Bind `name` to `value`, replacing any previous binding.
+    pub fn set<N: Into<String>>(&mut self, name: N, value: V) {
+        self.vars.insert(name.into(), value);
+    }
+}
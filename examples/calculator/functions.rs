@@ -0,0 +1,127 @@
+/*
+# Built-in functions
+
+Every function the calculator knows about is described by a `Function`: its
+name, how many arguments it takes and the code that computes it. The parser
+uses the arity to reject bad calls (`sqrt(1, 2)`) before anything is evaluated.
+*/
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How many arguments a function accepts
+pub enum Arity {
+    Exact(usize),
+    /// Variadic functions like `min` and `max`
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == *n,
+            Arity::AtLeast(n) => count >= *n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(1) => write!(f, "1 argument"),
+            Arity::Exact(n) => write!(f, "{} arguments", n),
+            Arity::AtLeast(1) => write!(f, "at least 1 argument"),
+            Arity::AtLeast(n) => write!(f, "at least {} arguments", n),
+        }
+    }
+}
+
+pub struct Function {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// Receives the arguments in the order they were written
+    pub apply: fn(&[f64]) -> f64,
+}
+
+/// The function registry
+pub const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "sin",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].sin(),
+    },
+    Function {
+        name: "cos",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].cos(),
+    },
+    Function {
+        name: "exp",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].exp(),
+    },
+    Function {
+        name: "ln",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].ln(),
+    },
+    Function {
+        name: "sqrt",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].sqrt(),
+    },
+    Function {
+        name: "abs",
+        arity: Arity::Exact(1),
+        apply: |args| args[0].abs(),
+    },
+    Function {
+        name: "arg",
+        arity: Arity::Exact(1),
+        // the angle of a real number, `π` for the negative ones
+        apply: |args| 0f64.atan2(args[0]),
+    },
+    Function {
+        name: "conj",
+        arity: Arity::Exact(1),
+        apply: |args| args[0],
+    },
+    Function {
+        name: "fact",
+        arity: Arity::Exact(1),
+        apply: |args| factorial(args[0]),
+    },
+    Function {
+        name: "min",
+        arity: Arity::AtLeast(1),
+        apply: |args| args.iter().copied().fold(f64::INFINITY, f64::min),
+    },
+    Function {
+        name: "max",
+        arity: Arity::AtLeast(1),
+        apply: |args| args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    },
+];
+
+/// `n!` for the non negative integers, NaN for anything else. Past `170!`
+/// the result doesn't fit in a `f64` anymore.
+fn factorial(n: f64) -> f64 {
+    if n < 0.0 || n.fract() != 0.0 {
+        return f64::NAN;
+    }
+
+    if n > 170.0 {
+        return f64::INFINITY;
+    }
+
+    (2..=n as u32).map(f64::from).product()
+}
+
+pub fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+/// How many arguments the built-in function `name` takes, if there is one.
+pub fn arity(name: &str) -> Option<Arity> {
+    lookup(name).map(|f| f.arity)
+}
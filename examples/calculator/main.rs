@@ -0,0 +1,1627 @@
+/*
+# The "shunting yard" algorithm
+
+Is a method of parsing arithmetic or logical operations that are written
+in "infix notation" (the mathematical notation most people are used to)
+to convert them into postfix notation or an AST.
+
+The algorithm allocates 2 variable strings (input and output) and a stack that holds
+operators not yet added to the output queue.
+
+> "the shunting yard algorithm is stack-based"
+> "Infix expressions are the form of mathematical notation most people are used to"
+
+- See: https://www.youtube.com/watch?v=KJwfZ06Z6og
+- See: https://en.wikipedia.org/wiki/Shunting_yard_algorithm
+*/
+
+mod ast;
+mod bigint;
+mod bytecode;
+mod complex;
+mod derivative;
+mod diagnostic;
+mod env;
+mod functions;
+mod notation;
+mod numeric;
+mod pratt;
+mod rational;
+mod repl;
+mod script;
+mod simplify;
+mod solver;
+mod token;
+mod trace;
+mod units;
+mod word;
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use ast::Expr;
+use bytecode::Program;
+use diagnostic::Errors;
+use env::Environment;
+use functions::Arity;
+use numeric::Numeric;
+use repl::Repl;
+use token::{Operator, Span, Spanned, Token};
+use trace::{Event, Observer};
+
+#[derive(Debug)]
+/// Define our possible errors. They are reported as `Spanned<Error>` so we
+/// know which part of the input they refer to, and the tokenizer and the
+/// parser collect every one they find in `Errors`.
+pub enum Error {
+    BadToken(char),
+    /// A numeric literal that doesn't follow the number grammar (`1..2`, `3e`).
+    BadNumber(String),
+    /// A literal too big for a float (`1e999`) where it's read before any
+    /// evaluation, like in the syntax tree
+    LiteralOverflow(String),
+    MismatchedParens,
+    /// An operator without enough operands (`2 +`)
+    MissingOperand,
+    /// Operands with no operator joining them (`2 3`)
+    MissingOperator,
+    /// A `let` that isn't `let <name> = <expression>`, or a stray `=`
+    InvalidAssignment,
+    /// A `,` that isn't separating the arguments of a function call
+    MisplacedComma,
+    /// A `?` whose `: <otherwise>` branch never comes
+    MissingColon,
+    /// A `:` that doesn't close the `?` of a conditional
+    MisplacedColon,
+    /// A call to a function that isn't in the registry (see `functions.rs`)
+    UnknownFunction(String),
+    /// A call with the wrong number of arguments (`sqrt(1, 2)`)
+    WrongArity {
+        name: String,
+        expected: Arity,
+        found: usize,
+    },
+    /// An operator or a function with no derivative (see `derivative.rs`)
+    NotDifferentiable(String),
+    /// A `solve` or `root` line that doesn't follow its usage (see
+    /// `solver.rs`)
+    InvalidProblem(&'static str),
+    /// A `to <unit>` that doesn't end the line
+    MisplacedConversion,
+    /// An `if`, a `while` or a `fn` whose `end` never comes (see `script.rs`)
+    UnclosedBlock,
+    /// An `else` that doesn't follow the body of an `if`
+    MisplacedElse,
+    /// An `end` with no block to close
+    MisplacedEnd,
+    /// A `return` outside of a function
+    MisplacedReturn,
+    /// A `fn` inside a block, functions are defined at the top of a script
+    MisplacedFunction,
+    /// A `fn` line that isn't `fn <name>(<parameters>)`
+    InvalidFunction,
+    /// A second `fn` with the same name, or one named like a built-in
+    FunctionRedefined(String),
+    /// Something after a keyword that stands alone, like `end 1`
+    ExpectedEndOfLine,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadToken(c) => write!(f, "unexpected character `{}`", c),
+            Error::BadNumber(literal) => write!(f, "malformed number `{}`", literal),
+            Error::LiteralOverflow(literal) => write!(f, "numeric overflow in `{}`", literal),
+            Error::MismatchedParens => write!(f, "mismatched parenthesis"),
+            Error::MissingOperand => write!(f, "missing operand"),
+            Error::MissingOperator => write!(f, "expected an operator before this"),
+            Error::InvalidAssignment => write!(f, "expected `let <name> = <expression>`"),
+            Error::MisplacedComma => write!(f, "`,` outside of a function call"),
+            Error::MissingColon => write!(f, "expected a `:` for this `?`"),
+            Error::MisplacedColon => write!(f, "`:` without a `?` before it"),
+            Error::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            Error::WrongArity {
+                name,
+                expected,
+                found,
+            } => write!(f, "`{}` takes {} but {} were given", name, expected, found),
+            Error::NotDifferentiable(name) => write!(f, "`{}` can't be differentiated", name),
+            Error::InvalidProblem(usage) => write!(f, "expected `{}`", usage),
+            Error::MisplacedConversion => {
+                write!(f, "`to <unit>` only converts the result of a whole line")
+            }
+            Error::UnclosedBlock => write!(f, "expected an `end` for this block"),
+            Error::MisplacedElse => write!(f, "`else` without an `if` before it"),
+            Error::MisplacedEnd => write!(f, "`end` without a block to close"),
+            Error::MisplacedReturn => write!(f, "`return` outside of a function"),
+            Error::MisplacedFunction => write!(f, "functions are defined outside of any block"),
+            Error::InvalidFunction => write!(f, "expected `fn <name>(<parameters>)`"),
+            Error::FunctionRedefined(name) => write!(f, "`{}` is already defined", name),
+            Error::ExpectedEndOfLine => write!(f, "expected the end of the line"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq)]
+/// What evaluating an expression results in: comparisons and the logical
+/// operators produce booleans, everything else numbers. `N` is the kind of
+/// number we compute with (see `numeric.rs`), floats unless asked otherwise.
+pub enum Value<N = f64> {
+    Number(N),
+    Bool(bool),
+}
+
+impl<N> Value<N> {
+    /// How the type of the value is called in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "boolean",
+        }
+    }
+
+    /// The number, or why this value can't be used as one.
+    pub fn number(self) -> Result<N, EvalError> {
+        match self {
+            Value::Number(n) => Ok(n),
+            other => Err(EvalError::WrongType {
+                expected: "number",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// The boolean, or why this value can't be used as one.
+    pub fn boolean(self) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            other => Err(EvalError::WrongType {
+                expected: "boolean",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// How `self` compares to `other` for `==` and `!=`: numbers compare with
+    /// numbers and booleans with booleans. On a mismatch it's `other` that has
+    /// the wrong type.
+    pub fn compare(&self, other: &Self) -> Result<Option<Ordering>, EvalError>
+    where
+        N: Numeric,
+    {
+        match (self, other) {
+            (Value::Number(l), Value::Number(r)) => l.equality(r),
+            (Value::Bool(l), Value::Bool(r)) => Ok(Some(l.cmp(r))),
+            (l, r) => Err(EvalError::WrongType {
+                expected: l.type_name(),
+                found: r.type_name(),
+            }),
+        }
+    }
+
+    /// What a variable that isn't bound stands for: `true` and `false`, or a
+    /// constant of the backend (see `Numeric::constant`).
+    pub fn constant(name: &str) -> Option<Result<Self, EvalError>>
+    where
+        N: Numeric,
+    {
+        match name {
+            "true" => Some(Ok(Value::Bool(true))),
+            "false" => Some(Ok(Value::Bool(false))),
+            _ => N::constant(name).map(|n| n.map(Value::Number)),
+        }
+    }
+
+    /// Switch the number to another `Numeric` backend, `None` when `convert`
+    /// has no equivalent for it. Booleans are the same everywhere.
+    pub fn convert<M>(self, convert: impl FnOnce(N) -> Option<M>) -> Option<Value<M>> {
+        match self {
+            Value::Number(n) => convert(n).map(Value::Number),
+            Value::Bool(b) => Some(Value::Bool(b)),
+        }
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Value<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Everything that can go wrong while evaluating an expression. Like `Error`
+/// these are reported as `Spanned<EvalError>`.
+pub enum EvalError {
+    /// An operator or function found fewer operands than it needs
+    StackUnderflow,
+    /// The evaluation finished with more than one value (holds how many)
+    LeftoverOperands(usize),
+    DivisionByZero,
+    /// A finite computation whose result doesn't fit in the backend's numbers
+    Overflow,
+    /// A result the backend can't represent, like `sqrt(-1)` in exact mode
+    NotANumber,
+    /// A result that isn't a fraction in exact mode, like `sqrt(2)`
+    NotExact,
+    /// A bitwise operator outside of programmer mode, see `word.rs`
+    NotFixedWidth,
+    /// A shift by a negative number of bits, like `1 << -1`
+    NegativeShift,
+    /// An operand of the wrong type, like the boolean in `(1 < 2) + 3`
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A fraction (or a fractional literal) in integer mode, like `2 ^ -1`
+    NotAnInteger,
+    UnknownVariable(String),
+    UnknownFunction(String),
+    UnknownUnit(String),
+    /// A unit outside of units mode, see `units.rs`
+    NoUnits,
+    /// Adding, comparing or converting quantities of different dimensions,
+    /// like `3 m + 2 s`
+    IncompatibleUnits(String, String),
+    /// A quantity where only a plain number makes sense, like `sin(3 m)`
+    NotDimensionless(String),
+    /// A power of a quantity that isn't a whole unit, like `sqrt(3 m)`
+    FractionalPower(String),
+    /// Functions of a script calling each other too deep, like a recursion
+    /// that never stops
+    RecursionLimit,
+    /// The loops of a script going round too many times, like a `while`
+    /// whose condition never turns false
+    IterationLimit,
+    /// A function of a script that reached its `end` without a `return`
+    NoReturn(String),
+    /// A complex number where only a real one makes sense, like `max(1, i)`
+    NotReal,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::StackUnderflow => write!(f, "missing operand"),
+            EvalError::LeftoverOperands(n) => write!(f, "{} values left without an operator", n),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "numeric overflow"),
+            EvalError::NotANumber => write!(f, "the result is not a number"),
+            EvalError::WrongType { expected, found } => {
+                write!(f, "expected a {}, found a {}", expected, found)
+            }
+            EvalError::NotAnInteger => write!(f, "the result is not an integer"),
+            EvalError::NotExact => write!(f, "the result has no exact value, try `:mode float`"),
+            EvalError::NotFixedWidth => write!(f, "bitwise operators need fixed width integers"),
+            EvalError::NegativeShift => write!(f, "can't shift by a negative number of bits"),
+            EvalError::UnknownVariable(name) => write!(f, "unknown variable `{}`", name),
+            EvalError::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            EvalError::UnknownUnit(unit) => write!(f, "unknown unit `{}`", unit),
+            EvalError::NoUnits => write!(f, "units are only supported in units mode"),
+            EvalError::IncompatibleUnits(l, r) => {
+                write!(f, "incompatible units `{}` and `{}`", l, r)
+            }
+            EvalError::NotDimensionless(unit) => {
+                write!(f, "expected a plain number, found a quantity in `{}`", unit)
+            }
+            EvalError::FractionalPower(unit) => {
+                write!(f, "`{}` can't be raised to a fractional power", unit)
+            }
+            EvalError::RecursionLimit => write!(f, "too many nested calls"),
+            EvalError::IterationLimit => write!(f, "too many loop iterations"),
+            EvalError::NoReturn(name) => write!(f, "`{}` ended without returning a value", name),
+            EvalError::NotReal => write!(f, "expected a real number"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+#[derive(Debug)]
+/// A line of input: either a plain expression or a `let` binding. Both hold
+/// the expression already converted to RPN, ready to be evaluated.
+pub enum Statement {
+    Expr(Vec<Spanned<Token>>),
+    Let(String, Vec<Spanned<Token>>),
+}
+
+/// Words that start a statement, never an operand: `let` and the keywords of
+/// scripts (see `script.rs`).
+const KEYWORDS: &[&str] = &["let", "if", "else", "while", "fn", "return", "end"];
+
+pub struct Calculator {}
+
+/// The output queue and the operator stack of the shunting yard. Every move
+/// of a token is told to the observer.
+struct Yard<'a, O> {
+    queue: Vec<Spanned<Token>>,
+    operations: Vec<Spanned<Token>>,
+    observer: &'a mut O,
+}
+
+impl<'a, O: Observer> Yard<'a, O> {
+    fn new(observer: &'a mut O) -> Self {
+        Yard {
+            queue: vec![],
+            operations: vec![],
+            observer,
+        }
+    }
+
+    /// The token on top of the operator stack
+    fn top(&self) -> Option<&Token> {
+        self.operations.last().map(|t| &t.node)
+    }
+
+    fn push(&mut self, token: Spanned<Token>) {
+        self.operations.push(token);
+        self.observer
+            .event(Event::Push(&self.operations[self.operations.len() - 1]));
+    }
+
+    fn pop(&mut self) -> Option<Spanned<Token>> {
+        let token = self.operations.pop()?;
+        self.observer.event(Event::Pop(&token));
+        Some(token)
+    }
+
+    fn output(&mut self, token: Spanned<Token>) {
+        self.queue.push(token);
+        self.observer
+            .event(Event::Output(&self.queue[self.queue.len() - 1]));
+    }
+
+    /// Move the operator on top of the stack to the output.
+    fn pop_output(&mut self) {
+        if let Some(token) = self.pop() {
+            self.output(token);
+        }
+    }
+}
+
+impl Calculator {
+    /// This is a static method that tokenize its input, reporting every
+    /// lexical error in it (see `Calculator::scan`).
+    pub fn tokenize<T: AsRef<str>>(expr: T) -> Result<Vec<Spanned<Token>>, Errors> {
+        Self::tokenize_with(expr.as_ref(), &mut ())
+    }
+
+    /// `tokenize`, telling `observer` about every token (see `trace.rs`).
+    pub fn tokenize_with(
+        expr: &str,
+        observer: &mut impl Observer,
+    ) -> Result<Vec<Spanned<Token>>, Errors> {
+        let (tokens, errors) = Self::scan(expr);
+
+        for token in &tokens {
+            observer.event(Event::Token(token));
+        }
+
+        Errors::check(errors)?;
+        Ok(tokens)
+    }
+
+    /// Tokenize `expr` and give the tokens to `parse`, like
+    /// `Calculator::parse` or `Calculator::statement`. The lexical errors and
+    /// the syntax errors are reported together: the tokens read around a
+    /// lexical error are parsed anyway.
+    pub fn read<T>(
+        expr: &str,
+        parse: impl FnOnce(Vec<Spanned<Token>>) -> Result<T, Errors>,
+    ) -> Result<T, Errors> {
+        Self::read_at(expr, 0, parse)
+    }
+
+    /// `read` a piece of a bigger source starting at `offset` in it, like a
+    /// line of a script. The spans point into the bigger source.
+    pub fn read_at<T>(
+        expr: &str,
+        offset: usize,
+        parse: impl FnOnce(Vec<Spanned<Token>>) -> Result<T, Errors>,
+    ) -> Result<T, Errors> {
+        let (mut tokens, mut errors) = Self::scan(expr);
+
+        let spans = tokens.iter_mut().map(|t| &mut t.span);
+        for span in spans.chain(errors.iter_mut().map(|e| &mut e.span)) {
+            *span = Span::new(span.start + offset, span.end + offset);
+        }
+
+        // A malformed number is kept as a token, the parser may complain
+        // about it again. Where a character was skipped we can't tell if an
+        // operand or an operator was meant, so what the parser says about the
+        // token after it is left out too.
+        let mut known: Vec<Span> = errors.iter().map(|e| e.span).collect();
+        known.extend(
+            errors
+                .iter()
+                .filter(|e| matches!(e.node, Error::BadToken(_)))
+                .filter_map(|e| tokens.iter().find(|t| t.span.start >= e.span.end))
+                .map(|t| t.span),
+        );
+
+        match parse(tokens) {
+            Ok(parsed) if errors.is_empty() => return Ok(parsed),
+            Ok(_) => {}
+            Err(syntax) => errors.extend(syntax.into_iter().filter(|e| !known.contains(&e.span))),
+        }
+
+        Err(Errors::new(errors))
+    }
+
+    /// Split `expr` into tokens. The scan doesn't stop at an error, it
+    /// records it and goes on: an unexpected character or a stray `)` is
+    /// skipped, a malformed number is kept as it is, and every bracket left
+    /// open is reported at the end.
+    fn scan(expr: &str) -> (Vec<Spanned<Token>>, Vec<Spanned<Error>>) {
+        let mut errors: Vec<Spanned<Error>> = vec![];
+
+        // stack of characters waiting to be processed
+        let mut chars = expr.char_indices().peekable();
+
+        // stack of `Token` enums along with their position in `expr`
+        let mut tokens: Vec<Spanned<Token>> = vec![];
+
+        // Stack of opening brackets (used to validate the number
+        // of opening & closing parenthesis).
+        let mut opening_brackets: Vec<Span> = vec![];
+
+        while let Some((start, c)) = chars.next() {
+            // span of a single char token
+            let span = Span::new(start, start + c.len_utf8());
+
+            match c {
+                // A digit or a dot starts a numeric literal. We grab the whole
+                // literal at once (`3.14`, `.5`, `1e-3`, `2.5E+10`, `0xff`) and
+                // let `Calculator::number` decide if it is well formed.
+                '0'..='9' | '.' => {
+                    let mut literal = String::from(c);
+
+                    // `0x`, `0o` and `0b` literals run up to the end of the word
+                    let radix = c == '0'
+                        && matches!(chars.peek(), Some((_, 'x' | 'X' | 'o' | 'O' | 'b' | 'B')));
+
+                    while let Some(&(_, next)) = chars.peek().filter(|_| radix) {
+                        if !(next.is_ascii_alphanumeric() || next == '_') {
+                            break;
+                        }
+
+                        literal.push(next);
+                        chars.next();
+                    }
+
+                    while let Some(&(_, next)) = chars.peek().filter(|_| !radix) {
+                        let after_exponent = matches!(literal.chars().last(), Some('e' | 'E'));
+
+                        match next {
+                            '0'..='9' | '.' | 'e' | 'E' => literal.push(next),
+                            '+' | '-' if after_exponent => literal.push(next),
+                            _ => break,
+                        }
+
+                        chars.next();
+                    }
+
+                    let mut span = Span::new(start, start + literal.len());
+                    if let Err(e) = Self::number(&literal) {
+                        errors.push(Spanned::new(e, span));
+                    }
+
+                    // a unit right after the number is part of the literal:
+                    // `3 m`, `20 km/h`
+                    let rest = &expr[span.end..];
+                    let gap = rest.len() - rest.trim_start_matches(' ').len();
+                    let unit = units::scan(&rest[gap..]);
+
+                    if unit > 0 {
+                        literal = format!("{} {}", literal, &rest[gap..gap + unit]);
+                        span.end += gap + unit;
+
+                        while chars.next_if(|&(i, _)| i < span.end).is_some() {}
+                    }
+
+                    tokens.push(Spanned::new(Token::Number(literal), span));
+                }
+                '(' => {
+                    Self::juxtapose(&mut tokens, start);
+                    tokens.push(Spanned::new(Token::Bracket(c), span));
+                    opening_brackets.push(span);
+                }
+                ')' => match opening_brackets.pop() {
+                    Some(_) => tokens.push(Spanned::new(Token::Bracket(c), span)),
+                    // a closing bracket with no opening one
+                    None => errors.push(Spanned::new(Error::MismatchedParens, span)),
+                },
+                // An identifier is a variable name (or the `let` keyword, or the
+                // `xor` operator)
+                'a'..='z' | 'A'..='Z' | '_' => {
+                    let mut name = String::from(c);
+
+                    while let Some(&(_, next)) = chars.peek() {
+                        if !(next.is_ascii_alphanumeric() || next == '_') {
+                            break;
+                        }
+
+                        name.push(next);
+                        chars.next();
+                    }
+
+                    let span = Span::new(start, start + name.len());
+
+                    // `to` followed by a unit converts the result of the line
+                    let rest = &expr[span.end..];
+                    let gap = rest.len() - rest.trim_start_matches(' ').len();
+                    let unit = rest[gap..]
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || "_^*/-".contains(c)))
+                        .unwrap_or(rest.len() - gap);
+                    let converts = name == "to"
+                        && !tokens.is_empty()
+                        && rest[gap..].starts_with(|c: char| c.is_ascii_alphabetic());
+
+                    // `xor` is an operator, and a name (but not a keyword)
+                    // right before an opening bracket is a function call
+                    if converts {
+                        let span = Span::new(start, span.end + gap + unit);
+                        let unit = rest[gap..gap + unit].to_string();
+
+                        while chars.next_if(|&(i, _)| i < span.end).is_some() {}
+                        tokens.push(Spanned::new(Token::Convert(unit), span));
+                    } else if name == "xor" {
+                        tokens.push(Spanned::new(Token::Op(Operator::BitXor), span));
+                    } else if chars.peek().is_some_and(|&(_, c)| c == '(')
+                        && !KEYWORDS.contains(&name.as_str())
+                    {
+                        Self::juxtapose(&mut tokens, start);
+                        tokens.push(Spanned::new(Token::Func(name), span));
+                    } else {
+                        Self::juxtapose(&mut tokens, start);
+                        tokens.push(Spanned::new(Token::Ident(name), span));
+                    }
+                }
+                ',' => tokens.push(Spanned::new(Token::Comma, span)),
+                '?' => tokens.push(Spanned::new(Token::Question, span)),
+                ':' => tokens.push(Spanned::new(Token::Colon, span)),
+                // Operators of one or two characters: `=` or `==`, `<` or `<=`...
+                '=' | '!' | '<' | '>' | '&' | '|' => {
+                    let second = chars.peek().map(|&(_, next)| next);
+
+                    let (token, len) = match (c, second) {
+                        ('=', Some('=')) => (Token::Op(Operator::Eq), 2),
+                        ('!', Some('=')) => (Token::Op(Operator::Ne), 2),
+                        ('<', Some('=')) => (Token::Op(Operator::Le), 2),
+                        ('>', Some('=')) => (Token::Op(Operator::Ge), 2),
+                        ('<', Some('<')) => (Token::Op(Operator::Shl), 2),
+                        ('>', Some('>')) => (Token::Op(Operator::Shr), 2),
+                        ('&', Some('&')) => (Token::Op(Operator::And), 2),
+                        ('|', Some('|')) => (Token::Op(Operator::Or), 2),
+                        ('=', _) => (Token::Assign, 1),
+                        // right after an operand `!` is the factorial
+                        ('!', _) if Self::ends_operand(&tokens) => (Token::Op(Operator::Fact), 1),
+                        ('!', _) => (Token::Op(Operator::Not), 1),
+                        ('<', _) => (Token::Op(Operator::Lt), 1),
+                        ('>', _) => (Token::Op(Operator::Gt), 1),
+                        ('&', _) => (Token::Op(Operator::BitAnd), 1),
+                        _ => (Token::Op(Operator::BitOr), 1),
+                    };
+
+                    if len == 2 {
+                        chars.next();
+                    }
+
+                    tokens.push(Spanned::new(token, Span::new(start, start + len)));
+                }
+                // `+` and `-` are prefix (unary) operators when there is no
+                // operand to their left: at the start of the input, right after
+                // another operator (but `3! - 1` is a subtraction), an opening
+                // bracket, a `,`, an `=` or one of the two halves of a
+                // conditional.
+                '+' | '-' => {
+                    let unary = !Self::ends_operand(&tokens);
+
+                    let op = match (c, unary) {
+                        ('+', true) => Operator::Pos,
+                        ('+', false) => Operator::Add,
+                        ('-', true) => Operator::Neg,
+                        _ => Operator::Sub,
+                    };
+
+                    tokens.push(Spanned::new(Token::Op(op), span));
+                }
+                '*' => tokens.push(Spanned::new(Token::Op(Operator::Mul), span)),
+                '/' => tokens.push(Spanned::new(Token::Op(Operator::Div), span)),
+                '^' => tokens.push(Spanned::new(Token::Op(Operator::Pow), span)),
+                '~' => tokens.push(Spanned::new(Token::Op(Operator::BitNot), span)),
+                '%' => tokens.push(Spanned::new(Token::Op(Operator::Percent), span)),
+                // tabs and the `\r` of Windows line endings separate tokens
+                // like spaces do
+                c if c.is_whitespace() => {}
+                _ => errors.push(Spanned::new(Error::BadToken(c), span)),
+            }
+        }
+
+        // the opening brackets that were never closed
+        for span in opening_brackets {
+            errors.push(Spanned::new(Error::MismatchedParens, span));
+        }
+
+        (tokens, errors)
+    }
+
+    /// Whether the last token ends an operand: a literal, a variable (not a
+    /// keyword), a closing bracket or a postfix operator.
+    fn ends_operand(tokens: &[Spanned<Token>]) -> bool {
+        match tokens.last().map(|t| &t.node) {
+            Some(Token::Number(_) | Token::Bracket(')')) => true,
+            Some(Token::Ident(name)) => !KEYWORDS.contains(&name.as_str()),
+            Some(Token::Op(op)) => op.is_postfix(),
+            _ => false,
+        }
+    }
+
+    /// Implicit multiplication: a variable, a call or a bracket starting at
+    /// `start` right after an operand is multiplied by it, so `2(3 + 4)`,
+    /// `3x` and `(a + b)(a - b)` are products. It's an ordinary `*`, so
+    /// `1 / 2x` is `(1 / 2) * x`. The `*` takes no room in the source, errors
+    /// about it point at the start of the right operand.
+    ///
+    /// A literal right after an operand (`2 3`, `x 2`) is still an error, and
+    /// a known unit after a number is a quantity (`3 m`, see `units.rs`).
+    fn juxtapose(tokens: &mut Vec<Spanned<Token>>, start: usize) {
+        if Self::ends_operand(tokens) {
+            let span = Span::new(start, start);
+            tokens.push(Spanned::new(Token::Op(Operator::Mul), span));
+        }
+    }
+
+    /// Validate a numeric literal gathered by the tokenizer. Literals are kept
+    /// as text so every `Numeric` backend can read them at its own precision.
+    ///
+    /// The accepted grammar is `digits? ('.' digits?)? (('e' | 'E') ('+' | '-')? digits)?`
+    /// with at least one digit before the exponent, so `.5` and `5.` are fine
+    /// but `.`, `1..2`, `3e` or `1e+` are not.
+    ///
+    /// Integers can also be written in hexadecimal (`0xff`), octal (`0o17`) or
+    /// binary (`0b1010`), with at least one digit of that base.
+    fn number(literal: &str) -> Result<(), Error> {
+        let bad_number = || Error::BadNumber(literal.to_string());
+
+        if let Some((radix, digits)) = numeric::radix(literal) {
+            if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                return Err(bad_number());
+            }
+
+            return Ok(());
+        }
+
+        let (mantissa, exponent) = match literal.find(['e', 'E']) {
+            Some(i) => (&literal[..i], Some(&literal[i + 1..])),
+            None => (literal, None),
+        };
+
+        let (integer, fraction) = match mantissa.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (mantissa, ""),
+        };
+
+        let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+
+        if !is_digits(integer) || !is_digits(fraction) || integer.len() + fraction.len() == 0 {
+            return Err(bad_number());
+        }
+
+        if let Some(exponent) = exponent {
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+
+            if digits.is_empty() || !is_digits(digits) {
+                return Err(bad_number());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert the tokens into a "Reverse Polish Notation" (RPN).
+    ///
+    /// Function calls end up in the output as a single `Token::Call` placed
+    /// after their arguments, carrying how many arguments were passed.
+    ///
+    /// A conditional `c ? a : b` ends up as `c a b ?:`. The `?` waits on the
+    /// operator stack (stopping operators from being popped past it, like a
+    /// bracket does) until its `:` arrives and replaces it with the
+    /// `Operator::Cond` itself.
+    ///
+    /// Every token either starts an operand (a literal, a variable, a prefix
+    /// operator, a bracket, a call) or comes after one (a binary or postfix
+    /// operator, `?`, `:`, `,`, `)`), so we know which one we expect next and
+    /// spot a missing operand or operator where it happens. An error doesn't
+    /// stop the conversion: operators and brackets tell what comes after
+    /// them, they are where we pick up again, and every error is reported.
+    ///
+    /// - See: https://en.wikipedia.org/wiki/Reverse_Polish_notation
+    fn expression(tokens: Vec<Spanned<Token>>) -> Result<Vec<Spanned<Token>>, Errors> {
+        Self::expression_with(tokens, &mut ())
+    }
+
+    /// `expression`, telling `observer` about every move of the tokens (see
+    /// `trace.rs`).
+    fn expression_with(
+        tokens: Vec<Spanned<Token>>,
+        observer: &mut impl Observer,
+    ) -> Result<Vec<Spanned<Token>>, Errors> {
+        Self::expression_in(tokens, observer, &functions::arity)
+    }
+
+    /// `expression` where `functions` tells the arity of the functions that
+    /// can be called, the built-ins and those of a script.
+    fn expression_in(
+        mut tokens: Vec<Spanned<Token>>,
+        observer: &mut impl Observer,
+        functions: &dyn Fn(&str) -> Option<Arity>,
+    ) -> Result<Vec<Spanned<Token>>, Errors> {
+        tokens.reverse();
+
+        let mut yard = Yard::new(observer);
+        let mut errors: Vec<Spanned<Error>> = vec![];
+
+        // One entry per open bracket, `true` if it holds the arguments of a call
+        let mut groups: Vec<bool> = vec![];
+        // Arguments counted so far for every call we are in, innermost last
+        let mut arguments: Vec<usize> = vec![];
+        // Whether the previous token was an opening bracket (to spot `f()`)
+        let mut after_open = false;
+        // Whether the next token has to start an operand
+        let mut operand = true;
+        // The span of the last token, an operand missing at the end of the
+        // input is reported there
+        let mut last = Span::new(0, 0);
+
+        while let Some(token) = tokens.pop() {
+            yard.observer.event(Event::Read(&token));
+
+            let opens = token.node == Token::Bracket('(');
+
+            let starts_operand = match &token.node {
+                Token::Number(_) | Token::Ident(_) | Token::Func(_) | Token::Bracket('(') => true,
+                Token::Op(op) => op.is_prefix(),
+                _ => false,
+            };
+
+            let missing = match &token.node {
+                _ if starts_operand => (!operand).then_some(Error::MissingOperator),
+                // a call without arguments, as in `pi()`
+                Token::Bracket(')') if after_open && groups.last() == Some(&true) => None,
+                // a stray `=` is up to `Calculator::statement`
+                Token::Assign | Token::Call(..) => None,
+                // an empty argument, as in `f(, 1)` or `f(1,)`, is one too
+                _ => operand.then_some(Error::MissingOperand),
+            };
+
+            if let Some(error) = missing {
+                errors.push(Spanned::new(error, token.span));
+            }
+
+            operand = match &token.node {
+                Token::Number(_) | Token::Ident(_) | Token::Bracket(')') | Token::Call(..) => false,
+                Token::Op(op) if op.is_postfix() => false,
+                Token::Convert(_) => false,
+                _ => true,
+            };
+            last = token.span;
+
+            match token.node {
+                Token::Number(_) | Token::Ident(_) => yard.output(token),
+                // the function waits on the operator stack until its closing bracket
+                Token::Func(_) => yard.push(token),
+                // a prefix operator has no left operand yet, so there is
+                // nothing on the stack it could take precedence over.
+                Token::Op(ref incoming) if incoming.is_prefix() => yard.push(token),
+                // a postfix operator binds tighter than anything on the stack,
+                // its operand is already complete in the queue.
+                Token::Op(ref incoming) if incoming.is_postfix() => yard.output(token),
+                Token::Op(ref incoming) => {
+                    // pop every operator that binds at least as tight as the incoming
+                    // one (strictly tighter for right associative operators), stopping
+                    // at an opening bracket.
+                    while let Some(Token::Op(top)) = yard.top() {
+                        if !top.pops_before(incoming) {
+                            break;
+                        }
+                        yard.pop_output();
+                    }
+
+                    yard.push(token);
+                }
+                Token::Question => {
+                    // as far as precedence goes the `?` is the incoming `Cond`
+                    while let Some(Token::Op(top)) = yard.top() {
+                        if !top.pops_before(&Operator::Cond) {
+                            break;
+                        }
+                        yard.pop_output();
+                    }
+
+                    yard.push(token);
+                }
+                Token::Colon => {
+                    // flush the `then` branch, up to the `?` it belongs to
+                    while let Some(Token::Op(_)) = yard.top() {
+                        yard.pop_output();
+                    }
+
+                    match yard.top() {
+                        Some(Token::Question) => {
+                            let question = yard.pop().unwrap();
+                            let cond = Spanned::new(Token::Op(Operator::Cond), question.span);
+                            yard.push(cond);
+                        }
+                        _ => errors.push(Spanned::new(Error::MisplacedColon, token.span)),
+                    }
+                }
+                Token::Bracket('(') => {
+                    let call = matches!(yard.top(), Some(Token::Func(_)));
+
+                    groups.push(call);
+                    if call {
+                        arguments.push(1);
+                    }
+
+                    yard.push(token);
+                }
+                Token::Comma => {
+                    if groups.last() != Some(&true) {
+                        errors.push(Spanned::new(Error::MisplacedComma, token.span));
+                        continue;
+                    }
+
+                    // flush the argument we just finished
+                    while yard.top() != Some(&Token::Bracket('(')) {
+                        let op = yard.pop().unwrap();
+                        yard.output(Self::flush(op, &mut errors));
+                    }
+
+                    *arguments.last_mut().unwrap() += 1;
+                }
+                Token::Bracket(')') => {
+                    while !matches!(yard.top(), None | Some(Token::Bracket('('))) {
+                        let op = yard.pop().unwrap();
+                        yard.output(Self::flush(op, &mut errors));
+                    }
+                    // discard the last opening bracket
+                    yard.pop();
+
+                    if groups.pop() == Some(true) {
+                        let count = arguments.pop().unwrap();
+                        let count = if after_open { 0 } else { count };
+                        let func = yard.pop().unwrap();
+
+                        if let Token::Func(name) = func.node {
+                            let span = func.span.to(token.span);
+
+                            match functions(&name) {
+                                None => {
+                                    let error = Error::UnknownFunction(name.clone());
+                                    errors.push(Spanned::new(error, func.span));
+                                }
+                                Some(arity) if !arity.accepts(count) => {
+                                    let error = Error::WrongArity {
+                                        name: name.clone(),
+                                        expected: arity,
+                                        found: count,
+                                    };
+                                    errors.push(Spanned::new(error, span));
+                                }
+                                Some(_) => {}
+                            }
+
+                            yard.output(Spanned::new(Token::Call(name, count), span));
+                        }
+                    }
+                }
+                Token::Convert(_) => {
+                    errors.push(Spanned::new(Error::MisplacedConversion, token.span))
+                }
+                Token::Bracket(_) | Token::Assign | Token::Call(..) => {}
+            }
+
+            after_open = opens;
+        }
+
+        if operand {
+            errors.push(Spanned::new(Error::MissingOperand, last));
+        }
+
+        yard.observer.event(Event::Flush);
+        while let Some(op) = yard.pop() {
+            yard.output(Self::flush(op, &mut errors));
+        }
+
+        Errors::check(errors)?;
+        Ok(yard.queue)
+    }
+
+    /// Check an operator leaving the stack for the output queue at the end of
+    /// a group: a `?` still there never got its `:`.
+    fn flush(op: Spanned<Token>, errors: &mut Vec<Spanned<Error>>) -> Spanned<Token> {
+        if op.node == Token::Question {
+            errors.push(Spanned::new(Error::MissingColon, op.span));
+        }
+
+        op
+    }
+
+    /// Parse the tokens into an `Expr` tree (see `ast.rs`).
+    pub fn parse(tokens: Vec<Spanned<Token>>) -> Result<Expr, Errors> {
+        Ok(Expr::from_rpn(Self::expression(tokens)?)?)
+    }
+
+    /// Split a line of tokens into a `Statement`. A line starting with the
+    /// `let` keyword must look like `let <name> = <expression>`.
+    pub fn statement(tokens: Vec<Spanned<Token>>) -> Result<Statement, Errors> {
+        Self::statement_in(tokens, &functions::arity)
+    }
+
+    /// `statement` where `functions` tells the arity of the functions that
+    /// can be called, like for `Calculator::expression_in`.
+    fn statement_in(
+        mut tokens: Vec<Spanned<Token>>,
+        functions: &dyn Fn(&str) -> Option<Arity>,
+    ) -> Result<Statement, Errors> {
+        let is_let = matches!(tokens.first(), Some(t) if t.node == Token::Ident("let".into()));
+        let mut errors = vec![];
+
+        let name = if is_let {
+            let head: Vec<_> = tokens.drain(..3.min(tokens.len())).collect();
+            let span = head[0].span.to(head[head.len() - 1].span);
+
+            match (head.get(1).map(|t| &t.node), head.get(2).map(|t| &t.node)) {
+                (Some(Token::Ident(name)), Some(Token::Assign)) => Some(name.clone()),
+                _ => {
+                    errors.push(Spanned::new(Error::InvalidAssignment, span));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        for assign in tokens.iter().filter(|t| t.node == Token::Assign) {
+            errors.push(Spanned::new(Error::InvalidAssignment, assign.span));
+        }
+
+        // `let x 5` is one mistake, not one more for the missing expression
+        if is_let && name.is_none() && tokens.is_empty() {
+            return Err(Errors::new(errors));
+        }
+
+        // `to <unit>` applies to the result, it comes last in RPN too
+        let convert = tokens.pop_if(|t| matches!(t.node, Token::Convert(_)));
+
+        let mut rpn = match Self::expression_in(tokens, &mut (), functions) {
+            Ok(rpn) => rpn,
+            Err(syntax) => {
+                errors.extend(syntax);
+                vec![]
+            }
+        };
+        rpn.extend(convert);
+
+        Errors::check(errors)?;
+
+        Ok(match name {
+            Some(name) => Statement::Let(name, rpn),
+            None => Statement::Expr(rpn),
+        })
+    }
+
+    /// Evaluate a statement, binding the result in `env` if it is a `let`.
+    pub fn execute<N: Numeric>(
+        statement: &Statement,
+        env: &mut Environment<Value<N>>,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        Self::execute_with(statement, env, &N::Context::default())
+    }
+
+    /// `execute` with the given settings for the backend, like the width of
+    /// the integers in programmer mode.
+    pub fn execute_with<N: Numeric>(
+        statement: &Statement,
+        env: &mut Environment<Value<N>>,
+        context: &N::Context,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        let (Statement::Expr(rpn) | Statement::Let(_, rpn)) = statement;
+        let value = Self::evaluate_with(rpn, env, context)?;
+
+        if let Statement::Let(name, _) = statement {
+            env.set(name.as_str(), value.clone());
+        }
+
+        Ok(value)
+    }
+
+    /// `evaluate_with` the default settings of the backend.
+    pub fn evaluate<N: Numeric>(
+        rpn: &[Spanned<Token>],
+        env: &Environment<Value<N>>,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        Self::evaluate_with(rpn, env, &N::Context::default())
+    }
+
+    /// Evaluate an expression in RPN. Variables are looked up in `env`.
+    ///
+    /// The kind of number we compute with is picked by the environment: an
+    /// `Environment<Value<f64>>` evaluates with floats, an
+    /// `Environment<Value<Rational>>` with exact fractions (see `numeric.rs`).
+    /// `context` holds the settings of that backend, if it has any.
+    ///
+    /// The expression is compiled to bytecode and run on the VM (see
+    /// `bytecode.rs`). To evaluate the same expression many times compile it
+    /// once with `Program::compile` instead.
+    pub fn evaluate_with<N: Numeric>(
+        rpn: &[Spanned<Token>],
+        env: &Environment<Value<N>>,
+        context: &N::Context,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        Program::compile(rpn, context)?.run_in(env)
+    }
+}
+
+// Start a session:
+//
+// ```
+// cargo run --example calc
+// ```
+//
+// or run a script (see `script.rs`):
+//
+// ```
+// cargo run --example calc -- fib.calc
+// ```
+fn main() {
+    if let Some(path) = std::env::args().nth(1) {
+        let (output, error) = Repl::new().run_script(&path);
+
+        for line in output {
+            println!("{}", line);
+        }
+        if let Some(error) = error {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let stdin = std::io::stdin();
+
+    if let Err(e) = Repl::new().run(stdin.lock(), std::io::stdout()) {
+        eprintln!("calc: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // Run the tests:
+    //
+    // ```bash
+    // # Test all
+    // cargo test --example calc
+    //
+    // # Test while watch
+    // cargo watch -q -c -w examples/ -x 'test --example calc'
+    // ```
+    use super::*;
+
+    fn eval(expr: &str) -> Result<Value, EvalError> {
+        let tokens = Calculator::tokenize(expr).unwrap();
+        Calculator::evaluate(
+            &Calculator::expression(tokens).unwrap(),
+            &Environment::new(),
+        )
+        .map_err(|e| e.node)
+    }
+
+    fn number(value: Value) -> f64 {
+        match value {
+            Value::Number(n) => n,
+            other => panic!("expected a number, got `{}`", other),
+        }
+    }
+
+    fn calc(expr: &str) -> Result<f64, EvalError> {
+        eval(expr).map(number)
+    }
+
+    /// Every error reported for a line, in order
+    fn errors(line: &str) -> Vec<Spanned<Error>> {
+        match Calculator::read(line, Calculator::statement) {
+            Ok(_) => vec![],
+            Err(errors) => errors.into_iter().collect(),
+        }
+    }
+
+    fn run(line: &str, env: &mut Environment) -> Result<f64, EvalError> {
+        let statement = Calculator::statement(Calculator::tokenize(line).unwrap()).unwrap();
+        Calculator::execute(&statement, env)
+            .map(number)
+            .map_err(|e| e.node)
+    }
+
+    #[test]
+    fn mixed_precedence() {
+        assert_eq!(calc("2 * 2 + 48 / 4"), Ok(16.0));
+        assert_eq!(calc("2 + 3 * 4"), Ok(14.0));
+        assert_eq!(calc("(2 + 3) * 4"), Ok(20.0));
+    }
+
+    #[test]
+    fn left_associative_chains() {
+        assert_eq!(calc("8 - 3 - 2"), Ok(3.0));
+        assert_eq!(calc("8 / 4 / 2"), Ok(1.0));
+        assert_eq!(calc("8 - 3 + 2"), Ok(7.0));
+        assert_eq!(calc("8 / 4 * 2"), Ok(4.0));
+    }
+
+    #[test]
+    fn decimal_and_exponent_literals() {
+        assert_eq!(calc("3.14"), Ok(3.14));
+        assert_eq!(calc(".5 + 5."), Ok(5.5));
+        assert_eq!(calc("1e-3"), Ok(0.001));
+        assert_eq!(calc("2.5E+10"), Ok(2.5e10));
+        assert_eq!(calc("1e3 - 1e-3"), Ok(999.999));
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        for literal in [
+            "1..2", "3e", "1e+", ".", "1.2.3", "2e3.5", "0x", "0b102", "0o8", "0xfg",
+        ] {
+            match Calculator::tokenize(literal)
+                .as_ref()
+                .map_err(Errors::first)
+            {
+                Err(Spanned {
+                    node: Error::BadNumber(n),
+                    ..
+                }) => assert_eq!(n, literal),
+                other => panic!("{} should be a bad number, got {:?}", literal, other),
+            }
+        }
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(calc("0xff"), Ok(255.0));
+        assert_eq!(calc("0XFF + 0b101 + 0o17"), Ok(275.0));
+        assert_eq!(calc("-0b1 * 0x10"), Ok(-16.0));
+        assert_eq!(calc("1 << 2"), Err(EvalError::NotFixedWidth));
+        assert_eq!(calc("~0"), Err(EvalError::NotFixedWidth));
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(calc("-3 + 4"), Ok(1.0));
+        assert_eq!(calc("2 * -(1 + 1)"), Ok(-4.0));
+        assert_eq!(calc("--5"), Ok(5.0));
+        assert_eq!(calc("+5 - +2"), Ok(3.0));
+        assert_eq!(calc("4 - -2"), Ok(6.0));
+        assert_eq!(calc("-2 * 3"), Ok(-6.0));
+        assert_eq!(calc("(-2) / -4"), Ok(0.5));
+    }
+
+    #[test]
+    fn right_associative_power() {
+        assert_eq!(calc("2 ^ 3 ^ 2"), Ok(512.0));
+        assert_eq!(calc("(2 ^ 3) ^ 2"), Ok(64.0));
+        assert_eq!(calc("-2 ^ 2"), Ok(-4.0));
+        assert_eq!(calc("(-2) ^ 2"), Ok(4.0));
+        assert_eq!(calc("2 ^ -1"), Ok(0.5));
+        assert_eq!(calc("2 * 3 ^ 2"), Ok(18.0));
+        assert_eq!(calc("-2 ^ -2 ^ 2"), Ok(-(2f64.powf(-4.0))));
+    }
+
+    #[test]
+    fn postfix_operators() {
+        assert_eq!(calc("3!"), Ok(6.0));
+        assert_eq!(calc("3! - 1"), Ok(5.0));
+        assert_eq!(calc("-3!"), Ok(-6.0));
+        assert_eq!(calc("2 ^ 3!"), Ok(64.0));
+        assert_eq!(calc("3!!"), Ok(720.0));
+        assert_eq!(calc("(1 + 2)! / 2"), Ok(3.0));
+        assert_eq!(calc("50%"), Ok(0.5));
+        assert_eq!(calc("200 * 15%"), Ok(30.0));
+        assert_eq!(eval("3! != 6"), Ok(Value::Bool(false)));
+        assert_eq!(eval("!(1 > 2)"), Ok(Value::Bool(true)));
+        assert!(matches!(errors("%")[0].node, Error::MissingOperand));
+
+        let rpn = Calculator::expression(Calculator::tokenize("-x! + !y").unwrap()).unwrap();
+        let rpn: Vec<_> = rpn.iter().map(|t| t.node.to_string()).collect();
+        assert_eq!(rpn.join(" "), "x fact neg y ! +");
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        let mut env = Environment::new();
+        env.set("a", Value::Number(5.0));
+        env.set("b", Value::Number(3.0));
+        env.set("x", Value::Number(2.0));
+
+        assert_eq!(run("2(3 + 4)", &mut env), Ok(14.0));
+        assert_eq!(run("3x", &mut env), Ok(6.0));
+        assert_eq!(run("(a + b)(a - b)", &mut env), Ok(16.0));
+        assert_eq!(run("2x ^ 2", &mut env), Ok(8.0));
+        assert_eq!(run("1 / 2x", &mut env), Ok(1.0));
+        assert_eq!(run("2 sqrt(x * 8)", &mut env), Ok(8.0));
+        assert_eq!(run("3! x", &mut env), Ok(12.0));
+        assert_eq!(run("let y = 2a", &mut env), Ok(10.0));
+
+        // only literals don't multiply
+        assert!(matches!(errors("2 3")[0].node, Error::MissingOperator));
+
+        // and keywords aren't operands (see `script.rs`)
+        let tokens = Calculator::tokenize("return -x").unwrap();
+        assert_eq!(tokens[1].node, Token::Op(Operator::Neg));
+        let tokens = Calculator::tokenize("if(x)").unwrap();
+        assert_eq!(tokens[0].node, Token::Ident("if".into()));
+        assert_eq!(tokens[1].node, Token::Bracket('('));
+    }
+
+    #[test]
+    fn variables_and_let() {
+        let mut env = Environment::new();
+        env.set("y", Value::Number(1.5));
+
+        assert_eq!(run("let x = 5", &mut env), Ok(5.0));
+        assert_eq!(run("x * 2 + y", &mut env), Ok(11.5));
+        assert_eq!(run("let x = -x", &mut env), Ok(-5.0));
+        assert_eq!(run("x - y", &mut env), Ok(-6.5));
+
+        // unknown variables can't be evaluated
+        assert_eq!(
+            run("z + 1", &mut env),
+            Err(EvalError::UnknownVariable("z".into()))
+        );
+    }
+
+    #[test]
+    fn same_formula_many_records() {
+        let rpn = Calculator::expression(Calculator::tokenize("a * b_2").unwrap()).unwrap();
+        let mut env = Environment::new();
+
+        for (a, b, expected) in [(1.0, 2.0, 2.0), (3.0, 4.0, 12.0), (0.5, 0.5, 0.25)] {
+            env.set("a", Value::Number(a));
+            env.set("b_2", Value::Number(b));
+            assert_eq!(
+                Calculator::evaluate(&rpn, &env).ok(),
+                Some(Value::Number(expected))
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(eval("1 + 1 == 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval("2 * 3 != 6"), Ok(Value::Bool(false)));
+        assert_eq!(
+            eval("1 < 2 && 2 <= 2 && 3 > 2 && 3 >= 4"),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(eval("1 > 2 || !(1 > 2)"), Ok(Value::Bool(true)));
+        // `&&` binds tighter than `||`
+        assert_eq!(eval("1 < 2 || 1 < 2 && 1 > 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval("(1 < 2) == (3 < 4)"), Ok(Value::Bool(true)));
+        assert_eq!(eval("!!(0 == 0)"), Ok(Value::Bool(true)));
+        // NaN is not equal to anything, not even itself
+        assert_eq!(eval("sqrt(-1) == sqrt(-1)"), Ok(Value::Bool(false)));
+        assert_eq!(eval("sqrt(-1) != sqrt(-1)"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(calc("1 < 2 ? 10 : 20"), Ok(10.0));
+        assert_eq!(calc("1 > 2 ? 10 : 20 + 1"), Ok(21.0));
+        assert_eq!(calc("(1 > 2 ? 10 : 20) + 1"), Ok(21.0));
+        assert_eq!(calc("1 > 2 ? 1 : 2 > 3 ? 2 : 3"), Ok(3.0));
+        assert_eq!(calc("1 < 2 ? 2 > 3 ? 1 : 2 : 3"), Ok(2.0));
+        assert_eq!(calc("max(0 == 0 ? -1 : 1, -2)"), Ok(-1.0));
+
+        let rule = "temp > 30 && humidity < 0.4 ? 1 : 0";
+        let rpn = Calculator::expression(Calculator::tokenize(rule).unwrap()).unwrap();
+        let mut env = Environment::new();
+
+        for (temp, humidity, alert) in [(35.0, 0.3, 1.0), (35.0, 0.5, 0.0), (20.0, 0.1, 0.0)] {
+            env.set("temp", Value::Number(temp));
+            env.set("humidity", Value::Number(humidity));
+            assert_eq!(Calculator::evaluate(&rpn, &env), Ok(Value::Number(alert)));
+        }
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        let mut env = Environment::new();
+        run("let x = 0", &mut env).unwrap();
+
+        let check = |expr| {
+            let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap()).unwrap();
+            Calculator::evaluate(&rpn, &env).map_err(|e| e.node)
+        };
+
+        assert_eq!(check("x != 0 && 1 / x > 2"), Ok(Value::Bool(false)));
+        assert_eq!(check("x == 0 || nope > 2"), Ok(Value::Bool(true)));
+        assert_eq!(check("x == 0 ? 0 : 1 / x"), Ok(Value::Number(0.0)));
+        assert_eq!(check("true || 1 / x > 2"), Ok(Value::Bool(true)));
+        assert_eq!(check("!false && x < 1"), Ok(Value::Bool(true)));
+        assert_eq!(check("x == 0 && 1 / x > 2"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            check("x != 0 ? 1 : nope"),
+            Err(EvalError::UnknownVariable("nope".into()))
+        );
+    }
+
+    #[test]
+    fn type_errors() {
+        let wrong = |expected, found| Err(EvalError::WrongType { expected, found });
+
+        assert_eq!(eval("(1 < 2) + 3"), wrong("number", "boolean"));
+        assert_eq!(eval("-(1 < 2)"), wrong("number", "boolean"));
+        assert_eq!(eval("!1"), wrong("boolean", "number"));
+        assert_eq!(eval("1 && 1 < 2"), wrong("boolean", "number"));
+        assert_eq!(eval("1 ? 2 : 3"), wrong("boolean", "number"));
+        assert_eq!(eval("1 == (1 < 2)"), wrong("number", "boolean"));
+        assert_eq!(eval("1 < 2 < 3"), wrong("number", "boolean"));
+        assert_eq!(eval("sqrt(1 < 2)"), wrong("number", "boolean"));
+
+        // the error points at the operand of the wrong type
+        let rpn = Calculator::expression(Calculator::tokenize("1 + (2 > 1)").unwrap()).unwrap();
+        let error = Calculator::evaluate(&rpn, &Environment::<Value>::new()).unwrap_err();
+        assert_eq!(error.span, Span::new(5, 10));
+    }
+
+    #[test]
+    fn malformed_conditionals() {
+        let parse_error = |expr| errors(expr).remove(0);
+
+        let error = parse_error("1 < 2 ? 3");
+        assert!(matches!(error.node, Error::MissingColon));
+        assert_eq!(error.span, Span::new(6, 7));
+
+        assert!(matches!(
+            parse_error("(1 ? 2) : 3").node,
+            Error::MissingColon
+        ));
+        assert!(matches!(
+            parse_error("max(1 ? 2, 3)").node,
+            Error::MissingColon
+        ));
+        assert!(matches!(parse_error("1 : 2").node, Error::MisplacedColon));
+        assert!(matches!(
+            parse_error("1 ? 2 : 3 : 4").node,
+            Error::MisplacedColon
+        ));
+        assert!(matches!(
+            errors("1 ? (2 : 3)").as_slice(),
+            [
+                Spanned {
+                    node: Error::MissingColon,
+                    ..
+                },
+                Spanned {
+                    node: Error::MisplacedColon,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn invalid_assignments() {
+        for line in [
+            "let = 5",
+            "let x 5",
+            "let 2 = 5",
+            "x = 5",
+            "let x = y = 2",
+            "let",
+        ] {
+            let statement = Calculator::statement(Calculator::tokenize(line).unwrap());
+            assert!(
+                matches!(
+                    statement.map_err(|e| e.into_iter().next().unwrap().node),
+                    Err(Error::InvalidAssignment)
+                ),
+                "{} should be rejected",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn function_calls() {
+        assert_eq!(calc("sqrt(16) + abs(-2)"), Ok(6.0));
+        assert_eq!(calc("max(1, 5, 3) - min(4, -2 * 3)"), Ok(11.0));
+        assert_eq!(calc("min(2)"), Ok(2.0));
+        assert_eq!(calc("sqrt(max(3, 4) ^ 2 + 3 ^ 2)"), Ok(5.0));
+        assert_eq!(calc("cos(0) + sin(0)"), Ok(1.0));
+        assert_eq!(calc("2 * abs(1 - 4) ^ 2"), Ok(18.0));
+        assert_eq!(calc("fact(5) / fact(3)"), Ok(20.0));
+        assert!(calc("fact(2.5)").unwrap().is_nan());
+        assert_eq!(calc("fact(171)"), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn call_errors() {
+        let rpn = |expr| {
+            let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap());
+            rpn.map_err(|e| e.into_iter().next().unwrap().node)
+        };
+
+        match rpn("sqrt(1, 2)") {
+            Err(Error::WrongArity {
+                name,
+                expected,
+                found,
+            }) => {
+                assert_eq!(name, "sqrt");
+                assert_eq!(expected, Arity::Exact(1));
+                assert_eq!(found, 2);
+            }
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+
+        assert!(matches!(
+            rpn("max()"),
+            Err(Error::WrongArity { found: 0, .. })
+        ));
+        assert!(matches!(rpn("nope(1)"), Err(Error::UnknownFunction(name)) if name == "nope"));
+        assert!(matches!(rpn("1, 2"), Err(Error::MisplacedComma)));
+        assert!(matches!(rpn("max(1, (2, 3))"), Err(Error::MisplacedComma)));
+        assert!(matches!(rpn("3 + max(1,)"), Err(Error::MissingOperand)));
+        assert!(matches!(rpn("max(, 1)"), Err(Error::MissingOperand)));
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        assert_eq!(calc("1 / 0"), Err(EvalError::DivisionByZero));
+        assert_eq!(calc("1 / (2 - 2)"), Err(EvalError::DivisionByZero));
+        assert_eq!(calc("10 ^ 400"), Err(EvalError::Overflow));
+        assert_eq!(calc("1e308 * 10"), Err(EvalError::Overflow));
+        assert_eq!(calc("1e999"), Err(EvalError::Overflow));
+        assert_eq!(calc("-1e999 * 0"), Err(EvalError::Overflow));
+
+        // reported at the literal, like an operator is
+        let rpn = Calculator::expression(Calculator::tokenize("1 + 2e400").unwrap()).unwrap();
+        let error = Calculator::evaluate(&rpn, &Environment::<Value>::new()).unwrap_err();
+        assert_eq!(error, Spanned::new(EvalError::Overflow, Span::new(4, 9)));
+
+        // the parser doesn't let malformed RPN through any more, but the
+        // tokens of `2 +` taken as they are already are some
+        let rpn = |rpn| {
+            let tokens = Calculator::tokenize(rpn).unwrap();
+            Calculator::evaluate(&tokens, &Environment::<Value>::new()).map_err(|e| e.node)
+        };
+        assert_eq!(rpn("2 +"), Err(EvalError::StackUnderflow));
+        assert_eq!(rpn("-"), Err(EvalError::StackUnderflow));
+        assert_eq!(rpn(""), Err(EvalError::StackUnderflow));
+        assert_eq!(rpn("2 3 4"), Err(EvalError::LeftoverOperands(3)));
+    }
+
+    #[test]
+    fn malformed_rpn_does_not_panic() {
+        let env = Environment::<Value>::new();
+        let token = |node| Spanned::new(node, Span::new(0, 0));
+
+        let rpn = [
+            token(Token::Number("1".into())),
+            token(Token::Call("max".into(), 3)),
+        ];
+        assert_eq!(
+            Calculator::evaluate(&rpn, &env).map_err(|e| e.node),
+            Err(EvalError::StackUnderflow)
+        );
+
+        let rpn = [token(Token::Call("nope".into(), 0))];
+        assert_eq!(
+            Calculator::evaluate(&rpn, &env).map_err(|e| e.node),
+            Err(EvalError::UnknownFunction("nope".into()))
+        );
+    }
+
+    #[test]
+    fn errors_carry_spans() {
+        let tokenize_error = |expr| Calculator::tokenize(expr).unwrap_err().first().span;
+        assert_eq!(tokenize_error("1 + $"), Span::new(4, 5));
+        assert_eq!(tokenize_error("2 * 1..2"), Span::new(4, 8));
+        assert_eq!(tokenize_error("(1 + (2)"), Span::new(0, 1));
+        assert_eq!(tokenize_error("1 + 2)"), Span::new(5, 6));
+
+        let parse_error = |expr| {
+            let errors = Calculator::statement(Calculator::tokenize(expr).unwrap()).unwrap_err();
+            errors.first().span
+        };
+        assert_eq!(parse_error("1 + sqrt(1, 2)"), Span::new(4, 14));
+        assert_eq!(parse_error("nope(1)"), Span::new(0, 4));
+        assert_eq!(parse_error("let 2 = 3"), Span::new(0, 7));
+        assert_eq!(parse_error("x = 3"), Span::new(2, 3));
+        assert_eq!(parse_error("2 +"), Span::new(2, 3));
+        assert_eq!(parse_error("2 3 4"), Span::new(2, 3));
+
+        let eval_error = |expr| {
+            let rpn = Calculator::expression(Calculator::tokenize(expr).unwrap()).unwrap();
+            Calculator::evaluate(&rpn, &Environment::<Value>::new())
+                .unwrap_err()
+                .span
+        };
+        assert_eq!(eval_error("1 / (2 - 2)"), Span::new(5, 10));
+        assert_eq!(eval_error("1 + x * 2"), Span::new(4, 5));
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let found = |line| -> Vec<_> {
+            errors(line)
+                .iter()
+                .map(|e| (e.node.to_string(), e.span.start))
+                .collect()
+        };
+        let error = |message: &str, at| (message.to_string(), at);
+
+        assert_eq!(
+            found("1 + $ 2 * 1..2 + # 3"),
+            [
+                error("unexpected character `$`", 4),
+                error("malformed number `1..2`", 10),
+                error("unexpected character `#`", 17),
+            ]
+        );
+        assert_eq!(
+            found("(1 + * 2)) * (3 -"),
+            [
+                error("missing operand", 5),
+                error("mismatched parenthesis", 9),
+                error("mismatched parenthesis", 13),
+                error("missing operand", 16),
+            ]
+        );
+        assert_eq!(
+            found("nope(1) + sqrt(1, 2) ? 3 , 4 : 5 : 6"),
+            [
+                error("unknown function `nope`", 0),
+                error("`sqrt` takes 1 argument but 2 were given", 10),
+                error("`,` outside of a function call", 25),
+                error("`:` without a `?` before it", 33),
+            ]
+        );
+        assert_eq!(
+            found("let x = 2 3 + = 4 to km"),
+            [
+                error("expected an operator before this", 10),
+                error("expected `let <name> = <expression>`", 14),
+            ]
+        );
+        assert_eq!(
+            found("max(1,, 2) + 1e"),
+            [
+                error("missing operand", 6),
+                error("malformed number `1e`", 13),
+            ]
+        );
+        assert!(found("max(1, 2) + (3 ? 4 : 5)").is_empty());
+    }
+
+    #[test]
+    fn renders_errors() {
+        let input = "10 / (5 - 5)";
+        let rpn = Calculator::expression(Calculator::tokenize(input).unwrap()).unwrap();
+        let error = Calculator::evaluate(&rpn, &Environment::<Value>::new()).unwrap_err();
+
+        assert_eq!(
+            error.render(input),
+            "error: division by zero\n \
+             --> 1:7\n  \
+             |\n\
+             1 | 10 / (5 - 5)\n  \
+             |       ^~~~~"
+        );
+    }
+}
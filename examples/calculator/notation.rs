@@ -0,0 +1,574 @@
+/*
+# Notations
+
+The `Display` of `Expr` is an s-expression, which is unambiguous but not what
+anyone writes. This renders a tree the way people read formulas:
+
+- `Expr::infix`: text the calculator reads back as the same tree, with only
+  the brackets it needs (`(a + b) * c`, but `a + b * c`) and an explicit `*`
+  for implicit multiplications. Parsing it again gives back the same tree,
+  so `parse → infix → parse` is the identity.
+- `Expr::latex`: LaTeX math, with fractions, exponents, roots and `cases`
+  for conditionals.
+- `Expr::mathml`: presentation MathML, the same layout as LaTeX.
+
+An operand needs brackets when the operator of its node binds looser than
+its parent's (see `Operator::precedence`), or as loose on the side the
+parent doesn't associate from: `8 - (3 - 2)`, `(2 ^ 3) ^ 2`. A prefix
+operator on the right never does, `2 ^ -x` reads as `2 ^ (-x)` anyway. In
+LaTeX and MathML a fraction, a root or a `cases` holds together without
+brackets.
+
+- See: https://www.w3.org/TR/mathml-core/
+*/
+
+use std::fmt;
+
+use crate::ast::{Expr, ExprKind};
+use crate::token::{Associativity, Operator};
+
+/// Binds tighter than any operator: literals, variables and calls
+const ATOM: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How a notation lays a formula out
+enum Layout {
+    /// Everything on one line, like the input
+    Line,
+    /// Fractions and exponents drawn in two dimensions
+    Math,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Which operand of an operator a sub expression is
+enum Side {
+    Left,
+    Right,
+}
+
+/// How tightly a node holds together.
+fn strength(expr: &Expr, layout: Layout) -> u8 {
+    match &expr.kind {
+        // a negative number reads as a negation
+        ExprKind::Number(num) if num.is_sign_negative() => Operator::Neg.precedence(),
+        ExprKind::Binary {
+            op: Operator::Div, ..
+        } if layout == Layout::Math => ATOM,
+        ExprKind::Unary { op, .. } | ExprKind::Binary { op, .. } => op.precedence(),
+        ExprKind::Conditional { .. } if layout == Layout::Math => ATOM,
+        ExprKind::Conditional { .. } => Operator::Cond.precedence(),
+        _ => ATOM,
+    }
+}
+
+/// Whether `child`, on `side` of `op`, needs brackets. The operand of a
+/// prefix operator is on its right, the one of a postfix operator on its
+/// left.
+fn bracketed(op: Operator, side: Side, child: &Expr, layout: Layout) -> bool {
+    let prefix = match &child.kind {
+        ExprKind::Number(num) => num.is_sign_negative(),
+        ExprKind::Unary { op, .. } => op.is_prefix(),
+        _ => false,
+    };
+
+    match (layout, op, side) {
+        // the bar of a fraction and the height of an exponent group already
+        (Layout::Math, Operator::Div, _) | (Layout::Math, Operator::Pow, Side::Right) => false,
+        (Layout::Math, Operator::Pow, Side::Left) => {
+            strength(child, layout) < ATOM || matches!(child.kind, ExprKind::Binary { .. })
+        }
+        (_, _, Side::Right) if prefix => false,
+        _ => {
+            let (p, c) = (op.precedence(), strength(child, layout));
+
+            match (op.associativity(), side) {
+                (Associativity::Left, Side::Left) | (Associativity::Right, Side::Right) => c < p,
+                _ => c <= p,
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Infix text with as few brackets as possible, `Calculator::parse`
+    /// reads it back as the same tree.
+    pub fn infix(&self) -> Infix<'_> {
+        Infix(self)
+    }
+
+    /// The expression as LaTeX math.
+    pub fn latex(&self) -> Latex<'_> {
+        Latex(self)
+    }
+
+    /// The expression as a MathML `<math>` element.
+    pub fn mathml(&self) -> MathMl<'_> {
+        MathMl(self)
+    }
+}
+
+/// See `Expr::infix`
+pub struct Infix<'a>(&'a Expr);
+
+/// See `Expr::latex`
+pub struct Latex<'a>(&'a Expr);
+
+/// See `Expr::mathml`
+pub struct MathMl<'a>(&'a Expr);
+
+impl fmt::Display for Infix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        infix(f, self.0)
+    }
+}
+
+fn infix(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    let operand = |f: &mut fmt::Formatter<'_>, op, side, child: &Expr| {
+        if bracketed(op, side, child, Layout::Line) {
+            write!(f, "({})", child.infix())
+        } else {
+            write!(f, "{}", child.infix())
+        }
+    };
+
+    match &expr.kind {
+        ExprKind::Number(num) => write!(f, "{}", num),
+        ExprKind::Quantity { value, unit } => write!(f, "{} {}", value, unit),
+        ExprKind::Variable(name) => write!(f, "{}", name),
+        ExprKind::Call { name, args } => {
+            write!(f, "{}(", name)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                infix(f, arg)?;
+            }
+            write!(f, ")")
+        }
+        ExprKind::Unary { op, operand: x } if op.is_postfix() => {
+            operand(f, *op, Side::Left, x)?;
+            write!(f, "{}", op)
+        }
+        ExprKind::Unary { op, operand: x } => {
+            write!(f, "{}", op)?;
+            operand(f, *op, Side::Right, x)
+        }
+        ExprKind::Binary { op, lhs, rhs } => {
+            operand(f, *op, Side::Left, lhs)?;
+            write!(f, " {} ", op)?;
+            operand(f, *op, Side::Right, rhs)
+        }
+        // anything goes between `?` and `:`
+        ExprKind::Conditional {
+            cond,
+            then,
+            otherwise,
+        } => {
+            operand(f, Operator::Cond, Side::Left, cond)?;
+            write!(f, " ? {} : ", then.infix())?;
+            operand(f, Operator::Cond, Side::Right, otherwise)
+        }
+    }
+}
+
+impl fmt::Display for Latex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        latex(f, self.0)
+    }
+}
+
+fn latex(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    let operand = |f: &mut fmt::Formatter<'_>, op, side, child: &Expr| {
+        if bracketed(op, side, child, Layout::Math) {
+            write!(f, "\\left({}\\right)", child.latex())
+        } else {
+            write!(f, "{}", child.latex())
+        }
+    };
+
+    match &expr.kind {
+        ExprKind::Number(num) => write!(f, "{}", num),
+        ExprKind::Quantity { value, unit } => {
+            write!(
+                f,
+                "{}\\,\\mathrm{{{}}}",
+                value,
+                unit.replace('*', "\\cdot ")
+            )
+        }
+        ExprKind::Variable(name) if name.chars().count() == 1 => write!(f, "{}", name),
+        ExprKind::Variable(name) => write!(f, "\\mathrm{{{}}}", name.replace('_', "\\_")),
+        ExprKind::Call { name, args } => match (name.as_str(), args.as_slice()) {
+            ("sqrt", [x]) => write!(f, "\\sqrt{{{}}}", x.latex()),
+            ("abs", [x]) => write!(f, "\\left|{}\\right|", x.latex()),
+            ("fact", [x]) => {
+                operand(f, Operator::Fact, Side::Left, x)?;
+                write!(f, "!")
+            }
+            _ => {
+                match name.as_str() {
+                    "sin" | "cos" | "exp" | "ln" | "min" | "max" => write!(f, "\\{}", name)?,
+                    _ => write!(f, "\\operatorname{{{}}}", name)?,
+                }
+
+                write!(f, "\\left(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    latex(f, arg)?;
+                }
+                write!(f, "\\right)")
+            }
+        },
+        ExprKind::Unary { op, operand: x } if op.is_postfix() => {
+            operand(f, *op, Side::Left, x)?;
+
+            match op {
+                Operator::Percent => write!(f, "\\%"),
+                _ => write!(f, "{}", op),
+            }
+        }
+        ExprKind::Unary { op, operand: x } => {
+            match op {
+                Operator::Not => write!(f, "\\lnot ")?,
+                Operator::BitNot => write!(f, "\\mathord{{\\sim}}")?,
+                _ => write!(f, "{}", op)?,
+            }
+            operand(f, *op, Side::Right, x)
+        }
+        ExprKind::Binary {
+            op: Operator::Div,
+            lhs,
+            rhs,
+        } => write!(f, "\\frac{{{}}}{{{}}}", lhs.latex(), rhs.latex()),
+        ExprKind::Binary {
+            op: Operator::Pow,
+            lhs,
+            rhs,
+        } => {
+            write!(f, "{{")?;
+            operand(f, Operator::Pow, Side::Left, lhs)?;
+            write!(f, "}}^{{{}}}", rhs.latex())
+        }
+        ExprKind::Binary { op, lhs, rhs } => {
+            let symbol = match op {
+                Operator::Mul => "\\cdot",
+                Operator::Eq => "=",
+                Operator::Ne => "\\neq",
+                Operator::Le => "\\leq",
+                Operator::Ge => "\\geq",
+                Operator::And => "\\land",
+                Operator::Or => "\\lor",
+                Operator::BitAnd => "\\mathbin{\\&}",
+                Operator::BitOr => "\\mathbin{|}",
+                Operator::BitXor => "\\oplus",
+                Operator::Shl => "\\ll",
+                Operator::Shr => "\\gg",
+                _ => "",
+            };
+
+            operand(f, *op, Side::Left, lhs)?;
+            match symbol {
+                "" => write!(f, " {} ", op)?,
+                symbol => write!(f, " {} ", symbol)?,
+            }
+            operand(f, *op, Side::Right, rhs)
+        }
+        ExprKind::Conditional {
+            cond,
+            then,
+            otherwise,
+        } => write!(
+            f,
+            "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+            then.latex(),
+            cond.latex(),
+            otherwise.latex()
+        ),
+    }
+}
+
+impl fmt::Display for MathMl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">")?;
+        mathml(f, self.0)?;
+        write!(f, "</math>")
+    }
+}
+
+fn mathml(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    let operand = |f: &mut fmt::Formatter<'_>, op, side, child: &Expr| {
+        let brackets = bracketed(op, side, child, Layout::Math);
+
+        if brackets {
+            write!(f, "<mrow><mo>(</mo>")?;
+        }
+        mathml(f, child)?;
+        if brackets {
+            write!(f, "<mo>)</mo></mrow>")?;
+        }
+        Ok(())
+    };
+
+    match &expr.kind {
+        ExprKind::Number(num) if num.is_sign_negative() => {
+            write!(f, "<mrow><mo>-</mo><mn>{}</mn></mrow>", -num)
+        }
+        ExprKind::Number(num) => write!(f, "<mn>{}</mn>", num),
+        ExprKind::Quantity { value, unit } => write!(
+            f,
+            "<mrow><mn>{}</mn><mi mathvariant=\"normal\">{}</mi></mrow>",
+            value, unit
+        ),
+        ExprKind::Variable(name) => write!(f, "<mi>{}</mi>", name),
+        ExprKind::Call { name, args } => match (name.as_str(), args.as_slice()) {
+            ("sqrt", [x]) => {
+                write!(f, "<msqrt>")?;
+                mathml(f, x)?;
+                write!(f, "</msqrt>")
+            }
+            ("abs", [x]) => {
+                write!(f, "<mrow><mo>|</mo>")?;
+                mathml(f, x)?;
+                write!(f, "<mo>|</mo></mrow>")
+            }
+            ("fact", [x]) => {
+                write!(f, "<mrow>")?;
+                operand(f, Operator::Fact, Side::Left, x)?;
+                write!(f, "<mo>!</mo></mrow>")
+            }
+            _ => {
+                // U+2061 is the invisible "function application" operator
+                write!(
+                    f,
+                    "<mrow><mi>{}</mi><mo>&#x2061;</mo><mrow><mo>(</mo>",
+                    name
+                )?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "<mo>,</mo>")?;
+                    }
+                    mathml(f, arg)?;
+                }
+                write!(f, "<mo>)</mo></mrow></mrow>")
+            }
+        },
+        ExprKind::Unary { op, operand: x } if op.is_postfix() => {
+            write!(f, "<mrow>")?;
+            operand(f, *op, Side::Left, x)?;
+            write!(f, "<mo>{}</mo></mrow>", op)
+        }
+        ExprKind::Unary { op, operand: x } => {
+            let symbol = match op {
+                Operator::Not => "¬".to_string(),
+                _ => op.to_string(),
+            };
+
+            write!(f, "<mrow><mo>{}</mo>", symbol)?;
+            operand(f, *op, Side::Right, x)?;
+            write!(f, "</mrow>")
+        }
+        ExprKind::Binary {
+            op: op @ (Operator::Div | Operator::Pow),
+            lhs,
+            rhs,
+        } => {
+            let element = match op {
+                Operator::Div => "mfrac",
+                _ => "msup",
+            };
+
+            write!(f, "<{}>", element)?;
+            operand(f, *op, Side::Left, lhs)?;
+            operand(f, *op, Side::Right, rhs)?;
+            write!(f, "</{}>", element)
+        }
+        ExprKind::Binary { op, lhs, rhs } => {
+            let symbol = match op {
+                Operator::Mul => "⋅".to_string(),
+                Operator::Ne => "≠".to_string(),
+                Operator::Lt => "&lt;".to_string(),
+                Operator::Le => "≤".to_string(),
+                Operator::Gt => "&gt;".to_string(),
+                Operator::Ge => "≥".to_string(),
+                Operator::Eq => "=".to_string(),
+                Operator::And => "∧".to_string(),
+                Operator::Or => "∨".to_string(),
+                Operator::BitAnd => "&amp;".to_string(),
+                Operator::BitXor => "⊕".to_string(),
+                Operator::Shl => "≪".to_string(),
+                Operator::Shr => "≫".to_string(),
+                op => op.to_string(),
+            };
+
+            write!(f, "<mrow>")?;
+            operand(f, *op, Side::Left, lhs)?;
+            write!(f, "<mo>{}</mo>", symbol)?;
+            operand(f, *op, Side::Right, rhs)?;
+            write!(f, "</mrow>")
+        }
+        ExprKind::Conditional {
+            cond,
+            then,
+            otherwise,
+        } => {
+            write!(f, "<mrow><mo>{{</mo><mtable><mtr><mtd>")?;
+            mathml(f, then)?;
+            write!(f, "</mtd><mtd><mtext>if&#xA0;</mtext>")?;
+            mathml(f, cond)?;
+            write!(f, "</mtd></mtr><mtr><mtd>")?;
+            mathml(f, otherwise)?;
+            write!(
+                f,
+                "</mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable></mrow>"
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::Expr;
+    use crate::pratt::test::{expression, Rng};
+    use crate::Calculator;
+
+    fn parse(expr: &str) -> Expr {
+        Calculator::parse(Calculator::tokenize(expr).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn minimal_brackets() {
+        for (expr, infix) in [
+            ("(1 + 2) + 3", "1 + 2 + 3"),
+            ("1 + (2 + 3)", "1 + (2 + 3)"),
+            ("8 - (3 - 2)", "8 - (3 - 2)"),
+            ("(a * b) / c", "a * b / c"),
+            ("a / (b * c)", "a / (b * c)"),
+            ("(a + b) * c", "(a + b) * c"),
+            ("a + (b * c)", "a + b * c"),
+            ("2 ^ (3 ^ 2)", "2 ^ 3 ^ 2"),
+            ("(2 ^ 3) ^ 2", "(2 ^ 3) ^ 2"),
+            ("-(2 ^ 2)", "-2 ^ 2"),
+            ("(-2) ^ 2", "(-2) ^ 2"),
+            ("2 ^ (-x)", "2 ^ -x"),
+            ("-(a + b)", "-(a + b)"),
+            ("(-3)!", "(-3)!"),
+            ("-(3!)", "-3!"),
+            ("(x + 1)! %", "(x + 1)!%"),
+            ("!(a && b)", "!(a && b)"),
+            ("(a > 1) && (b < 2)", "a > 1 && b < 2"),
+            ("(x & 0xf) == 0", "(x & 15) == 0"),
+            ("(a ? b : c) ? d : e", "(a ? b : c) ? d : e"),
+            ("a ? (b ? c : d) : (e ? f : g)", "a ? b ? c : d : e ? f : g"),
+            ("(a ? b : c) + 1", "(a ? b : c) + 1"),
+            ("max((1), a ? b : c)", "max(1, a ? b : c)"),
+            ("2(a + b)(a - b)", "2 * (a + b) * (a - b)"),
+            ("3 km/h * (2 h)", "3 km/h * 2 h"),
+        ] {
+            assert_eq!(parse(expr).infix().to_string(), infix, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng(0x51_7cc1_b727_220a);
+
+        for _ in 0..5000 {
+            let depth = rng.below(6);
+            let expr = expression(&mut rng, depth);
+
+            let tree = parse(&expr);
+            let infix = tree.infix().to_string();
+            assert_eq!(
+                parse(&infix).to_string(),
+                tree.to_string(),
+                "{} -> {}",
+                expr,
+                infix
+            );
+        }
+    }
+
+    #[test]
+    fn simplified_trees() {
+        // folding leaves negative numbers, they read as negations
+        let tree = parse("x ^ (0 - 2) * (1 - 3)").simplify(crate::simplify::Inputs::Any);
+        assert_eq!(tree.infix().to_string(), "-2 * x ^ -2");
+        assert_eq!(
+            parse("(0 - 2) ^ x")
+                .simplify(crate::simplify::Inputs::Any)
+                .infix()
+                .to_string(),
+            "(-2) ^ x"
+        );
+    }
+
+    #[test]
+    fn latex() {
+        for (expr, latex) in [
+            ("(a + 1) / (b - 2) * c", "\\frac{a + 1}{b - 2} \\cdot c"),
+            ("(a / b) ^ 2", "{\\left(\\frac{a}{b}\\right)}^{2}"),
+            ("(x + 1) ^ (n - 1)", "{\\left(x + 1\\right)}^{n - 1}"),
+            (
+                "sqrt(abs(x)) + sin(x) ^ 2",
+                "\\sqrt{\\left|x\\right|} + {\\sin\\left(x\\right)}^{2}",
+            ),
+            (
+                "fact(n + 1) == (n + 1)!",
+                "\\left(n + 1\\right)! = \\left(n + 1\\right)!",
+            ),
+            (
+                "!(a <= b) || rate_2 != 50%",
+                "\\lnot \\left(a \\leq b\\right) \\lor \\mathrm{rate\\_2} \\neq 50\\%",
+            ),
+            (
+                "x > 0 ? x : -x",
+                "\\begin{cases} x & \\text{if } x > 0 \\\\ -x & \\text{otherwise} \\end{cases}",
+            ),
+            (
+                "9.81 m/s^2 * 2 kg",
+                "9.81\\,\\mathrm{m/s^2} \\cdot 2\\,\\mathrm{kg}",
+            ),
+        ] {
+            assert_eq!(parse(expr).latex().to_string(), latex, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn mathml() {
+        let math = |body: &str| {
+            format!(
+                "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>",
+                body
+            )
+        };
+
+        assert_eq!(
+            parse("(a + 1) / 2").mathml().to_string(),
+            math("<mfrac><mrow><mi>a</mi><mo>+</mo><mn>1</mn></mrow><mn>2</mn></mfrac>")
+        );
+        assert_eq!(
+            parse("-(x + 1) ^ 2 < 3").mathml().to_string(),
+            math(
+                "<mrow><mrow><mo>-</mo><msup><mrow><mo>(</mo><mrow><mi>x</mi><mo>+</mo><mn>1</mn>\
+                 </mrow><mo>)</mo></mrow><mn>2</mn></msup></mrow><mo>&lt;</mo><mn>3</mn></mrow>"
+            )
+        );
+        assert_eq!(
+            parse("max(x, sqrt(y))").mathml().to_string(),
+            math(
+                "<mrow><mi>max</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mi>x</mi><mo>,</mo>\
+                 <msqrt><mi>y</mi></msqrt><mo>)</mo></mrow></mrow>"
+            )
+        );
+        assert_eq!(
+            parse("c ? 1 : 0").mathml().to_string(),
+            math(
+                "<mrow><mo>{</mo><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mtext>if&#xA0;</mtext>\
+                 <mi>c</mi></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mtext>otherwise</mtext>\
+                 </mtd></mtr></mtable></mrow>"
+            )
+        );
+    }
+}
@@ -0,0 +1,951 @@
+/*
+# Numeric backends
+
+`Calculator::evaluate` doesn't care what a number is as long as it knows how
+to build one from a literal and how to do arithmetic with it. That is what
+the `Numeric` trait describes. There are several backends:
+
+- `f64`: fast, but `1/3*3` isn't exactly `1` and big integers lose precision.
+- `Rational`: exact fractions of arbitrary precision integers. Results that
+  aren't fractions (`sqrt(2)`, `sin(1)`) are an error rather than a rounded
+  fraction passing for an exact one.
+- `BigInt`: arbitrary precision integers. Anything that leaves the integers
+  (`7 / 2`, `2 ^ -1`, `sqrt(2)`, `1.5`) is an error rather than being
+  silently rounded.
+- `Word`: fixed width integers (`u8`, `i32`...), the only backend with the
+  bitwise operators. Which width is a setting of the evaluation, the
+  `Context` of the backend, as literals have to be read with it.
+- `Quantity`: an `f64` with a dimension, the only backend with units (see
+  `units.rs`).
+- `Complex`: a pair of `f64`, the only backend with the imaginary unit `i`
+  (see `complex.rs`).
+
+Every backend reads decimal literals as well as `0x`, `0o` and `0b` ones.
+The backends computing with floats know `pi` and `e`, the exact ones refuse
+them.
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::bigint::BigInt;
+use crate::complex::Complex;
+use crate::functions::{self, Function};
+use crate::rational::Rational;
+use crate::token::Operator;
+use crate::units::{self, Quantity, Unit};
+use crate::word::{Word, WordType};
+use crate::EvalError;
+
+/// Exponents allowed in literals when parsing them exactly.
+const MAX_LITERAL_EXPONENT: u32 = 10_000;
+
+/// Results bigger than this (in bits) are reported as an overflow, so `9^9^9`
+/// fails instead of eating all the memory.
+const MAX_BITS: u64 = 1 << 20;
+
+/// Numbers are also compared (`<`, `==`...), `partial_cmp` returns `None`
+/// for values that aren't ordered, like a NaN.
+pub trait Numeric: Clone + PartialOrd + fmt::Display {
+    /// Settings the evaluation needs to build numbers, `()` when there are none
+    type Context: Default;
+
+    /// Build a number out of a literal as it was written (`3`, `.5`, `1e-3`,
+    /// `0xff`)
+    fn literal(literal: &str, context: &Self::Context) -> Result<Self, EvalError>;
+
+    /// The literal with a minus in front of it, `-128`. Only differs from
+    /// negating the literal for fixed width integers, where the minimum has
+    /// no positive counterpart.
+    fn negated_literal(literal: &str, context: &Self::Context) -> Result<Self, EvalError> {
+        Self::literal(literal, context)?.neg()
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError>;
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError>;
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError>;
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError>;
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError>;
+    fn neg(&self) -> Result<Self, EvalError>;
+
+    /// Apply a built-in function, `args` has already been checked against
+    /// the function's arity.
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError>;
+
+    /// The number a variable that isn't bound stands for: `pi`, `e` and the
+    /// imaginary unit `i` of `Complex`. The exact backends refuse `pi` and
+    /// `e` rather than have them be unknown.
+    fn constant(name: &str) -> Option<Result<Self, EvalError>> {
+        match name {
+            "pi" | "e" => Some(Err(EvalError::NotExact)),
+            _ => None,
+        }
+    }
+
+    // The bitwise operators only make sense for fixed width integers, the
+    // other backends refuse them.
+
+    fn bit_and(&self, _rhs: &Self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    fn bit_or(&self, _rhs: &Self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    fn bit_xor(&self, _rhs: &Self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    fn bit_not(&self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    fn shl(&self, _rhs: &Self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    fn shr(&self, _rhs: &Self) -> Result<Self, EvalError> {
+        Err(EvalError::NotFixedWidth)
+    }
+
+    /// The postfix `!`, the same as `fact`.
+    fn factorial(&self) -> Result<Self, EvalError> {
+        let fact = functions::lookup("fact").expect("`fact` is a built-in function");
+        Self::call(fact, std::slice::from_ref(self))
+    }
+
+    /// The postfix `%`, `self / 100`.
+    fn percent(&self) -> Result<Self, EvalError> {
+        self.div(&Self::literal("100", &Self::Context::default())?)
+    }
+
+    /// Order two numbers. Quantities of different dimensions can't be
+    /// compared at all, which is an error rather than `None`.
+    fn compare(&self, rhs: &Self) -> Result<Option<Ordering>, EvalError> {
+        Ok(self.partial_cmp(rhs))
+    }
+
+    /// `compare` for `==` and `!=`, for the numbers that can be equal
+    /// without having an order, like complex numbers.
+    fn equality(&self, rhs: &Self) -> Result<Option<Ordering>, EvalError> {
+        self.compare(rhs)
+    }
+
+    // Units are only known to `Quantity`, the other backends refuse them.
+
+    /// The number `self` of `unit`, from a literal like `20 km/h`
+    fn with_unit(self, _unit: &Unit) -> Result<Self, EvalError> {
+        Err(EvalError::NoUnits)
+    }
+
+    /// The same number shown in `unit`, for `to` conversions
+    fn convert(&self, _unit: &Unit) -> Result<Self, EvalError> {
+        Err(EvalError::NoUnits)
+    }
+}
+
+/// Build a number out of a literal from the tokenizer, which may end with a
+/// unit (`20 km/h`), and may be `negated` by the minus in front of it.
+pub fn parse<N: Numeric>(
+    literal: &str,
+    negated: bool,
+    context: &N::Context,
+) -> Result<N, EvalError> {
+    let (number, unit) = units::split(literal);
+    let number = match negated {
+        true => N::negated_literal(number, context)?,
+        false => N::literal(number, context)?,
+    };
+
+    match unit {
+        Some(unit) => number.with_unit(&units::parse(unit)?),
+        None => Ok(number),
+    }
+}
+
+/// Apply one of the arithmetic or bitwise binary operators.
+pub fn apply<N: Numeric>(op: Operator, l: &N, r: &N) -> Result<N, EvalError> {
+    match op {
+        Operator::Add => l.add(r),
+        Operator::Sub => l.sub(r),
+        Operator::Mul => l.mul(r),
+        Operator::Div => l.div(r),
+        Operator::Pow => l.pow(r),
+        Operator::BitAnd => l.bit_and(r),
+        Operator::BitOr => l.bit_or(r),
+        Operator::BitXor => l.bit_xor(r),
+        Operator::Shl => l.shl(r),
+        Operator::Shr => l.shr(r),
+        _ => unreachable!("{} is not an arithmetic operator", op),
+    }
+}
+
+/// Apply one of the arithmetic or bitwise unary operators.
+pub fn unary<N: Numeric>(op: Operator, v: &N) -> Result<N, EvalError> {
+    match op {
+        Operator::Neg => v.neg(),
+        Operator::Pos => Ok(v.clone()),
+        Operator::BitNot => v.bit_not(),
+        Operator::Fact => v.factorial(),
+        Operator::Percent => v.percent(),
+        _ => unreachable!("{} is not an arithmetic operator", op),
+    }
+}
+
+/// Split a `0x`, `0o` or `0b` literal into its radix and its digits.
+pub fn radix(literal: &str) -> Option<(u32, &str)> {
+    let radix = match literal.get(..2)? {
+        "0x" | "0X" => 16,
+        "0o" | "0O" => 8,
+        "0b" | "0B" => 2,
+        _ => return None,
+    };
+
+    Some((radix, &literal[2..]))
+}
+
+/// The integer written in a `0x`, `0o` or `0b` literal.
+fn radix_literal(literal: &str) -> Option<Result<BigInt, EvalError>> {
+    let (radix, digits) = radix(literal)?;
+    Some(BigInt::from_str_radix(digits, radix).map_err(|_| EvalError::NotANumber))
+}
+
+impl Numeric for f64 {
+    type Context = ();
+
+    fn literal(literal: &str, _: &()) -> Result<Self, EvalError> {
+        if let Some(integer) = radix_literal(literal) {
+            return checked(integer?.to_f64(), &[]);
+        }
+
+        // `1e999` is as much an overflow as `1e308 * 10`
+        checked(literal.parse().map_err(|_| EvalError::NotANumber)?, &[])
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        checked(self + rhs, &[*self, *rhs])
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        checked(self - rhs, &[*self, *rhs])
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        checked(self * rhs, &[*self, *rhs])
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        if *rhs == 0.0 {
+            return Err(EvalError::DivisionByZero);
+        }
+
+        checked(self / rhs, &[*self, *rhs])
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        checked(self.powf(*rhs), &[*self, *rhs])
+    }
+
+    fn neg(&self) -> Result<Self, EvalError> {
+        Ok(-self)
+    }
+
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        checked((function.apply)(args), args)
+    }
+
+    fn constant(name: &str) -> Option<Result<Self, EvalError>> {
+        match name {
+            "pi" => Some(Ok(std::f64::consts::PI)),
+            "e" => Some(Ok(std::f64::consts::E)),
+            _ => None,
+        }
+    }
+}
+
+/// An infinite result out of finite operands means we went past `f64::MAX`.
+fn checked(value: f64, operands: &[f64]) -> Result<f64, EvalError> {
+    if value.is_infinite() && operands.iter().all(|v| v.is_finite()) {
+        return Err(EvalError::Overflow);
+    }
+
+    Ok(value)
+}
+
+impl Numeric for Rational {
+    type Context = ();
+
+    fn literal(literal: &str, _: &()) -> Result<Self, EvalError> {
+        if let Some(integer) = radix_literal(literal) {
+            return Ok(Rational::integer(integer?));
+        }
+
+        Rational::parse_decimal(literal, MAX_LITERAL_EXPONENT).ok_or(EvalError::Overflow)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(Rational::add(self, rhs))
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(Rational::sub(self, rhs))
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(Rational::mul(self, rhs))
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Rational::div(self, rhs).ok_or(EvalError::DivisionByZero)
+    }
+
+    /// Integer exponents are exact, fractional ones only when the root is:
+    /// `8 ^ (2/3)` is `4`, `2 ^ 0.5` is an error.
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        if !rhs.is_integer() {
+            let degree = rhs
+                .denominator()
+                .to_i64()
+                .and_then(|d| u32::try_from(d).ok());
+            let root = root(self, degree.ok_or(EvalError::NotExact)?)?;
+            return Numeric::pow(&root, &Rational::integer(rhs.numerator().clone()));
+        }
+
+        let exponent = rhs.numerator().to_i64().ok_or(EvalError::Overflow)?;
+        let bits = self.numerator().bits().max(self.denominator().bits());
+
+        if bits.saturating_mul(exponent.unsigned_abs()) > MAX_BITS {
+            return Err(EvalError::Overflow);
+        }
+
+        self.powi(exponent).ok_or(EvalError::DivisionByZero)
+    }
+
+    fn neg(&self) -> Result<Self, EvalError> {
+        Ok(Rational::neg(self))
+    }
+
+    /// Results that aren't fractions are refused rather than rounded, the
+    /// other functions are only exact where they are rational: `sin(0)`,
+    /// `ln(1)`...
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        let x = &args[0];
+        let (zero, one) = (
+            x.numerator().is_zero(),
+            *x == Rational::integer(BigInt::one()),
+        );
+
+        match function.name {
+            "abs" => Ok(x.abs()),
+            "conj" => Ok(x.clone()),
+            "min" => Ok(args.iter().min().unwrap().clone()),
+            "max" => Ok(args.iter().max().unwrap().clone()),
+            "fact" if x.is_integer() => factorial(x.numerator()).map(Rational::integer),
+            "fact" => Err(EvalError::NotANumber),
+            "sqrt" => root(x, 2),
+            "sin" if zero => Ok(x.clone()),
+            "cos" | "exp" if zero => Ok(Rational::integer(BigInt::one())),
+            "ln" if one => Ok(Rational::integer(BigInt::zero())),
+            "arg" if !x.numerator().is_negative() => Ok(Rational::integer(BigInt::zero())),
+            _ => Err(EvalError::NotExact),
+        }
+    }
+}
+
+impl Numeric for BigInt {
+    type Context = ();
+
+    fn literal(literal: &str, _: &()) -> Result<Self, EvalError> {
+        integer(Rational::literal(literal, &())?)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self + rhs)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self - rhs)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self * rhs)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        match self.divmod(rhs).ok_or(EvalError::DivisionByZero)? {
+            (quotient, remainder) if remainder.is_zero() => Ok(quotient),
+            _ => Err(EvalError::NotAnInteger),
+        }
+    }
+
+    /// Negative exponents only give an integer for `1` and `-1`, so this goes
+    /// through the exact fractions and checks what comes out.
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let base = Rational::integer(self.clone());
+        integer(Numeric::pow(&base, &Rational::integer(rhs.clone()))?)
+    }
+
+    fn neg(&self) -> Result<Self, EvalError> {
+        Ok(-self)
+    }
+
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        let args: Vec<Rational> = args.iter().cloned().map(Rational::integer).collect();
+        let value = Rational::call(function, &args).map_err(|e| match e {
+            EvalError::NotExact => EvalError::NotAnInteger,
+            e => e,
+        })?;
+        integer(value)
+    }
+}
+
+impl Numeric for Word {
+    type Context = WordType;
+
+    /// Decimal literals are numbers that have to fit in the type, the others
+    /// are bit patterns: `0xff` is `255` as an `u8` but `-1` as an `i8`.
+    fn literal(literal: &str, ty: &WordType) -> Result<Self, EvalError> {
+        if let Some(pattern) = radix_literal(literal) {
+            return ty.pattern(pattern?.to_i128().ok_or(EvalError::Overflow)?);
+        }
+
+        let value = BigInt::literal(literal, &())?;
+        ty.word(value.to_i128().ok_or(EvalError::Overflow)?)
+    }
+
+    /// `-128` fits in an `i8`, checking `128` first would say it doesn't.
+    /// Bit patterns are read as they are and then negated.
+    fn negated_literal(literal: &str, ty: &WordType) -> Result<Self, EvalError> {
+        if radix(literal).is_some() {
+            return Word::literal(literal, ty)?.neg();
+        }
+
+        let value = -&BigInt::literal(literal, &())?;
+        ty.word(value.to_i128().ok_or(EvalError::Overflow)?)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::add(self, rhs)
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::sub(self, rhs)
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::mul(self, rhs)
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::div(self, rhs)
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::pow(self, rhs)
+    }
+
+    fn neg(&self) -> Result<Self, EvalError> {
+        Word::neg(self)
+    }
+
+    /// Computed on big integers, the result has to fit in the type.
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        let ty = args[0].ty();
+        let args: Vec<BigInt> = args.iter().map(|a| BigInt::from_i128(a.value())).collect();
+        let value = BigInt::call(function, &args)?;
+
+        ty.word(value.to_i128().ok_or(EvalError::Overflow)?)
+    }
+
+    fn bit_and(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self.and(rhs))
+    }
+
+    fn bit_or(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self.or(rhs))
+    }
+
+    fn bit_xor(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Ok(self.xor(rhs))
+    }
+
+    fn bit_not(&self) -> Result<Self, EvalError> {
+        Ok(self.not())
+    }
+
+    fn shl(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::shl(self, rhs)
+    }
+
+    fn shr(&self, rhs: &Self) -> Result<Self, EvalError> {
+        Word::shr(self, rhs)
+    }
+
+    /// `100` has to be read in the type of `self`.
+    fn percent(&self) -> Result<Self, EvalError> {
+        Word::div(self, &self.ty().word(100)?)
+    }
+}
+
+/// Arithmetic on the values in SI units, the dimensions follow along: lengths
+/// can be added together, a length over a time is a speed.
+impl Numeric for Quantity {
+    type Context = ();
+
+    fn literal(literal: &str, _: &()) -> Result<Self, EvalError> {
+        f64::literal(literal, &()).map(Quantity::number)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        self.same_dimension(rhs.dimension())?;
+        Ok(Quantity::new(
+            self.value().add(&rhs.value())?,
+            self.dimension(),
+        ))
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        self.same_dimension(rhs.dimension())?;
+        Ok(Quantity::new(
+            self.value().sub(&rhs.value())?,
+            self.dimension(),
+        ))
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let dimension = self
+            .dimension()
+            .mul(rhs.dimension())
+            .ok_or(EvalError::Overflow)?;
+        Ok(Quantity::new(self.value().mul(&rhs.value())?, dimension))
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let dimension = self
+            .dimension()
+            .div(rhs.dimension())
+            .ok_or(EvalError::Overflow)?;
+        Ok(Quantity::new(self.value().div(&rhs.value())?, dimension))
+    }
+
+    /// The exponent is a plain number, and has to leave whole powers of the
+    /// units: `(2 m) ^ 2` is fine, `(2 m) ^ 0.5` isn't.
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let exponent = rhs.plain()?;
+        let dimension = self
+            .dimension()
+            .pow(exponent)
+            .ok_or_else(|| EvalError::FractionalPower(self.dimension().to_string()))?;
+
+        Ok(Quantity::new(self.value().pow(&exponent)?, dimension))
+    }
+
+    fn neg(&self) -> Result<Self, EvalError> {
+        Ok(Quantity::new(-self.value(), self.dimension()))
+    }
+
+    /// `abs`, `min` and `max` keep the unit and `sqrt` halves it, the other
+    /// functions only take plain numbers.
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        let dimension = match function.name {
+            "abs" => args[0].dimension(),
+            "min" | "max" => {
+                for arg in &args[1..] {
+                    args[0].same_dimension(arg.dimension())?;
+                }
+
+                args[0].dimension()
+            }
+            "sqrt" => args[0]
+                .dimension()
+                .pow(0.5)
+                .ok_or_else(|| EvalError::FractionalPower(args[0].dimension().to_string()))?,
+            _ => {
+                let args = args
+                    .iter()
+                    .map(Quantity::plain)
+                    .collect::<Result<Vec<_>, _>>()?;
+                return f64::call(function, &args).map(Quantity::number);
+            }
+        };
+
+        let values: Vec<f64> = args.iter().map(Quantity::value).collect();
+        Ok(Quantity::new(f64::call(function, &values)?, dimension))
+    }
+
+    fn compare(&self, rhs: &Self) -> Result<Option<Ordering>, EvalError> {
+        self.same_dimension(rhs.dimension())?;
+        Ok(self.value().partial_cmp(&rhs.value()))
+    }
+
+    fn with_unit(self, unit: &Unit) -> Result<Self, EvalError> {
+        let value = self.plain()?.mul(&unit.factor)?;
+        Ok(Quantity::new(value, unit.dimension))
+    }
+
+    fn convert(&self, unit: &Unit) -> Result<Self, EvalError> {
+        self.to(unit)
+    }
+
+    fn constant(name: &str) -> Option<Result<Self, EvalError>> {
+        f64::constant(name).map(|value| value.map(Quantity::number))
+    }
+}
+
+/// Floats with an imaginary part. The functions that only make sense for real
+/// numbers (`min`, `max`, `fact`) go through `f64`.
+impl Numeric for Complex {
+    type Context = ();
+
+    fn literal(literal: &str, _: &()) -> Result<Self, EvalError> {
+        f64::literal(literal, &()).map(Complex::real)
+    }
+
+    fn add(&self, rhs: &Self) -> Result<Self, EvalError> {
+        complex(
+            Complex::new(self.re + rhs.re, self.im + rhs.im),
+            &[*self, *rhs],
+        )
+    }
+
+    fn sub(&self, rhs: &Self) -> Result<Self, EvalError> {
+        complex(
+            Complex::new(self.re - rhs.re, self.im - rhs.im),
+            &[*self, *rhs],
+        )
+    }
+
+    fn mul(&self, rhs: &Self) -> Result<Self, EvalError> {
+        complex(Complex::mul(self, rhs), &[*self, *rhs])
+    }
+
+    fn div(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let quotient = Complex::div(self, rhs).ok_or(EvalError::DivisionByZero)?;
+        complex(quotient, &[*self, *rhs])
+    }
+
+    fn pow(&self, rhs: &Self) -> Result<Self, EvalError> {
+        let power = Complex::pow(self, rhs).ok_or(EvalError::DivisionByZero)?;
+        complex(power, &[*self, *rhs])
+    }
+
+    /// `0 - z` rather than `-z`, so `-1` is `-1 + 0i` and not `-1 - 0i`
+    /// which is on the other side of the branch cut of `ln` and `sqrt`.
+    fn neg(&self) -> Result<Self, EvalError> {
+        Ok(Complex::new(0.0 - self.re, 0.0 - self.im))
+    }
+
+    fn call(function: &Function, args: &[Self]) -> Result<Self, EvalError> {
+        let z = &args[0];
+        let value = match function.name {
+            "sqrt" => z.sqrt(),
+            "exp" => z.exp(),
+            "ln" => z.ln(),
+            "sin" => z.sin(),
+            "cos" => z.cos(),
+            "abs" => Complex::real(z.norm()),
+            "arg" => Complex::real(z.arg()),
+            "conj" => z.conj(),
+            _ => {
+                let args = args
+                    .iter()
+                    .map(|z| z.to_real())
+                    .collect::<Result<Vec<_>, _>>()?;
+                return f64::call(function, &args).map(Complex::real);
+            }
+        };
+
+        complex(value, args)
+    }
+
+    /// Only real numbers are ordered.
+    fn compare(&self, rhs: &Self) -> Result<Option<Ordering>, EvalError> {
+        Ok(self.to_real()?.partial_cmp(&rhs.to_real()?))
+    }
+
+    fn equality(&self, rhs: &Self) -> Result<Option<Ordering>, EvalError> {
+        Ok(self.partial_cmp(rhs))
+    }
+
+    fn constant(name: &str) -> Option<Result<Self, EvalError>> {
+        match name {
+            "i" => Some(Ok(Complex::I)),
+            _ => f64::constant(name).map(|value| value.map(Complex::real)),
+        }
+    }
+}
+
+/// `checked` for both parts of a complex result.
+fn complex(value: Complex, operands: &[Complex]) -> Result<Complex, EvalError> {
+    let parts: Vec<f64> = operands.iter().flat_map(|z| [z.re, z.im]).collect();
+    checked(value.re, &parts)?;
+    checked(value.im, &parts)?;
+
+    Ok(value)
+}
+
+/// The integer `value` is, if it is one.
+fn integer(value: Rational) -> Result<BigInt, EvalError> {
+    if !value.is_integer() {
+        return Err(EvalError::NotAnInteger);
+    }
+
+    Ok(value.numerator().clone())
+}
+
+/// `n!`, as long as it stays under `MAX_BITS`.
+fn factorial(n: &BigInt) -> Result<BigInt, EvalError> {
+    if n.is_negative() {
+        return Err(EvalError::NotANumber);
+    }
+
+    let n = n.to_i64().ok_or(EvalError::Overflow)?;
+
+    // n! > (n/e)^n, don't even start when that is already too big
+    if n as f64 * (n as f64 / std::f64::consts::E).log2() > MAX_BITS as f64 {
+        return Err(EvalError::Overflow);
+    }
+
+    let mut result = BigInt::one();
+
+    for i in 2..=n {
+        result = &result * &BigInt::from(i);
+
+        if result.bits() > MAX_BITS {
+            return Err(EvalError::Overflow);
+        }
+    }
+
+    Ok(result)
+}
+
+/// The exact `n`th root of `value`. An even root of a negative number isn't
+/// a number, an odd one is the real root: `(-8) ^ (1/3)` is `-2`.
+fn root(value: &Rational, n: u32) -> Result<Rational, EvalError> {
+    let negative = value.numerator().is_negative();
+
+    if negative && n.is_multiple_of(2) {
+        return Err(EvalError::NotANumber);
+    }
+
+    let numerator = value.numerator().abs().exact_root(n);
+    let denominator = value.denominator().exact_root(n);
+
+    match (numerator, denominator) {
+        (Some(numerator), Some(denominator)) => {
+            let root = Rational::new(numerator, denominator).unwrap();
+            Ok(if negative { root.neg() } else { root })
+        }
+        _ => Err(EvalError::NotExact),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Numeric;
+    use crate::bigint::BigInt;
+    use crate::env::Environment;
+    use crate::rational::Rational;
+    use crate::word::{Overflow, Word, WordType};
+    use crate::{Calculator, EvalError, Value};
+
+    fn eval<N: Numeric>(expr: &str) -> Result<N, EvalError> {
+        let statement = Calculator::statement(Calculator::tokenize(expr).unwrap()).unwrap();
+
+        match Calculator::execute(&statement, &mut Environment::new()) {
+            Ok(Value::Number(n)) => Ok(n),
+            Ok(other) => panic!("expected a number, got `{}`", other),
+            Err(e) => Err(e.node),
+        }
+    }
+
+    fn exact(expr: &str) -> Result<Rational, EvalError> {
+        eval(expr)
+    }
+
+    fn word(expr: &str, ty: &str, overflow: Overflow) -> Result<i128, EvalError> {
+        let statement = Calculator::statement(Calculator::tokenize(expr).unwrap()).unwrap();
+        let ty = WordType::named(ty, overflow).unwrap();
+
+        match Calculator::execute_with(&statement, &mut Environment::new(), &ty) {
+            Ok(Value::Number(n)) => Ok(Word::value(&n)),
+            Ok(other) => panic!("expected a number, got `{}`", other),
+            Err(e) => Err(e.node),
+        }
+    }
+
+    fn integer(expr: &str) -> Result<String, EvalError> {
+        eval::<BigInt>(expr).map(|n| n.to_string())
+    }
+
+    fn exact_str(expr: &str) -> String {
+        exact(expr).unwrap().to_string()
+    }
+
+    #[test]
+    fn constants() {
+        use crate::complex::Complex;
+        use crate::units::Quantity;
+        use std::f64::consts::{E, PI};
+
+        assert_eq!(eval::<f64>("2 * pi"), Ok(2.0 * PI));
+        assert_eq!(eval::<f64>("ln(e)"), Ok(1.0));
+        assert_eq!(eval::<Quantity>("pi").map(|q| q.value()), Ok(PI));
+        assert_eq!(eval::<Complex>("e"), Ok(Complex::real(E)));
+
+        for expr in ["pi", "e"] {
+            assert_eq!(exact(expr), Err(EvalError::NotExact), "{}", expr);
+            assert_eq!(eval::<BigInt>(expr), Err(EvalError::NotExact), "{}", expr);
+            assert_eq!(
+                word(expr, "i32", Overflow::Wrap),
+                Err(EvalError::NotExact),
+                "{}",
+                expr
+            );
+        }
+
+        // they are variables, a value bound to them comes first
+        let statement = Calculator::statement(Calculator::tokenize("e + 1").unwrap()).unwrap();
+        let mut env = Environment::new();
+        env.set("e", Value::Number(Rational::integer(BigInt::from(2))));
+        assert_eq!(
+            Calculator::execute(&statement, &mut env),
+            Ok(Value::Number(Rational::integer(BigInt::from(3))))
+        );
+    }
+
+    #[test]
+    fn exact_results() {
+        assert_eq!(exact_str("1/3*3"), "1");
+        assert_eq!(exact_str("0.1 + 0.2"), "3/10");
+        assert_eq!(exact_str("1/3 + 1/6"), "1/2");
+        assert_eq!(exact_str("2 ^ -2"), "1/4");
+        assert_eq!(exact_str("(2/3) ^ 3"), "8/27");
+        assert_eq!(exact_str("-max(1/3, 0.3, abs(-1/2))"), "-1/2");
+        assert_eq!(exact_str("conj(1/3)"), "1/3");
+        assert_eq!(
+            exact_str("12345678901234567890 * 10 + 1"),
+            "123456789012345678901"
+        );
+    }
+
+    #[test]
+    fn only_exact_results() {
+        assert_eq!(exact_str("sqrt(4)"), "2");
+        assert_eq!(exact_str("sqrt(9/4) + 4 ^ 0.5"), "7/2");
+        assert_eq!(exact_str("8 ^ (2/3) + (-8) ^ (1/3)"), "2");
+        assert_eq!(exact_str("(1/16) ^ -0.25"), "2");
+        assert_eq!(exact_str("sin(0) + cos(0) + exp(0) + ln(1) + arg(3)"), "2");
+        assert_eq!(exact("sqrt(-1)"), Err(EvalError::NotANumber));
+        assert_eq!(exact("(-4) ^ 0.5"), Err(EvalError::NotANumber));
+
+        for expr in ["sqrt(2)", "2 ^ 0.5", "sin(1)", "exp(1)", "ln(2)", "arg(-1)"] {
+            assert_eq!(exact(expr), Err(EvalError::NotExact), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn exact_errors() {
+        assert_eq!(exact("1 / (1/3 - 1/3)"), Err(EvalError::DivisionByZero));
+        assert_eq!(exact("0 ^ -1"), Err(EvalError::DivisionByZero));
+        assert_eq!(exact("9 ^ 9 ^ 9"), Err(EvalError::Overflow));
+        assert_eq!(exact("1e100000"), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn decimal_expansion_of_results() {
+        assert_eq!(exact("2/3").unwrap().to_decimal(10), "0.6666666667");
+        assert_eq!(exact("-22/7").unwrap().to_decimal(3), "-3.143");
+    }
+
+    #[test]
+    fn integer_results() {
+        let ok = |s: &str| Ok(s.to_string());
+
+        assert_eq!(
+            integer("2 ^ 200"),
+            ok("1606938044258990275541962092341162602522202993782792835301376")
+        );
+        assert_eq!(integer("fact(30)"), ok("265252859812191058636308480000000"));
+        assert_eq!(integer("fact(25) / fact(23)"), ok("600"));
+        assert_eq!(integer("-8 / 2"), ok("-4"));
+        assert_eq!(integer("1e3 + 1"), ok("1001"));
+        assert_eq!(integer("(-1) ^ -3 + sqrt(16)"), ok("3"));
+        assert_eq!(
+            integer("99999999999999999999 + 1"),
+            ok("100000000000000000000")
+        );
+        assert_eq!(exact_str("fact(20) / 2 ^ 10"), "2375880867360000");
+    }
+
+    #[test]
+    fn integer_errors() {
+        assert_eq!(integer("1.5"), Err(EvalError::NotAnInteger));
+        assert_eq!(integer("-7 / 2"), Err(EvalError::NotAnInteger));
+        assert_eq!(integer("2 ^ -1"), Err(EvalError::NotAnInteger));
+        assert_eq!(integer("sqrt(2)"), Err(EvalError::NotAnInteger));
+        assert_eq!(integer("1 / 0"), Err(EvalError::DivisionByZero));
+        assert_eq!(integer("fact(-1)"), Err(EvalError::NotANumber));
+        assert_eq!(integer("fact(1000000)"), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn fixed_width_results() {
+        let check = |expr| word(expr, "u8", Overflow::Check);
+        let wrap = |expr| word(expr, "u8", Overflow::Wrap);
+
+        assert_eq!(check("0xf0 | 0x0f"), Ok(0xff));
+        assert_eq!(check("0b1100 xor 0b1010"), Ok(0b0110));
+        assert_eq!(check("~0 >> 4"), Ok(0x0f));
+        assert_eq!(check("1 << 7"), Ok(0x80));
+        assert_eq!(check("max(3, 200) + 2"), Ok(202));
+        assert_eq!(check("255 + 1"), Err(EvalError::Overflow));
+        assert_eq!(check("256"), Err(EvalError::Overflow));
+        assert_eq!(check("0x100"), Err(EvalError::Overflow));
+        assert_eq!(check("fact(6)"), Err(EvalError::Overflow));
+        assert_eq!(check("1.5"), Err(EvalError::NotAnInteger));
+        assert_eq!(wrap("255 + 1"), Ok(0));
+        assert_eq!(wrap("256"), Ok(0));
+        assert_eq!(wrap("-1"), Ok(255));
+        assert_eq!(wrap("16 * 17"), Ok(16));
+
+        assert_eq!(word("0x80", "i8", Overflow::Check), Ok(-128));
+        assert_eq!(word("-8 >> 1", "i8", Overflow::Check), Ok(-4));
+        assert_eq!(
+            word("0xffffffffffffffff", "u64", Overflow::Check),
+            Ok(u64::MAX as i128)
+        );
+        assert_eq!(
+            word("18446744073709551615", "u64", Overflow::Check),
+            Ok(u64::MAX as i128)
+        );
+        assert_eq!(word("1 << 64", "i64", Overflow::Check), Ok(0));
+        assert_eq!(word("-1 >> 64", "i64", Overflow::Wrap), Ok(-1));
+        assert_eq!(
+            word("1 << -1", "i64", Overflow::Wrap),
+            Err(EvalError::NegativeShift)
+        );
+
+        // the minimum of a signed type is written with a minus
+        assert_eq!(word("-128", "i8", Overflow::Check), Ok(-128));
+        assert_eq!(word("-(128)", "i8", Overflow::Check), Ok(-128));
+        assert_eq!(
+            word("-129", "i8", Overflow::Check),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(
+            word("-128 - 1", "i8", Overflow::Check),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(
+            word("--128", "i8", Overflow::Check),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(
+            word("-9223372036854775808", "i64", Overflow::Check),
+            Ok(i64::MIN as i128)
+        );
+        assert_eq!(word("-1", "u8", Overflow::Check), Err(EvalError::Overflow));
+    }
+}
@@ -0,0 +1,455 @@
+/*
+# Pratt parser
+
+`Calculator::expression` parses bottom up with the shunting yard. This is the
+same grammar parsed top down, with "top down operator precedence" (Pratt
+parsing): a token either starts an operand (a literal, a variable, a prefix
+operator, a bracket, a call) or continues the expression on its left (a
+binary, postfix or conditional operator), and how far an operator reaches is
+decided by the binding powers of `Operator::precedence`.
+
+Prefix, postfix and conditional forms are each a few lines of their own
+instead of special cases of the operator stack, and malformed input is
+reported where it goes wrong. Unlike the shunting yard, which goes on to
+report every error of the line, the first error stops it.
+
+Both parsers build the same trees, spans included, for every well formed
+expression. The tests check that on random expressions before we switch.
+
+- See: https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html
+*/
+
+use std::iter::Peekable;
+use std::vec;
+
+use crate::ast::{Expr, ExprKind};
+use crate::functions;
+use crate::token::{Associativity, Operator, Span, Spanned, Token};
+use crate::Error;
+
+impl Expr {
+    /// Parse the tokens top down, the counterpart of `Calculator::parse`.
+    pub fn parse(tokens: Vec<Spanned<Token>>) -> Result<Expr, Spanned<Error>> {
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+            last: Span::new(0, 0),
+        };
+
+        let expr = parser.expr(0)?;
+
+        match parser.next() {
+            Some(token) => Err(stray(token)),
+            None => Ok(expr),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Peekable<vec::IntoIter<Spanned<Token>>>,
+    /// The span of the last token read, a missing operand at the end of the
+    /// input is reported there
+    last: Span,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Spanned<Token>> {
+        let token = self.tokens.next()?;
+        self.last = token.span;
+        Some(token)
+    }
+
+    /// An expression, as long as its operators bind at least as tight as
+    /// `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr, Spanned<Error>> {
+        let mut lhs = self.operand()?;
+
+        loop {
+            let op = match self.tokens.peek().map(|t| &t.node) {
+                Some(Token::Op(op)) if !op.is_prefix() => *op,
+                Some(Token::Question) => Operator::Cond,
+                _ => return Ok(lhs),
+            };
+
+            if op.precedence() < min {
+                return Ok(lhs);
+            }
+
+            let token = self.next().unwrap();
+
+            lhs = match op {
+                Operator::Cond => self.conditional(lhs)?,
+                op if op.is_postfix() => {
+                    let span = token.span.to(lhs.span);
+                    let operand = Box::new(lhs);
+                    Expr::new(ExprKind::Unary { op, operand }, span)
+                }
+                op => {
+                    let rhs = self.expr(right(op))?;
+                    let span = lhs.span.to(rhs.span);
+
+                    let kind = ExprKind::Binary {
+                        op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    };
+                    Expr::new(kind, span)
+                }
+            };
+        }
+    }
+
+    /// The start of an expression: everything but a binary, postfix or
+    /// conditional operator.
+    fn operand(&mut self) -> Result<Expr, Spanned<Error>> {
+        let Some(token) = self.next() else {
+            return Err(Spanned::new(Error::MissingOperand, self.last));
+        };
+        let span = token.span;
+
+        match token.node {
+            Token::Number(literal) => Expr::literal(literal, span),
+            Token::Ident(name) => Ok(Expr::new(ExprKind::Variable(name), span)),
+            Token::Op(op) if op.is_prefix() => {
+                let operand = self.expr(op.precedence())?;
+                let span = span.to(operand.span);
+
+                let operand = Box::new(operand);
+                Ok(Expr::new(ExprKind::Unary { op, operand }, span))
+            }
+            // brackets aren't part of the tree
+            Token::Bracket('(') => {
+                let expr = self.expr(0)?;
+                self.close(span)?;
+                Ok(expr)
+            }
+            Token::Func(name) => self.call(name, span),
+            Token::Assign | Token::Convert(_) => Err(stray(token)),
+            _ => Err(Spanned::new(Error::MissingOperand, span)),
+        }
+    }
+
+    /// The rest of `cond ? then : otherwise` once the `?` is read. Anything
+    /// goes in `then`, up to the `:`, and `otherwise` may be another
+    /// conditional as `? :` is right associative.
+    fn conditional(&mut self, cond: Expr) -> Result<Expr, Spanned<Error>> {
+        let question = self.last;
+        let then = self.expr(0)?;
+
+        match self.next() {
+            Some(token) if token.node == Token::Colon => {}
+            _ => return Err(Spanned::new(Error::MissingColon, question)),
+        }
+
+        let otherwise = self.expr(right(Operator::Cond))?;
+        let span = cond.span.to(otherwise.span);
+
+        let kind = ExprKind::Conditional {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        };
+        Ok(Expr::new(kind, span))
+    }
+
+    /// The arguments of a call to `name`, checked against the registry like
+    /// `Calculator::expression` does.
+    fn call(&mut self, name: String, at: Span) -> Result<Expr, Spanned<Error>> {
+        let open = match self.next() {
+            Some(token) if token.node == Token::Bracket('(') => token.span,
+            _ => return Err(Spanned::new(Error::MismatchedParens, at)),
+        };
+
+        let mut args = vec![];
+
+        let close = loop {
+            if args.is_empty() && self.tokens.peek().map(|t| &t.node) == Some(&Token::Bracket(')'))
+            {
+                break self.next().unwrap().span;
+            }
+
+            // an empty argument (`f(1,)`) is a missing operand
+            args.push(self.expr(0)?);
+
+            match self.next() {
+                Some(token) if token.node == Token::Comma => {}
+                Some(token) if token.node == Token::Bracket(')') => break token.span,
+                Some(token) => return Err(stray(token)),
+                None => return Err(Spanned::new(Error::MismatchedParens, open)),
+            }
+        };
+
+        let span = at.to(close);
+
+        let Some(function) = functions::lookup(&name) else {
+            return Err(Spanned::new(Error::UnknownFunction(name), at));
+        };
+
+        if !function.arity.accepts(args.len()) {
+            let error = Error::WrongArity {
+                name,
+                expected: function.arity,
+                found: args.len(),
+            };
+            return Err(Spanned::new(error, span));
+        }
+
+        Ok(Expr::new(ExprKind::Call { name, args }, span))
+    }
+
+    /// The `)` of the bracket opened at `open`.
+    fn close(&mut self, open: Span) -> Result<(), Spanned<Error>> {
+        match self.next() {
+            Some(token) if token.node == Token::Bracket(')') => Ok(()),
+            Some(token) => Err(stray(token)),
+            None => Err(Spanned::new(Error::MismatchedParens, open)),
+        }
+    }
+}
+
+/// The binding power the right operand of `op` needs: a left associative
+/// operator doesn't let another one of the same precedence in.
+fn right(op: Operator) -> u8 {
+    match op.associativity() {
+        Associativity::Left => op.precedence() + 1,
+        Associativity::Right => op.precedence(),
+    }
+}
+
+/// A token found after a complete expression that can't continue it.
+fn stray(token: Spanned<Token>) -> Spanned<Error> {
+    let error = match token.node {
+        Token::Comma => Error::MisplacedComma,
+        Token::Colon => Error::MisplacedColon,
+        Token::Assign => Error::InvalidAssignment,
+        Token::Convert(_) => Error::MisplacedConversion,
+        Token::Bracket(')') => Error::MismatchedParens,
+        _ => Error::MissingOperator,
+    };
+
+    Spanned::new(error, token.span)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::ast::Expr;
+    use crate::bytecode::Program;
+    use crate::diagnostic::Errors;
+    use crate::env::Environment;
+    use crate::token::{Span, Spanned};
+    use crate::{Calculator, Error, Value};
+
+    /// xorshift, good enough to pick random expressions
+    pub(crate) struct Rng(pub(crate) u64);
+
+    impl Rng {
+        pub(crate) fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    const ATOMS: &[&str] = &["0", "1", "2", "3", "0.5", "10", "a", "b", "x"];
+    const BINARY: &[&str] = &[
+        "+", "-", "*", "/", "^", "==", "!=", "<", "<=", ">", ">=", "&&", "||", "&", "|", "xor",
+        "<<", ">>",
+    ];
+    const PREFIX: &[&str] = &["-", "+", "!", "~"];
+    const POSTFIX: &[&str] = &["!", "%"];
+
+    /// A random well formed expression, using every form of the grammar.
+    pub(crate) fn expression(rng: &mut Rng, depth: usize) -> String {
+        if depth == 0 {
+            return rng.pick(ATOMS).to_string();
+        }
+
+        let depth = depth - 1;
+
+        match rng.below(9) {
+            0 => rng.pick(ATOMS).to_string(),
+            1 | 2 => {
+                let lhs = expression(rng, depth);
+                let op = rng.pick(BINARY);
+                format!("{} {} {}", lhs, op, expression(rng, depth))
+            }
+            3 => {
+                let op = rng.pick(PREFIX);
+                format!("{}{}", op, expression(rng, depth))
+            }
+            4 => {
+                let operand = expression(rng, depth);
+                format!("{}{}", operand, rng.pick(POSTFIX))
+            }
+            5 => format!("({})", expression(rng, depth)),
+            6 => {
+                let cond = expression(rng, depth);
+                let then = expression(rng, depth);
+                format!("{} ? {} : {}", cond, then, expression(rng, depth))
+            }
+            7 => {
+                let args: Vec<_> = (0..1 + 2 * rng.below(2))
+                    .map(|_| expression(rng, depth))
+                    .collect();
+
+                match args.len() {
+                    1 => format!("sin({})", args[0]),
+                    _ => format!("max({})", args.join(", ")),
+                }
+            }
+            // implicit multiplication
+            _ => {
+                let lhs = expression(rng, depth);
+                format!("{} ({})", lhs, expression(rng, depth))
+            }
+        }
+    }
+
+    fn env() -> Environment<Value> {
+        let mut env = Environment::new();
+        env.set("a", Value::Number(2.0));
+        env.set("b", Value::Number(-3.0));
+        env.set("x", Value::Number(0.5));
+        env
+    }
+
+    /// Both parsers, on the same tokens
+    fn parse(expr: &str) -> (Result<Expr, Errors>, Result<Expr, Spanned<Error>>) {
+        let tokens = Calculator::tokenize(expr).unwrap();
+        (Calculator::parse(tokens.clone()), Expr::parse(tokens))
+    }
+
+    #[test]
+    fn same_trees() {
+        for expr in [
+            "1 + 2 * 3",
+            "8 - 3 - 2",
+            "2 ^ 3 ^ 2",
+            "-2 ^ 2",
+            "2 ^ -3 ^ 2",
+            "a ^ -b * x",
+            "-3!",
+            "2 ^ 3! %",
+            "!a == b",
+            "a || b ? x : a && b",
+            "a ? b : x ? 1 : 2",
+            "a ? b ? 1 : 2 : 3",
+            "(a + b)(a - b)",
+            "2 sqrt(x) max(1, -a, b!)",
+            "abs(-x)%",
+        ] {
+            match parse(expr) {
+                (Ok(shunting), Ok(pratt)) => assert_eq!(pratt, shunting, "{}", expr),
+                (shunting, pratt) => panic!("{}: {:?} {:?}", expr, shunting, pratt),
+            }
+        }
+    }
+
+    #[test]
+    fn random_expressions_agree() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let env = env();
+
+        for _ in 0..5000 {
+            let depth = rng.below(6);
+            let expr = expression(&mut rng, depth);
+
+            let tokens = Calculator::tokenize(&expr).unwrap();
+            let rpn = Calculator::expression(tokens.clone()).unwrap();
+            let shunting = Expr::from_rpn(rpn.clone()).unwrap();
+            let pratt = Expr::parse(tokens).unwrap();
+            assert_eq!(pratt, shunting, "{}", expr);
+
+            // the VM on the shunting yard's RPN and on the Pratt tree
+            let expected = Calculator::evaluate(&rpn, &env).map_err(|e| e.node);
+            let found = Program::compile(&pratt.rpn(), &())
+                .and_then(|program| program.run_in(&env))
+                .map_err(|e| e.node);
+            assert_eq!(
+                format!("{:?}", found),
+                format!("{:?}", expected),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    /// Both parsers accept the same expressions and read them the same way.
+    /// Only the errors differ, the Pratt parser stops at the first one.
+    #[test]
+    fn random_tokens_agree() {
+        const TOKENS: &[&str] = &[
+            "1", "x", "(", ")", "+", "-", "*", "^", "!", "%", "?", ":", ",", "max(", "==",
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut accepted = 0;
+
+        for _ in 0..20_000 {
+            let len = 1 + rng.below(8);
+            let expr: Vec<_> = (0..len).map(|_| rng.pick(TOKENS)).collect();
+            let expr = expr.join(" ");
+
+            let Ok(tokens) = Calculator::tokenize(&expr) else {
+                continue;
+            };
+
+            match (Expr::parse(tokens.clone()), Calculator::parse(tokens)) {
+                (Ok(pratt), Ok(shunting)) => {
+                    assert_eq!(pratt, shunting, "{}", expr);
+                    accepted += 1;
+                }
+                (Err(_), Err(_)) => {}
+                (pratt, shunting) => panic!("{}: {:?} {:?}", expr, pratt, shunting),
+            }
+        }
+
+        assert!(accepted > 100, "only {} accepted", accepted);
+    }
+
+    #[test]
+    fn reports_where_it_goes_wrong() {
+        let error = |expr| {
+            let error = parse(expr).1.unwrap_err();
+            (error.node.to_string(), error.span)
+        };
+
+        assert_eq!(
+            error("1 2 +"),
+            ("expected an operator before this".into(), Span::new(2, 3))
+        );
+        assert_eq!(error("* 1 2"), ("missing operand".into(), Span::new(0, 1)));
+
+        assert_eq!(error("2 +"), ("missing operand".into(), Span::new(2, 3)));
+        assert_eq!(
+            error("max(1,)"),
+            ("missing operand".into(), Span::new(6, 7))
+        );
+        assert_eq!(
+            error("1 ? 2"),
+            ("expected a `:` for this `?`".into(), Span::new(2, 3))
+        );
+        assert_eq!(
+            error("1 : 2"),
+            ("`:` without a `?` before it".into(), Span::new(2, 3))
+        );
+        assert_eq!(
+            error("(1, 2)"),
+            ("`,` outside of a function call".into(), Span::new(2, 3))
+        );
+        assert_eq!(
+            error("sqrt(1, 2)"),
+            (
+                "`sqrt` takes 1 argument but 2 were given".into(),
+                Span::new(0, 10)
+            )
+        );
+        assert_eq!(
+            error("nope(1)"),
+            ("unknown function `nope`".into(), Span::new(0, 4))
+        );
+    }
+}
@@ -0,0 +1,291 @@
+/*
+# Exact rational numbers
+
+A fraction of two `BigInt`s, always kept in lowest terms with a positive
+denominator. That makes equal numbers have equal representations, so the
+derived `PartialEq` is the mathematical one.
+
+Every decimal literal is a rational number (`2.5e-3` is `25 / 10^4`), so
+parsing them is exact too.
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::bigint::BigInt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    numerator: BigInt,
+    denominator: BigInt,
+}
+
+impl Rational {
+    /// Returns `None` when `denominator` is zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Self> {
+        if denominator.is_zero() {
+            return None;
+        }
+
+        let gcd = numerator.gcd(&denominator);
+        let (mut numerator, _) = numerator.divmod(&gcd).unwrap();
+        let (mut denominator, _) = denominator.divmod(&gcd).unwrap();
+
+        if denominator.is_negative() {
+            numerator = -&numerator;
+            denominator = -&denominator;
+        }
+
+        Some(Rational {
+            numerator,
+            denominator,
+        })
+    }
+
+    pub fn integer(value: BigInt) -> Self {
+        Rational {
+            numerator: value,
+            denominator: BigInt::one(),
+        }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denominator == BigInt::one()
+    }
+
+    /// Parse a decimal literal such as `12`, `.5`, `3.14` or `2.5E+10`.
+    ///
+    /// Exponents bigger than `max_exponent` are refused, as `1e999999999`
+    /// would need a gigantic power of ten.
+    pub fn parse_decimal(literal: &str, max_exponent: u32) -> Option<Self> {
+        let (mantissa, exponent) = match literal.find(['e', 'E']) {
+            Some(i) => (&literal[..i], literal[i + 1..].parse::<i64>().ok()?),
+            None => (literal, 0),
+        };
+
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits: BigInt = format!("0{}{}", integer, fraction).parse().ok()?;
+
+        // the value is digits * 10^(exponent - fraction digits)
+        let scale = exponent - fraction.len() as i64;
+        if scale.unsigned_abs() > max_exponent as u64 {
+            return None;
+        }
+
+        let power = BigInt::from(10).pow(scale.unsigned_abs() as u32);
+
+        if scale >= 0 {
+            Some(Rational::integer(&digits * &power))
+        } else {
+            Rational::new(digits, power)
+        }
+    }
+
+    /// The exact value of a finite `f64`.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let (mantissa, exponent) = BigInt::from_f64_parts(value)?;
+
+        if exponent >= 0 {
+            Some(Rational::integer(mantissa.shl(exponent as u64)))
+        } else {
+            Rational::new(mantissa, BigInt::one().shl(exponent.unsigned_abs() as u64))
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // scale the division so the quotient keeps 64 significant bits
+        let shift = self.numerator.bits() as i64 - self.denominator.bits() as i64 - 64;
+
+        let (quotient, _) = if shift >= 0 {
+            self.numerator.divmod(&self.denominator.shl(shift as u64))
+        } else {
+            self.numerator
+                .shl(shift.unsigned_abs())
+                .divmod(&self.denominator)
+        }
+        .unwrap();
+
+        quotient.to_f64() * 2f64.powi(shift.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational {
+            numerator: self.numerator.abs(),
+            denominator: self.denominator.clone(),
+        }
+    }
+
+    pub fn neg(&self) -> Rational {
+        Rational {
+            numerator: -&self.numerator,
+            denominator: self.denominator.clone(),
+        }
+    }
+
+    pub fn add(&self, other: &Rational) -> Rational {
+        let numerator =
+            &(&self.numerator * &other.denominator) + &(&other.numerator * &self.denominator);
+        Rational::new(numerator, &self.denominator * &other.denominator).unwrap()
+    }
+
+    pub fn sub(&self, other: &Rational) -> Rational {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Rational) -> Rational {
+        Rational::new(
+            &self.numerator * &other.numerator,
+            &self.denominator * &other.denominator,
+        )
+        .unwrap()
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn div(&self, other: &Rational) -> Option<Rational> {
+        Rational::new(
+            &self.numerator * &other.denominator,
+            &self.denominator * &other.numerator,
+        )
+    }
+
+    /// Raise to an integer power. Returns `None` for `0` to a negative power.
+    pub fn powi(&self, exponent: i64) -> Option<Rational> {
+        let e = u32::try_from(exponent.unsigned_abs()).ok()?;
+        let numerator = self.numerator.pow(e);
+        let denominator = self.denominator.pow(e);
+
+        if exponent >= 0 {
+            Rational::new(numerator, denominator)
+        } else {
+            Rational::new(denominator, numerator)
+        }
+    }
+
+    /// The decimal expansion rounded (half away from zero) to `digits`
+    /// places after the point: `1/3` with 4 digits is `0.3333`.
+    pub fn to_decimal(&self, digits: usize) -> String {
+        let scale = BigInt::from(10).pow(digits as u32);
+
+        // round(|n| * 10^digits / d) = (2 * |n| * 10^digits + d) / (2 * d)
+        let two = BigInt::from(2);
+        let scaled = &(&(&self.numerator.abs() * &scale) * &two) + &self.denominator;
+        let (rounded, _) = scaled.divmod(&(&self.denominator * &two)).unwrap();
+
+        let (integer, fraction) = rounded.divmod(&scale).unwrap();
+        let sign = if self.numerator.is_negative() && !rounded.is_zero() {
+            "-"
+        } else {
+            ""
+        };
+
+        if digits == 0 {
+            return format!("{}{}", sign, integer);
+        }
+
+        format!(
+            "{}{}.{:0>width$}",
+            sign,
+            integer,
+            fraction.to_string(),
+            width = digits
+        )
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // denominators are positive, so cross multiplying keeps the order
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Prints `numerator/denominator`, or just the numerator for integers.
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rational;
+    use crate::bigint::BigInt;
+
+    fn ratio(n: i64, d: i64) -> Rational {
+        Rational::new(BigInt::from(n), BigInt::from(d)).unwrap()
+    }
+
+    #[test]
+    fn lowest_terms() {
+        assert_eq!(ratio(6, -8).to_string(), "-3/4");
+        assert_eq!(ratio(10, 5).to_string(), "2");
+        assert_eq!(ratio(0, -3), ratio(0, 1));
+        assert!(Rational::new(BigInt::one(), BigInt::zero()).is_none());
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        let third = ratio(1, 3);
+
+        assert_eq!(third.mul(&ratio(3, 1)), ratio(1, 1));
+        assert_eq!(ratio(1, 10).add(&ratio(2, 10)), ratio(3, 10));
+        assert_eq!(third.sub(&ratio(1, 2)), ratio(-1, 6));
+        assert_eq!(third.div(&ratio(2, 3)), Some(ratio(1, 2)));
+        assert_eq!(third.div(&ratio(0, 1)), None);
+        assert_eq!(ratio(2, 3).powi(-3), Some(ratio(27, 8)));
+        assert!(ratio(1, 3) > ratio(-1, 2) && ratio(1, 3) < ratio(1, 2));
+    }
+
+    #[test]
+    fn parses_decimals() {
+        let parse = |s| Rational::parse_decimal(s, 1000).unwrap();
+
+        assert_eq!(parse("0.1"), ratio(1, 10));
+        assert_eq!(parse(".5"), ratio(1, 2));
+        assert_eq!(parse("2.5E+3"), ratio(2500, 1));
+        assert_eq!(parse("125e-3"), ratio(1, 8));
+        assert_eq!(
+            parse("12345678901234567890").to_string(),
+            "12345678901234567890"
+        );
+        assert!(Rational::parse_decimal("1e5000", 1000).is_none());
+    }
+
+    #[test]
+    fn decimal_expansion() {
+        assert_eq!(ratio(1, 3).to_decimal(5), "0.33333");
+        assert_eq!(ratio(2, 3).to_decimal(5), "0.66667");
+        assert_eq!(ratio(-1, 8).to_decimal(2), "-0.13");
+        assert_eq!(ratio(-1, 1000).to_decimal(2), "0.00");
+        assert_eq!(ratio(199, 100).to_decimal(1), "2.0");
+        assert_eq!(ratio(7, 2).to_decimal(0), "4");
+        assert_eq!(ratio(1, 40).to_decimal(4), "0.0250");
+    }
+
+    #[test]
+    fn float_conversions() {
+        assert_eq!(Rational::from_f64(0.375), Some(ratio(3, 8)));
+        assert_eq!(Rational::from_f64(-2.0), Some(ratio(-2, 1)));
+        assert_eq!(Rational::from_f64(f64::NAN), None);
+        assert_eq!(ratio(1, 3).to_f64(), 1.0 / 3.0);
+        assert_eq!(ratio(-22, 7).to_f64(), -22.0 / 7.0);
+    }
+}
@@ -0,0 +1,1013 @@
+/*
+# REPL
+
+An interactive session on top of `Calculator`. Every line is a statement
+(`let x = 2`, `x ^ 2 + 1`) and the variables live for the whole session.
+Lines starting with `:` are commands, see `HELP`.
+
+Errors are rendered against the line that caused them, all the mistakes of
+a line at once, and never end the session.
+
+The session computes with floats by default, `:mode exact` switches it to
+exact fractions, `:mode integer` to big integers, `:mode units` to numbers
+with units (see `units.rs`), `:mode complex` to complex numbers (see
+`complex.rs`) and `:mode u8` (or any other width) to the fixed width
+integers of programmer mode (see `numeric.rs`). The variables are carried
+over when they can be represented in the new mode.
+*/
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::ast::Expr;
+use crate::bigint::BigInt;
+use crate::bytecode::{Program, Vm};
+use crate::complex::{Complex, Form};
+use crate::env::Environment;
+use crate::numeric::Numeric;
+use crate::rational::Rational;
+use crate::script::Script;
+use crate::simplify::Inputs;
+use crate::solver::{self, Problem, Tolerance};
+use crate::trace::Steps;
+use crate::units::Quantity;
+use crate::word::{Overflow, Word, WordType};
+use crate::{Calculator, Statement, Value};
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+<expression>        evaluate an expression, e.g. `2 * (x + 1)`
+let <name> = <expr> bind a variable for the rest of the session
+<expr> to <unit>    show the result in a unit, e.g. `60 mph to km/h`
+                    (units mode only)
+solve(<lhs> = <rhs>, <var>[, <guess>])
+                    find the value of a variable that makes both sides
+                    equal, and bind it (float mode only)
+root(<expr>, <var>, <a>, <b>)
+                    find where the expression is 0 between a and b by
+                    bisection, and bind the variable (float mode only)
+:run <file>         run a script: one statement per line, with `if`,
+                    `else`, `while` and `fn` blocks closed by `end`
+:rpn <expression>   show the expression in reverse polish notation
+:ast <expression>   show the syntax tree of the expression
+:pratt <expression> show the syntax tree the Pratt parser builds
+:bytecode <expr>    show the bytecode the expression compiles to
+:trace <expression> show every step of tokenizing, parsing and running
+                    the expression, in the current mode
+:infix <expression> show the expression with only the brackets it needs
+:latex <expression> show the expression as LaTeX
+:mathml <expr>      show the expression as MathML
+:simplify <expr>    show the expression simplified, as a syntax tree
+:derive <var> <expr> show the derivative of the expression with respect
+                    to a variable, as a syntax tree
+:vars               list the variables
+:mode [<mode>]      show or change how numbers are computed: float,
+                    exact (fractions), integer, units (numbers like
+                    `3 m` or `9.81 m/s^2`), complex (numbers like
+                    `3 + 4i`), or a fixed width integer type (i8, i16,
+                    i32, i64, u8, u16, u32, u64)
+:overflow [<mode>]  show or change what fixed width integers do when a
+                    result doesn't fit: wrap or check
+:form [<form>]      show or change how complex results are shown:
+                    rectangular (`3 + 4i`) or polar (`5 ∠ 0.927...`)
+:digits <n>         decimals shown for exact results that aren't integers
+:help               show this message
+:quit               leave (so does ctrl-d)";
+
+/// The variables of the session, in the numeric mode we are in
+enum Mode {
+    Float(Environment<Value<f64>>),
+    Exact(Environment<Value<Rational>>),
+    Integer(Environment<Value<BigInt>>),
+    Units(Environment<Value<Quantity>>),
+    Complex(Environment<Value<Complex>>),
+    Word(Environment<Value<Word>>, WordType),
+}
+
+impl Mode {
+    /// An empty mode called `name`, if there is one.
+    fn named(name: &str, overflow: Overflow) -> Option<Mode> {
+        Some(match name {
+            "float" => Mode::Float(Environment::new()),
+            "exact" => Mode::Exact(Environment::new()),
+            "integer" => Mode::Integer(Environment::new()),
+            "units" => Mode::Units(Environment::new()),
+            "complex" => Mode::Complex(Environment::new()),
+            _ => Mode::Word(Environment::new(), WordType::named(name, overflow)?),
+        })
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Mode::Float(_) => "float".to_string(),
+            Mode::Exact(_) => "exact".to_string(),
+            Mode::Integer(_) => "integer".to_string(),
+            Mode::Units(_) => "units".to_string(),
+            Mode::Complex(_) => "complex".to_string(),
+            Mode::Word(_, ty) => ty.to_string(),
+        }
+    }
+
+    /// The variables as exact fractions, the common ground of all the modes
+    fn exact_vars(&self) -> Vec<(String, Value<Rational>)> {
+        fn collect<V: Clone>(
+            env: &Environment<V>,
+            convert: impl Fn(V) -> Option<Value<Rational>>,
+        ) -> Vec<(String, Value<Rational>)> {
+            env.iter()
+                .filter_map(|(name, v)| Some((name.to_string(), convert(v.clone())?)))
+                .collect()
+        }
+
+        match self {
+            // infinities and NaN have no exact value, they are dropped
+            Mode::Float(env) => collect(env, |v| v.convert(Rational::from_f64)),
+            Mode::Exact(env) => collect(env, Some),
+            Mode::Integer(env) => collect(env, |v| v.convert(|n| Some(Rational::integer(n)))),
+            // only plain numbers make sense in the other modes
+            Mode::Units(env) => collect(env, |v| {
+                v.convert(|q| q.plain().ok().and_then(Rational::from_f64))
+            }),
+            Mode::Complex(env) => collect(env, |v| {
+                v.convert(|z| z.to_real().ok().and_then(Rational::from_f64))
+            }),
+            Mode::Word(env, _) => collect(env, |v| {
+                v.convert(|n| Some(Rational::integer(BigInt::from_i128(n.value()))))
+            }),
+        }
+    }
+
+    /// Bind the `vars` coming from another mode. Those that can't be
+    /// represented in this one (fractions in the integer modes, numbers too
+    /// big for the width) are dropped rather than rounded.
+    fn load(&mut self, vars: Vec<(String, Value<Rational>)>) {
+        let integer = |n: Rational| n.is_integer().then(|| n.numerator().clone());
+
+        for (name, value) in vars {
+            match self {
+                Mode::Float(env) => env.set(name, value.convert(|n| Some(n.to_f64())).unwrap()),
+                Mode::Exact(env) => env.set(name, value),
+                Mode::Units(env) => {
+                    let number = |n: Rational| Some(Quantity::number(n.to_f64()));
+                    env.set(name, value.convert(number).unwrap())
+                }
+                Mode::Complex(env) => {
+                    let number = |n: Rational| Some(Complex::real(n.to_f64()));
+                    env.set(name, value.convert(number).unwrap())
+                }
+                Mode::Integer(env) => {
+                    if let Some(value) = value.convert(integer) {
+                        env.set(name, value);
+                    }
+                }
+                Mode::Word(env, ty) => {
+                    let word = value.convert(|n| ty.word(integer(n)?.to_i128()?).ok());
+
+                    if let Some(value) = word {
+                        env.set(name, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What a line that isn't a command asks for
+enum Line {
+    Solve(Problem),
+    Statement(Statement),
+}
+
+pub struct Repl {
+    mode: Mode,
+    /// How many decimals to print next to an exact fraction
+    digits: usize,
+    /// What the fixed width integers do on overflow
+    overflow: Overflow,
+    /// How complex results are shown
+    form: Form,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl {
+            mode: Mode::Float(Environment::new()),
+            digits: 10,
+            overflow: Overflow::Check,
+            form: Form::Rectangular,
+        }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::default()
+    }
+
+    /// Read lines from `input` until it runs out or we get `:quit`. The line
+    /// editing itself (backspace, moving around) is left to the terminal.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+
+            if matches!(line.trim(), ":quit" | ":q") {
+                break;
+            }
+
+            if let Some(reply) = self.line(&line) {
+                writeln!(output, "{}", reply)?;
+            }
+
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a single line, returning what should be printed (if anything).
+    pub fn line(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+
+        let reply = match line.split_once(' ').unwrap_or((line, "")) {
+            ("", _) => return None,
+            (":help", _) => HELP.to_string(),
+            (":vars", _) => self.vars(),
+            (":mode", mode) => self.mode(mode.trim()),
+            (":overflow", overflow) => self.overflow(overflow.trim()),
+            (":form", form) => self.form(form.trim()),
+            (":digits", digits) => match digits.trim().parse() {
+                Ok(digits) => {
+                    self.digits = digits;
+                    format!("showing {} decimals", digits)
+                }
+                Err(_) => "usage: `:digits <n>`".to_string(),
+            },
+            (":rpn", expr) => Self::rpn(expr.trim()),
+            (":ast", expr) => Self::ast(expr.trim()),
+            (":pratt", expr) => Self::pratt(expr.trim()),
+            (":bytecode", expr) => Self::bytecode(expr.trim()),
+            (":trace", expr) => self.trace(expr.trim()),
+            (":run", path) => return self.run_command(path.trim()),
+            (":infix", expr) => Self::notation(expr.trim(), |ast| ast.infix().to_string()),
+            (":latex", expr) => Self::notation(expr.trim(), |ast| ast.latex().to_string()),
+            (":mathml", expr) => Self::notation(expr.trim(), |ast| ast.mathml().to_string()),
+            (":simplify", expr) => Self::simplify(expr.trim()),
+            (":derive", args) => match args.trim().split_once(' ') {
+                Some((var, expr)) => Self::derive(var, expr.trim()),
+                None => "usage: `:derive <variable> <expression>`".to_string(),
+            },
+            (command, _) if command.starts_with(':') => {
+                format!("unknown command `{}`, try `:help`", command)
+            }
+            _ => self.statement(line),
+        };
+
+        Some(reply)
+    }
+
+    fn statement(&mut self, line: &str) -> String {
+        let parsed = Calculator::read(line, |tokens| match Problem::parse(&tokens) {
+            Some(problem) => problem.map(Line::Solve),
+            None => Calculator::statement(tokens).map(Line::Statement),
+        });
+
+        let statement = match parsed {
+            Ok(Line::Statement(statement)) => statement,
+            Ok(Line::Solve(problem)) => return self.solve(&problem, line),
+            Err(e) => return e.render(line),
+        };
+
+        let result = match &mut self.mode {
+            Mode::Float(env) => Calculator::execute(&statement, env).map(|v| v.to_string()),
+            Mode::Exact(env) => Calculator::execute(&statement, env).map(|v| match v {
+                Value::Number(n) => Self::exact(&n, self.digits),
+                other => other.to_string(),
+            }),
+            Mode::Integer(env) => Calculator::execute(&statement, env).map(|v| v.to_string()),
+            Mode::Units(env) => Calculator::execute(&statement, env).map(|v| v.to_string()),
+            Mode::Complex(env) => Calculator::execute(&statement, env).map(|v| match v {
+                Value::Number(z) => Self::complex(&z, self.form),
+                other => other.to_string(),
+            }),
+            Mode::Word(env, ty) => Calculator::execute_with(&statement, env, ty).map(|v| match v {
+                Value::Number(n) => Self::bases(&n),
+                other => other.to_string(),
+            }),
+        };
+
+        result.unwrap_or_else(|e| e.render(line))
+    }
+
+    /// Solve for the variable and bind it, like a `let` would.
+    fn solve(&mut self, problem: &Problem, line: &str) -> String {
+        let Mode::Float(env) = &mut self.mode else {
+            return "solving needs float mode, try `:mode float`".to_string();
+        };
+
+        match problem.solve(env, &Tolerance::default()) {
+            Ok(solution) => {
+                env.set(problem.var.as_str(), Value::Number(solution.root));
+                format!(
+                    "{} = {}  ({}, {})",
+                    problem.var,
+                    solver::show(solution.root),
+                    solution.method,
+                    solver::count(solution.iterations)
+                )
+            }
+            Err(e) => e.render(line),
+        }
+    }
+
+    /// Exact results print as a fraction followed by its decimal expansion
+    /// with that many `digits`.
+    fn exact(value: &Rational, digits: usize) -> String {
+        if value.is_integer() {
+            return value.to_string();
+        }
+
+        format!("{} ≈ {}", value, value.to_decimal(digits))
+    }
+
+    /// Complex results print in the chosen `form`.
+    fn complex(value: &Complex, form: Form) -> String {
+        match form {
+            Form::Rectangular => value.to_string(),
+            Form::Polar => value.polar().to_string(),
+        }
+    }
+
+    /// `:run`, what the script printed followed by what went wrong.
+    fn run_command(&mut self, path: &str) -> Option<String> {
+        let (mut lines, error) = self.run_script(path);
+        lines.extend(error);
+
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Run the script in `path` in the current mode, with the variables of
+    /// the session at its top level. Returns what the script printed, and
+    /// what went wrong if anything did: the file can't be read, a line is
+    /// wrong or the script failed while running.
+    pub fn run_script(&mut self, path: &str) -> (Vec<String>, Option<String>) {
+        fn run<N: Numeric>(
+            source: &str,
+            env: &mut Environment<Value<N>>,
+            context: &N::Context,
+            show: impl Fn(Value<N>) -> String,
+        ) -> (Vec<String>, Option<String>) {
+            let script = match Script::parse(source, context) {
+                Ok(script) => script,
+                Err(e) => return (vec![], Some(e.render(source))),
+            };
+
+            let mut lines = vec![];
+            let result = script.run(env, |value| lines.push(show(value)));
+            (lines, result.err().map(|e| e.render(source)))
+        }
+
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return (vec![], Some(format!("can't read `{}`: {}", path, e))),
+        };
+
+        let (digits, form) = (self.digits, self.form);
+
+        match &mut self.mode {
+            Mode::Float(env) => run(&source, env, &(), |v| v.to_string()),
+            Mode::Exact(env) => run(&source, env, &(), |v| match v {
+                Value::Number(n) => Self::exact(&n, digits),
+                other => other.to_string(),
+            }),
+            Mode::Integer(env) => run(&source, env, &(), |v| v.to_string()),
+            Mode::Units(env) => run(&source, env, &(), |v| v.to_string()),
+            Mode::Complex(env) => run(&source, env, &(), |v| match v {
+                Value::Number(z) => Self::complex(&z, form),
+                other => other.to_string(),
+            }),
+            Mode::Word(env, ty) => run(&source, env, ty, |v| match v {
+                Value::Number(n) => Self::bases(&n),
+                other => other.to_string(),
+            }),
+        }
+    }
+
+    /// Fixed width integers print in decimal, hexadecimal, octal and binary.
+    /// The last three show the bits, so an `i8` `-1` is `0xff`.
+    fn bases(value: &Word) -> String {
+        let bits = value.pattern();
+        format!("{}  0x{:x}  0o{:o}  0b{:b}", value, bits, bits, bits)
+    }
+
+    fn mode(&mut self, mode: &str) -> String {
+        if mode.is_empty() {
+            return format!("computing in {} mode", self.mode.name());
+        }
+
+        if mode == self.mode.name() {
+            return format!("already in {} mode", mode);
+        }
+
+        let Some(mut target) = Mode::named(mode, self.overflow) else {
+            return "usage: `:mode <float|exact|integer|units|complex|i8..i64|u8..u64>`"
+                .to_string();
+        };
+
+        target.load(self.mode.exact_vars());
+        self.mode = target;
+
+        format!("switched to {} mode", mode)
+    }
+
+    fn overflow(&mut self, overflow: &str) -> String {
+        self.overflow = match overflow {
+            "" => return format!("overflow mode: {}", self.overflow),
+            "wrap" => Overflow::Wrap,
+            "check" => Overflow::Check,
+            _ => return "usage: `:overflow wrap` or `:overflow check`".to_string(),
+        };
+
+        // the variables of programmer mode carry their type, overflow included
+        if let Mode::Word(_, ty) = &self.mode {
+            let ty = WordType {
+                overflow: self.overflow,
+                ..*ty
+            };
+
+            let mut target = Mode::Word(Environment::new(), ty);
+            target.load(self.mode.exact_vars());
+            self.mode = target;
+        }
+
+        format!("switched to overflow mode {}", self.overflow)
+    }
+
+    fn form(&mut self, form: &str) -> String {
+        self.form = match form {
+            "" => return format!("complex form: {}", self.form),
+            "rectangular" => Form::Rectangular,
+            "polar" => Form::Polar,
+            _ => return "usage: `:form rectangular` or `:form polar`".to_string(),
+        };
+
+        format!("showing complex results in {} form", self.form)
+    }
+
+    fn vars(&self) -> String {
+        match &self.mode {
+            Mode::Float(env) => Self::list(env),
+            Mode::Exact(env) => Self::list(env),
+            Mode::Integer(env) => Self::list(env),
+            Mode::Units(env) => Self::list(env),
+            Mode::Complex(env) => Self::list(env),
+            Mode::Word(env, _) => Self::list(env),
+        }
+    }
+
+    fn list<V: Clone + fmt::Display>(env: &Environment<V>) -> String {
+        let mut vars: Vec<_> = env.iter().collect();
+
+        if vars.is_empty() {
+            return "no variables, bind one with `let <name> = <expression>`".to_string();
+        }
+
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars.iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn rpn(expr: &str) -> String {
+        match Calculator::read(expr, Calculator::expression) {
+            Ok(rpn) => rpn
+                .iter()
+                .map(|t| t.node.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    fn ast(expr: &str) -> String {
+        match Calculator::read(expr, Calculator::parse) {
+            Ok(ast) => ast.to_string(),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    /// The tree of the Pratt parser, to compare with `:ast` (see `pratt.rs`).
+    fn pratt(expr: &str) -> String {
+        match Calculator::read(expr, |tokens| Ok(Expr::parse(tokens)?)) {
+            Ok(ast) => ast.to_string(),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    /// The expression written out again, see `notation.rs`.
+    fn notation(expr: &str, print: fn(&Expr) -> String) -> String {
+        match Calculator::read(expr, Calculator::parse) {
+            Ok(ast) => print(&ast),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    /// Variables may hold anything here, a session can hold a NaN.
+    fn simplify(expr: &str) -> String {
+        match Calculator::read(expr, Calculator::parse) {
+            Ok(ast) => ast.simplify(Inputs::Any).to_string(),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    fn derive(var: &str, expr: &str) -> String {
+        match Calculator::read(expr, Calculator::parse).and_then(|ast| Ok(ast.derivative(var)?)) {
+            Ok(derivative) => derivative.to_string(),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    /// The listing of the program, compiled for floats, followed by the
+    /// variables it reads in slot order.
+    fn bytecode(expr: &str) -> String {
+        let rpn = match Calculator::read(expr, Calculator::expression) {
+            Ok(rpn) => rpn,
+            Err(e) => return e.render(expr),
+        };
+
+        match Program::<f64>::compile(&rpn, &()) {
+            Ok(program) if program.slots().is_empty() => program.to_string(),
+            Ok(program) => format!("{}\nslots: {}", program, program.slots().join(", ")),
+            Err(e) => e.render(expr),
+        }
+    }
+
+    /// The steps of every stage for `expr`, in the current mode and with the
+    /// variables of the session, followed by the result.
+    fn trace(&self, expr: &str) -> String {
+        fn steps<N: Numeric>(
+            expr: &str,
+            env: &Environment<Value<N>>,
+            context: &N::Context,
+        ) -> String {
+            let mut steps = Steps::new();
+
+            let rpn = Calculator::tokenize_with(expr, &mut steps)
+                .and_then(|tokens| Calculator::expression_with(tokens, &mut steps));
+            let rpn = match rpn {
+                Ok(rpn) => rpn,
+                Err(e) => return format!("{}\n\n{}", steps, e.render(expr)),
+            };
+
+            let result = Program::compile(&rpn, context).and_then(|program| {
+                let load = |slot: usize| env.get(&program.slots()[slot]);
+                Vm::new().run_with(&program, load, &mut steps)
+            });
+
+            match result {
+                Ok(value) => format!("{}\n\n= {}", steps, value),
+                Err(e) => format!("{}\n\n{}", steps, e.render(expr)),
+            }
+        }
+
+        match &self.mode {
+            Mode::Float(env) => steps(expr, env, &()),
+            Mode::Exact(env) => steps(expr, env, &()),
+            Mode::Integer(env) => steps(expr, env, &()),
+            Mode::Units(env) => steps(expr, env, &()),
+            Mode::Complex(env) => steps(expr, env, &()),
+            Mode::Word(env, ty) => steps(expr, env, ty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Repl;
+
+    fn session(input: &str) -> String {
+        let mut output = vec![];
+        Repl::new().run(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn keeps_variables_between_lines() {
+        let mut repl = Repl::new();
+
+        assert_eq!(repl.line("let x = 4"), Some("4".to_string()));
+        assert_eq!(repl.line("let y = x / 8"), Some("0.5".to_string()));
+        assert_eq!(repl.line("x * y"), Some("2".to_string()));
+        assert_eq!(repl.line(":vars"), Some("x = 4\ny = 0.5".to_string()));
+        assert_eq!(repl.line("   "), None);
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = Repl::new();
+
+        assert_eq!(
+            repl.line(":rpn -2 ^ 2 + max(1, x)"),
+            Some("2 2 ^ neg 1 x max/2 +".to_string())
+        );
+        assert_eq!(
+            repl.line(":ast -2 ^ 2 + max(1, x)"),
+            Some("(+ (- (^ 2 2)) (max 1 x))".to_string())
+        );
+        assert_eq!(
+            repl.line(":pratt -2 ^ 2 + 3x!"),
+            Some("(+ (- (^ 2 2)) (* 3 (x !)))".to_string())
+        );
+        assert!(repl
+            .line(":pratt 1 2 +")
+            .unwrap()
+            .starts_with("error: expected an operator before this"));
+        assert_eq!(
+            repl.line(":bytecode x > 0 ? x : -x"),
+            Some(
+                "   0  load x\n   1  const 0\n   2  binary >\n   3  jump-if-false 6\n   \
+                    4  load x\n   5  jump 8\n   6  load x\n   7  unary -\nslots: x"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            repl.line(":infix ((a + b)) * -(c ^ 2) - (d - e)"),
+            Some("(a + b) * -c ^ 2 - (d - e)".to_string())
+        );
+        assert_eq!(
+            repl.line(":latex sqrt(x) / 2"),
+            Some("\\frac{\\sqrt{x}}{2}".to_string())
+        );
+        assert_eq!(
+            repl.line(":mathml x!"),
+            Some(
+                "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow><mi>x</mi><mo>!</mo>\
+                    </mrow></math>"
+                    .to_string()
+            )
+        );
+        assert!(repl.line(":infix (1 + 2").unwrap().starts_with("error: "));
+        // `x` may be infinite, `2 * x - x` isn't `x` then
+        assert_eq!(
+            repl.line(":simplify 2 * x + 1 - x * 1 + 0 * y"),
+            Some("(+ (- (+ (* 2 x) 1) x) (* 0 y))".to_string())
+        );
+        assert_eq!(
+            repl.line(":simplify x > 0 && 1 < 2"),
+            Some("(> x 0)".to_string())
+        );
+        assert_eq!(
+            repl.line(":derive x x ^ 3 + 2 * x * y"),
+            Some("(+ (* 3 (^ x 2)) (* 2 y))".to_string())
+        );
+        assert_eq!(
+            repl.line(":derive x fact(x)"),
+            Some(
+                "error: `fact` can't be differentiated\n --> 1:1\n  |\n1 | fact(x)\n  | ^~~~~~~"
+                    .to_string()
+            )
+        );
+        assert!(repl.line(":derive x").unwrap().starts_with("usage"));
+        assert_eq!(
+            repl.line(":bytecode 1 +"),
+            Some("error: missing operand\n --> 1:3\n  |\n1 | 1 +\n  |   ^".to_string())
+        );
+        assert_eq!(
+            repl.line(":nope"),
+            Some("unknown command `:nope`, try `:help`".to_string())
+        );
+        assert!(repl.line(":vars").unwrap().starts_with("no variables"));
+    }
+
+    #[test]
+    fn tracing() {
+        let mut repl = Repl::new();
+
+        repl.line(":mode exact");
+        repl.line("let x = 2");
+        let reply = repl.line(":trace x / 3").unwrap();
+        assert!(
+            reply.starts_with("tokens  x / 3\n\ntoken  moves"),
+            "{}",
+            reply
+        );
+        assert!(reply.contains("\n0   load x       2\n"), "{}", reply);
+        assert!(reply.ends_with("\n\n= 2/3"), "{}", reply);
+
+        // the steps up to the error, then the error
+        let reply = repl.line(":trace 1 / (x - 2)").unwrap();
+        assert!(reply.contains("binary -     1, 0\n"), "{}", reply);
+        let error = "error: division by zero\n --> 1:6\n  |\n1 | 1 / (x - 2)\n  |      ^~~~~";
+        assert!(reply.ends_with(error), "{}", reply);
+        assert!(repl.line(":trace 1 +").unwrap().ends_with("^"));
+    }
+
+    #[test]
+    fn scripts() {
+        let path = std::env::temp_dir().join(format!("calc-{}.calc", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut repl = Repl::new();
+
+        // in the current mode, with the variables of the session
+        std::fs::write(
+            path,
+            "fn half(x)\n    return x / 2\nend\nhalf(n)\nlet m = n + 1",
+        )
+        .unwrap();
+        repl.line(":mode exact");
+        repl.line("let n = 3");
+        assert_eq!(
+            repl.line(&format!(":run {}", path)),
+            Some("3/2 ≈ 1.5000000000".to_string())
+        );
+        assert_eq!(repl.line("m"), Some("4".to_string()));
+
+        // what was printed, then what went wrong
+        std::fs::write(path, "n\nn / 0").unwrap();
+        assert_eq!(
+            repl.line(&format!(":run {}", path)),
+            Some("3\nerror: division by zero\n --> 2:5\n  |\n2 | n / 0\n  |     ^".to_string())
+        );
+        // kept apart for `main`, which prints the error on stderr
+        let (output, error) = repl.run_script(path);
+        assert_eq!(output, ["3"]);
+        assert!(error.unwrap().starts_with("error: division by zero"));
+
+        std::fs::write(path, "let n = 0").unwrap();
+        assert_eq!(repl.line(&format!(":run {}", path)), None);
+        std::fs::remove_file(path).unwrap();
+        assert!(repl
+            .line(&format!(":run {}", path))
+            .unwrap()
+            .starts_with("can't read"));
+    }
+
+    #[test]
+    fn solving() {
+        let mut repl = Repl::new();
+
+        repl.line("let target = 2");
+        let reply = repl.line("solve(x ^ 2 = target, x)").unwrap();
+        assert!(reply.starts_with("x = 1.414213562373095"), "{}", reply);
+        assert!(reply.contains("newton"), "{}", reply);
+        // and `x` is bound
+        assert_eq!(
+            repl.line("abs(x * x - target) < 1e-12"),
+            Some("true".to_string())
+        );
+
+        // big roots are written in scientific notation
+        assert_eq!(
+            repl.line("solve(x = 1e30, x)"),
+            Some("x = 1e30  (newton, 1 iteration)".to_string())
+        );
+        assert_eq!(
+            repl.line("root(x, x, 1, 2)"),
+            Some(
+                "error: no sign change between f(1) = 1 and f(2) = 2\n --> 1:1\n  |\n\
+                 1 | root(x, x, 1, 2)\n  | ^~~~~~~~~~~~~~~~"
+                    .to_string()
+            )
+        );
+        assert!(repl
+            .line("solve(x = 1)")
+            .unwrap()
+            .contains("expected `solve(<lhs> = <rhs>, <variable>[, <guess>])`"));
+
+        repl.line(":mode exact");
+        assert_eq!(
+            repl.line("solve(x = 1, x)"),
+            Some("solving needs float mode, try `:mode float`".to_string())
+        );
+    }
+
+    #[test]
+    fn errors_do_not_end_the_session() {
+        let output = session("1 / 0\nlet z = 2\n1 +* z\nz * 3\n:quit\n4\n");
+
+        assert!(output.contains("error: division by zero"));
+        assert!(output.contains("error: missing operand"));
+        assert!(output.contains("> 6\n"));
+        // nothing after `:quit` is evaluated
+        assert!(!output.contains("> 4"));
+    }
+
+    #[test]
+    fn exact_mode() {
+        let mut repl = Repl::new();
+
+        repl.line("let third = 1/3");
+        repl.line("let half = 1/2");
+        assert_eq!(
+            repl.line(":mode"),
+            Some("computing in float mode".to_string())
+        );
+        assert_eq!(
+            repl.line("0.1 + 0.2"),
+            Some("0.30000000000000004".to_string())
+        );
+
+        assert_eq!(
+            repl.line(":mode exact"),
+            Some("switched to exact mode".to_string())
+        );
+        assert_eq!(
+            repl.line("0.1 + 0.2"),
+            Some("3/10 ≈ 0.3000000000".to_string())
+        );
+        assert_eq!(repl.line("1/3 * 3"), Some("1".to_string()));
+        assert_eq!(repl.line("0.1 + 0.2 == 0.3"), Some("true".to_string()));
+
+        // float variables come along with their exact (binary) value
+        assert_eq!(repl.line("half * 2"), Some("1".to_string()));
+        assert_ne!(repl.line("third * 3"), Some("1".to_string()));
+
+        repl.line(":digits 3");
+        assert_eq!(repl.line("let x = 2/3"), Some("2/3 ≈ 0.667".to_string()));
+
+        repl.line(":mode float");
+        assert_eq!(repl.line("x * 3"), Some("2".to_string()));
+        assert!(repl.line(":mode fast").unwrap().starts_with("usage"));
+    }
+
+    #[test]
+    fn integer_mode() {
+        let mut repl = Repl::new();
+
+        repl.line("let n = 30");
+        repl.line("let half = 0.5");
+        assert_eq!(
+            repl.line(":mode integer"),
+            Some("switched to integer mode".to_string())
+        );
+        assert_eq!(
+            repl.line("fact(n)"),
+            Some("265252859812191058636308480000000".to_string())
+        );
+        assert!(repl
+            .line("7 / 2")
+            .unwrap()
+            .contains("error: the result is not an integer"));
+        assert_eq!(
+            repl.line("2 ^ 100"),
+            Some("1267650600228229401496703205376".to_string())
+        );
+        assert!(repl
+            .line("1 / 2 ^ -1")
+            .unwrap()
+            .contains("error: the result is not an integer"));
+
+        // `half` isn't an integer, it didn't make it here
+        assert_eq!(repl.line(":vars"), Some("n = 30".to_string()));
+    }
+
+    #[test]
+    fn programmer_mode() {
+        let mut repl = Repl::new();
+
+        repl.line("let mask = 0xf0");
+        assert_eq!(
+            repl.line(":mode u8"),
+            Some("switched to u8 mode".to_string())
+        );
+        assert_eq!(
+            repl.line("mask | 0b0101"),
+            Some("245  0xf5  0o365  0b11110101".to_string())
+        );
+        assert_eq!(
+            repl.line("~mask & 0xff >> 2"),
+            Some("15  0xf  0o17  0b1111".to_string())
+        );
+        assert_eq!(
+            repl.line("0x0f xor 0xff"),
+            Some("240  0xf0  0o360  0b11110000".to_string())
+        );
+        assert!(repl
+            .line("255 + 1")
+            .unwrap()
+            .contains("error: numeric overflow"));
+
+        assert_eq!(
+            repl.line(":overflow wrap"),
+            Some("switched to overflow mode wrap".to_string())
+        );
+        assert_eq!(repl.line("255 + 1"), Some("0  0x0  0o0  0b0".to_string()));
+        assert_eq!(
+            repl.line("mask << 1"),
+            Some("224  0xe0  0o340  0b11100000".to_string())
+        );
+
+        repl.line(":mode i8");
+        assert_eq!(
+            repl.line("0xff"),
+            Some("-1  0xff  0o377  0b11111111".to_string())
+        );
+        assert_eq!(repl.line("-128 / -1 < 0"), Some("true".to_string()));
+        // 240 doesn't fit in an i8, it wrapped around like any other result
+        assert_eq!(repl.line(":vars"), Some("mask = -16".to_string()));
+
+        repl.line(":mode float");
+        assert!(repl
+            .line("1 & 1")
+            .unwrap()
+            .contains("error: bitwise operators need fixed width integers"));
+        assert!(repl.line(":mode u7").unwrap().starts_with("usage"));
+    }
+
+    #[test]
+    fn units_mode() {
+        let mut repl = Repl::new();
+
+        repl.line("let n = 4");
+        repl.line(":mode units");
+        assert_eq!(repl.line("60 mph to m/s"), Some("26.8224 m/s".to_string()));
+        assert_eq!(repl.line("2 kg * 9.81 m/s^2"), Some("19.62 N".to_string()));
+        assert_eq!(repl.line("n * 3 m"), Some("12 m".to_string()));
+        assert_eq!(
+            repl.line("let d = 3 km + 500 m"),
+            Some("3500 m".to_string())
+        );
+        assert_eq!(
+            repl.line("d / (30 min) to km/h"),
+            Some("7 km/h".to_string())
+        );
+        assert_eq!(repl.line("(2 m) ^ 2 * 3 m"), Some("12 m^3".to_string()));
+        assert_eq!(repl.line("1 mi > 1 km"), Some("true".to_string()));
+        assert_eq!(repl.line("sqrt(16 m^2)"), Some("4 m".to_string()));
+
+        for (line, error) in [
+            ("3 m + 2 s", "incompatible units `m` and `s`"),
+            ("1 mi > 1 h", "incompatible units `m` and `s`"),
+            (
+                "sin(3 m)",
+                "expected a plain number, found a quantity in `m`",
+            ),
+            (
+                "2 ^ (1 s)",
+                "expected a plain number, found a quantity in `s`",
+            ),
+            ("sqrt(2 m)", "`m` can't be raised to a fractional power"),
+            ("3 kg to furlong", "unknown unit `furlong`"),
+            ("3 kg to m", "incompatible units `kg` and `m`"),
+            (
+                "(3 m to cm) + 1",
+                "`to <unit>` only converts the result of a whole line",
+            ),
+        ] {
+            assert!(repl.line(line).unwrap().contains(error), "{}", line);
+        }
+
+        // quantities with units have no meaning in the other modes, only
+        // plain numbers are kept
+        repl.line(":mode float");
+        assert_eq!(repl.line(":vars"), Some("n = 4".to_string()));
+        assert!(repl
+            .line("1 m")
+            .unwrap()
+            .contains("error: units are only supported in units mode"));
+    }
+
+    #[test]
+    fn complex_mode() {
+        let mut repl = Repl::new();
+
+        repl.line("let n = 2");
+        assert_eq!(
+            repl.line(":mode complex"),
+            Some("switched to complex mode".to_string())
+        );
+        assert_eq!(repl.line("sqrt(-4)"), Some("2i".to_string()));
+        assert_eq!(repl.line("let z = n + 2i"), Some("2 + 2i".to_string()));
+        assert_eq!(repl.line("z * conj(z)"), Some("8".to_string()));
+        assert_eq!(repl.line("abs(3 + 4i) == 5"), Some("true".to_string()));
+
+        assert_eq!(
+            repl.line(":form polar"),
+            Some("showing complex results in polar form".to_string())
+        );
+        assert_eq!(repl.line("-1"), Some("1 ∠ 3.141592653589793".to_string()));
+        assert_eq!(repl.line(":form"), Some("complex form: polar".to_string()));
+        assert!(repl.line(":form cartesian").unwrap().starts_with("usage"));
+        repl.line(":form rectangular");
+
+        assert!(repl
+            .line("min(i, 2)")
+            .unwrap()
+            .contains("error: expected a real number"));
+
+        // `z` isn't real, it doesn't make it to float mode where `i` is
+        // unknown
+        repl.line(":mode float");
+        assert_eq!(repl.line(":vars"), Some("n = 2".to_string()));
+        assert!(repl
+            .line("2i")
+            .unwrap()
+            .contains("error: unknown variable `i`"));
+    }
+}
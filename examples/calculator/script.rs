@@ -0,0 +1,936 @@
+/*
+# Scripts
+
+A script is a calculator session that can make decisions and repeat itself,
+one statement per line:
+
+```text
+# the sum of the first 10 Fibonacci numbers
+fn fib(n)
+    if n < 2
+        return n
+    end
+    return fib(n - 1) + fib(n - 2)
+end
+
+let total = 0
+let i = 0
+while i < 10
+    total = total + fib(i)
+    i = i + 1
+end
+total
+```
+
+- `let <name> = <expr>` binds a variable in the innermost scope, and
+  `<name> = <expr>` changes the innermost variable of that name;
+- `if <cond>`, `else if <cond>`, `else` and `while <cond>` run the lines up to
+  their `end` (or the next `else`). Every block is a scope, what it binds is
+  gone after its `end`;
+- `fn <name>(<parameters>)` defines a function up to its `end`, which gives
+  its result with `return <expr>`. Functions are defined at the top of the
+  script and can be called from anywhere in it, before their definition or
+  from themselves. Their body sees their parameters, their own variables and
+  the variables of the top of the script, never those of their caller;
+- any other line is an expression, its value is printed. That's how a
+  script shows something.
+
+Everything after a `#` is a comment. Lines are read like those of the REPL,
+and every mistake of the script is reported at once. The expressions are
+compiled to bytecode once, the VM calls the functions of the script through
+`Vm::run_calling`.
+*/
+
+use std::collections::HashMap;
+
+use crate::bytecode::{Program, Vm};
+use crate::diagnostic::Errors;
+use crate::env::Environment;
+use crate::functions::{self, Arity};
+use crate::numeric::Numeric;
+use crate::token::{Span, Spanned, Token};
+use crate::{Calculator, Error, EvalError, Statement, Value, KEYWORDS};
+
+/// How deep the functions of a script can call each other, well before the
+/// native stack runs out.
+const MAX_DEPTH: usize = 100;
+
+/// How many times the loops of a script can go round in a run, all loops
+/// together, so one that never ends is reported instead of hanging.
+const MAX_ITERATIONS: usize = 1_000_000;
+
+/// A script ready to run, `N` is the kind of numbers it computes with.
+pub struct Script<N> {
+    body: Vec<Stmt<N>>,
+    functions: HashMap<String, Function<N>>,
+}
+
+struct Function<N> {
+    params: Vec<String>,
+    body: Vec<Stmt<N>>,
+}
+
+/// The condition of an `if` or a `while`, and where it is to point at it
+/// when it isn't a boolean
+struct Condition<N> {
+    program: Program<N>,
+    span: Span,
+}
+
+/// A statement of a script. Those opening a block hold the statements up to
+/// its `end`.
+enum Stmt<N> {
+    Let(String, Program<N>),
+    Assign(Spanned<String>, Program<N>),
+    Expr(Program<N>),
+    /// The `if` and `else if` branches in order, then the `else` one
+    If(Vec<(Condition<N>, Vec<Stmt<N>>)>, Vec<Stmt<N>>),
+    While(Condition<N>, Vec<Stmt<N>>),
+    Return(Program<N>),
+}
+
+/// A line of a script, before the blocks are put together. The expressions
+/// are in RPN, the conditions come with their span.
+enum Line {
+    Statement(Statement),
+    Assign(Spanned<String>, Vec<Spanned<Token>>),
+    If(Vec<Spanned<Token>>, Span),
+    ElseIf(Vec<Spanned<Token>>, Span),
+    Else,
+    While(Vec<Spanned<Token>>, Span),
+    End,
+    Fn(Spanned<String>, Vec<String>),
+    Return(Vec<Spanned<Token>>),
+    /// An `if`, `while` or `fn` line with mistakes, the lines up to its
+    /// `end` are skipped
+    Broken,
+}
+
+impl<N: Numeric> Script<N> {
+    /// Parse `source` and compile its expressions, `context` holds the
+    /// settings of the backend like for `Calculator::execute_with`.
+    pub fn parse(source: &str, context: &N::Context) -> Result<Script<N>, Errors> {
+        // the lines with code, and where they start in `source`
+        let mut lines = vec![];
+        let mut offset = 0;
+
+        for text in source.split('\n') {
+            let code = text.split('#').next().unwrap_or_default();
+
+            if !code.trim().is_empty() {
+                lines.push((offset, code));
+            }
+            offset += text.len() + 1;
+        }
+
+        // the functions are known before any line is read, so they can be
+        // called before their definition
+        let mut arities = HashMap::new();
+        for &(_, code) in &lines {
+            let (tokens, _) = Calculator::scan(code);
+
+            if let Some((name, params)) = header(&tokens) {
+                arities.entry(name.node).or_insert(params.len());
+            }
+        }
+        let arity = |name: &str| {
+            functions::arity(name).or_else(|| arities.get(name).map(|&n| Arity::Exact(n)))
+        };
+
+        let mut errors = vec![];
+        let mut parsed = vec![];
+
+        for (offset, code) in lines {
+            match Calculator::read_at(code, offset, |tokens| line(tokens, &arity)) {
+                Ok(line) => parsed.extend(line),
+                Err(e) => {
+                    errors.extend(e);
+
+                    // keep the blocks balanced, or every `end` after this
+                    // line would be reported too
+                    let mut words = code.trim_start().split(|c: char| !c.is_alphanumeric());
+                    let broken = match words.next() {
+                        Some("if" | "while" | "fn") => Line::Broken,
+                        Some("else") => Line::Else,
+                        Some("end") => Line::End,
+                        _ => continue,
+                    };
+                    let start = offset + code.len() - code.trim_start().len();
+                    parsed.push(Spanned::new(broken, Span::new(start, start)));
+                }
+            }
+        }
+
+        let mut builder = Builder {
+            lines: parsed.into_iter(),
+            context,
+            functions: HashMap::new(),
+            in_function: false,
+            errors,
+        };
+        let (body, _) = builder.block(Body::Script);
+
+        Errors::check(builder.errors)?;
+        Ok(Script {
+            body,
+            functions: builder.functions,
+        })
+    }
+
+    /// Run the script, with the variables of its top level in `env`. `print`
+    /// gets the value of every expression statement, as they are run.
+    pub fn run(
+        &self,
+        env: &mut Environment<Value<N>>,
+        print: impl FnMut(Value<N>),
+    ) -> Result<(), Spanned<EvalError>> {
+        let mut run = Run {
+            script: self,
+            globals: env,
+            print,
+            depth: 0,
+            iterations: 0,
+        };
+
+        // there is no `return` outside of functions, the top level runs to
+        // its end
+        run.block(&self.body, &mut vec![]).map(drop)
+    }
+}
+
+/// Parse a line of tokens, `None` when there are none. `functions` gives
+/// the arity of the functions that can be called.
+fn line(
+    mut tokens: Vec<Spanned<Token>>,
+    functions: &dyn Fn(&str) -> Option<Arity>,
+) -> Result<Option<Spanned<Line>>, Errors> {
+    let Some(first) = tokens.first() else {
+        return Ok(None);
+    };
+    let head = first.span;
+
+    // the expression after a keyword, which must be there
+    let operand = |tokens: Vec<Spanned<Token>>, keyword: Span| {
+        let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+            return Err(Errors::from(Spanned::new(Error::MissingOperand, keyword)));
+        };
+        let span = first.span.to(last.span);
+
+        Ok((Calculator::expression_in(tokens, &mut (), functions)?, span))
+    };
+    // nothing may come after a keyword that stands alone
+    let alone = |tokens: &[Spanned<Token>]| match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => Err(Errors::from(Spanned::new(
+            Error::ExpectedEndOfLine,
+            first.span.to(last.span),
+        ))),
+        _ => Ok(()),
+    };
+
+    let keyword = match &first.node {
+        Token::Ident(word) if KEYWORDS.contains(&word.as_str()) => word.clone(),
+        _ => String::new(),
+    };
+    let is_if = |token: Option<&Spanned<Token>>| matches!(token, Some(t) if t.node == Token::Ident("if".into()));
+
+    let line = match keyword.as_str() {
+        "if" => {
+            let (rpn, span) = operand(tokens.split_off(1), head)?;
+            Line::If(rpn, span)
+        }
+        "else" if is_if(tokens.get(1)) => {
+            let (rpn, span) = operand(tokens.split_off(2), head.to(tokens[1].span))?;
+            Line::ElseIf(rpn, span)
+        }
+        "else" => {
+            alone(&tokens[1..])?;
+            Line::Else
+        }
+        "while" => {
+            let (rpn, span) = operand(tokens.split_off(1), head)?;
+            Line::While(rpn, span)
+        }
+        "end" => {
+            alone(&tokens[1..])?;
+            Line::End
+        }
+        "fn" => match header(&tokens) {
+            Some((name, params)) => Line::Fn(name, params),
+            None => {
+                let span = head.to(tokens[tokens.len() - 1].span);
+                return Err(Errors::from(Spanned::new(Error::InvalidFunction, span)));
+            }
+        },
+        "return" => Line::Return(operand(tokens.split_off(1), head)?.0),
+        _ => match (&tokens[0].node, tokens.get(1).map(|t| &t.node)) {
+            (Token::Ident(name), Some(Token::Assign)) if keyword.is_empty() => {
+                let name = Spanned::new(name.clone(), head);
+                let assign = tokens[1].span;
+                Line::Assign(name, operand(tokens.split_off(2), assign)?.0)
+            }
+            _ => Line::Statement(Calculator::statement_in(tokens, functions)?),
+        },
+    };
+
+    Ok(Some(Spanned::new(line, head)))
+}
+
+/// The name and the parameters of a `fn <name>(<parameters>)` line, `None`
+/// if it isn't one or a parameter comes twice.
+fn header(tokens: &[Spanned<Token>]) -> Option<(Spanned<String>, Vec<String>)> {
+    let [fn_, name, open, params @ .., close] = tokens else {
+        return None;
+    };
+
+    let Token::Func(name) = &name.node else {
+        return None;
+    };
+    if fn_.node != Token::Ident("fn".into())
+        || open.node != Token::Bracket('(')
+        || close.node != Token::Bracket(')')
+        // a `,` at the end
+        || params.len() % 2 == 0 && !params.is_empty()
+    {
+        return None;
+    }
+
+    let mut names: Vec<String> = vec![];
+    for (i, param) in params.iter().enumerate() {
+        match &param.node {
+            Token::Ident(param) if i % 2 == 0 => {
+                if KEYWORDS.contains(&param.as_str()) || names.contains(param) {
+                    return None;
+                }
+                names.push(param.clone());
+            }
+            Token::Comma if i % 2 == 1 => {}
+            _ => return None,
+        }
+    }
+
+    Some((Spanned::new(name.clone(), tokens[1].span), names))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What a block is the body of, which tells what ends it
+enum Body {
+    /// The top of the script, it runs to the end of the source
+    Script,
+    /// A `while`, a `fn` or an `else`, up to their `end`
+    Block,
+    /// An `if` or an `else if`, up to their `end` or the next `else`
+    Branch,
+}
+
+/// Puts the lines of a script together into blocks, compiling their
+/// expressions, and records the misplaced ones.
+struct Builder<'c, N: Numeric> {
+    lines: std::vec::IntoIter<Spanned<Line>>,
+    context: &'c N::Context,
+    functions: HashMap<String, Function<N>>,
+    /// Whether we are in the body of a function, where `return` belongs
+    in_function: bool,
+    errors: Vec<Spanned<Error>>,
+}
+
+impl<N: Numeric> Builder<'_, N> {
+    /// The statements of a block, and the line that ended it (`None` at the
+    /// end of the source).
+    fn block(&mut self, body: Body) -> (Vec<Stmt<N>>, Option<Spanned<Line>>) {
+        let mut stmts = vec![];
+
+        while let Some(line) = self.lines.next() {
+            let head = line.span;
+
+            let stmt = match line.node {
+                Line::End if body != Body::Script => return (stmts, Some(line)),
+                Line::Else | Line::ElseIf(..) if body == Body::Branch => {
+                    return (stmts, Some(line))
+                }
+                Line::End => {
+                    self.errors.push(Spanned::new(Error::MisplacedEnd, head));
+                    continue;
+                }
+                Line::Else | Line::ElseIf(..) => {
+                    self.errors.push(Spanned::new(Error::MisplacedElse, head));
+                    continue;
+                }
+                Line::Statement(Statement::Let(name, rpn)) => Stmt::Let(name, self.compile(&rpn)),
+                Line::Statement(Statement::Expr(rpn)) => Stmt::Expr(self.compile(&rpn)),
+                Line::Assign(name, rpn) => Stmt::Assign(name, self.compile(&rpn)),
+                Line::Return(rpn) => {
+                    if !self.in_function {
+                        self.errors.push(Spanned::new(Error::MisplacedReturn, head));
+                    }
+                    Stmt::Return(self.compile(&rpn))
+                }
+                Line::If(rpn, span) => {
+                    let cond = self.condition(&rpn, span);
+                    self.conditional(cond, head)
+                }
+                Line::While(rpn, span) => {
+                    let cond = self.condition(&rpn, span);
+                    Stmt::While(cond, self.body(head))
+                }
+                Line::Fn(name, params) => {
+                    self.function(name, params, head, body == Body::Script);
+                    continue;
+                }
+                // it may have been an `if`, its `else`s are skipped too
+                Line::Broken => {
+                    while let (_, Some(end)) = self.block(Body::Branch) {
+                        if matches!(end.node, Line::End) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            stmts.push(stmt);
+        }
+
+        (stmts, None)
+    }
+
+    /// The body of the block opened at `head`, up to its `end`.
+    fn body(&mut self, head: Span) -> Vec<Stmt<N>> {
+        let (stmts, end) = self.block(Body::Block);
+
+        if end.is_none() {
+            self.errors.push(Spanned::new(Error::UnclosedBlock, head));
+        }
+        stmts
+    }
+
+    /// The branches of the `if` opened at `head`.
+    fn conditional(&mut self, mut cond: Condition<N>, head: Span) -> Stmt<N> {
+        let mut branches = vec![];
+
+        loop {
+            let (stmts, end) = self.block(Body::Branch);
+            branches.push((cond, stmts));
+
+            match end.map(|line| line.node) {
+                Some(Line::ElseIf(rpn, span)) => cond = self.condition(&rpn, span),
+                Some(Line::Else) => return Stmt::If(branches, self.body(head)),
+                Some(_) => return Stmt::If(branches, vec![]),
+                None => {
+                    self.errors.push(Spanned::new(Error::UnclosedBlock, head));
+                    return Stmt::If(branches, vec![]);
+                }
+            }
+        }
+    }
+
+    /// Record the function opened at `head`, `top` tells if it's at the top
+    /// of the script.
+    fn function(&mut self, name: Spanned<String>, params: Vec<String>, head: Span, top: bool) {
+        if !top {
+            self.errors
+                .push(Spanned::new(Error::MisplacedFunction, head));
+        }
+
+        let outer = std::mem::replace(&mut self.in_function, true);
+        let body = self.body(head);
+        self.in_function = outer;
+
+        if functions::lookup(&name.node).is_some() || self.functions.contains_key(&name.node) {
+            let error = Error::FunctionRedefined(name.node.clone());
+            self.errors.push(Spanned::new(error, name.span));
+        } else if top {
+            self.functions.insert(name.node, Function { params, body });
+        }
+    }
+
+    fn condition(&mut self, rpn: &[Spanned<Token>], span: Span) -> Condition<N> {
+        Condition {
+            program: self.compile(rpn),
+            span,
+        }
+    }
+
+    /// A literal that doesn't fit is only reported when it's reached, what
+    /// can fail here is the shape of the RPN which the parser already
+    /// checked. If it does anyway it's a mistake of the line like any other.
+    fn compile(&mut self, rpn: &[Spanned<Token>]) -> Program<N> {
+        Program::compile(rpn, self.context).unwrap_or_else(|e| {
+            let error = match e.node {
+                EvalError::LeftoverOperands(_) => Error::MissingOperator,
+                _ => Error::MissingOperand,
+            };
+            self.errors.push(Spanned::new(error, e.span));
+            Program::default()
+        })
+    }
+}
+
+/// A script running: where its top level variables are, what to do with the
+/// values it prints, how deep its functions are calling each other and how
+/// many times its loops went round.
+struct Run<'s, 'e, N, P> {
+    script: &'s Script<N>,
+    globals: &'e mut Environment<Value<N>>,
+    print: P,
+    depth: usize,
+    iterations: usize,
+}
+
+impl<'s, N: Numeric, P: FnMut(Value<N>)> Run<'s, '_, N, P> {
+    /// Run the statements of a block, `scopes` holds the variables of the
+    /// blocks it is in (innermost last) up to the top of the script or the
+    /// body of a function. The result is the value of the `return` that
+    /// ended it, if any.
+    fn block(
+        &mut self,
+        body: &'s [Stmt<N>],
+        scopes: &mut Vec<Environment<Value<N>>>,
+    ) -> Result<Option<Value<N>>, Spanned<EvalError>> {
+        for stmt in body {
+            match stmt {
+                Stmt::Let(name, program) => {
+                    let value = self.eval(program, scopes)?;
+
+                    match scopes.last_mut() {
+                        Some(scope) => scope.set(name.as_str(), value),
+                        None => self.globals.set(name.as_str(), value),
+                    }
+                }
+                Stmt::Assign(name, program) => {
+                    let value = self.eval(program, scopes)?;
+                    let scope = scopes.iter_mut().rev().find(|s| s.contains(&name.node));
+
+                    match scope {
+                        Some(scope) => scope.set(name.node.as_str(), value),
+                        None if self.globals.contains(&name.node) => {
+                            self.globals.set(name.node.as_str(), value)
+                        }
+                        None => {
+                            let error = EvalError::UnknownVariable(name.node.clone());
+                            return Err(Spanned::new(error, name.span));
+                        }
+                    }
+                }
+                Stmt::Expr(program) => {
+                    let value = self.eval(program, scopes)?;
+                    (self.print)(value);
+                }
+                Stmt::If(branches, otherwise) => {
+                    let mut taken = otherwise;
+
+                    for (cond, body) in branches {
+                        if self.holds(cond, scopes)? {
+                            taken = body;
+                            break;
+                        }
+                    }
+
+                    if let Some(value) = self.scoped(taken, scopes)? {
+                        return Ok(Some(value));
+                    }
+                }
+                Stmt::While(cond, body) => {
+                    while self.holds(cond, scopes)? {
+                        if self.iterations == MAX_ITERATIONS {
+                            return Err(Spanned::new(EvalError::IterationLimit, cond.span));
+                        }
+                        self.iterations += 1;
+
+                        if let Some(value) = self.scoped(body, scopes)? {
+                            return Ok(Some(value));
+                        }
+                    }
+                }
+                Stmt::Return(program) => return self.eval(program, scopes).map(Some),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Run a block in a scope of its own.
+    fn scoped(
+        &mut self,
+        body: &'s [Stmt<N>],
+        scopes: &mut Vec<Environment<Value<N>>>,
+    ) -> Result<Option<Value<N>>, Spanned<EvalError>> {
+        scopes.push(Environment::new());
+        let result = self.block(body, scopes);
+        scopes.pop();
+
+        result
+    }
+
+    fn holds(
+        &mut self,
+        cond: &Condition<N>,
+        scopes: &[Environment<Value<N>>],
+    ) -> Result<bool, Spanned<EvalError>> {
+        self.eval(&cond.program, scopes)?
+            .boolean()
+            .map_err(|e| Spanned::new(e, cond.span))
+    }
+
+    fn eval(
+        &mut self,
+        program: &Program<N>,
+        scopes: &[Environment<Value<N>>],
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        // the innermost variable of that name, the function calls can't
+        // change them while the expression runs
+        let row: Vec<_> = program
+            .slots()
+            .iter()
+            .map(|name| {
+                let mut scopes = scopes.iter().rev();
+                scopes
+                    .find_map(|scope| scope.get(name))
+                    .or_else(|| self.globals.get(name))
+            })
+            .collect();
+
+        Vm::new().run_calling(
+            program,
+            |slot| row[slot].clone(),
+            |name, args, at| self.call(name, args, at),
+        )
+    }
+
+    /// Call the function `name` of the script, `at` is the span of the call.
+    fn call(
+        &mut self,
+        name: &str,
+        args: Vec<Value<N>>,
+        at: Span,
+    ) -> Result<Value<N>, Spanned<EvalError>> {
+        let script = self.script;
+        let function = script
+            .functions
+            .get(name)
+            .ok_or_else(|| Spanned::new(EvalError::UnknownFunction(name.to_string()), at))?;
+
+        if self.depth == MAX_DEPTH {
+            return Err(Spanned::new(EvalError::RecursionLimit, at));
+        }
+
+        // the body starts with the parameters only, the variables of the
+        // caller are out of its reach
+        let mut params = Environment::new();
+        for (param, arg) in function.params.iter().zip(args) {
+            params.set(param.as_str(), arg);
+        }
+
+        self.depth += 1;
+        let result = self.block(&function.body, &mut vec![params]);
+        self.depth -= 1;
+
+        result?.ok_or_else(|| Spanned::new(EvalError::NoReturn(name.to_string()), at))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Script;
+    use crate::env::Environment;
+    use crate::rational::Rational;
+    use crate::Value;
+
+    /// What the script prints in float mode, or its first error
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut env = Environment::new();
+        run_in(source, &mut env)
+    }
+
+    fn run_in(source: &str, env: &mut Environment<Value>) -> Result<Vec<String>, String> {
+        let script = Script::<f64>::parse(source, &()).map_err(|e| e.render(source))?;
+
+        let mut printed = vec![];
+        script
+            .run(env, |value| printed.push(value.to_string()))
+            .map_err(|e| e.render(source))?;
+        Ok(printed)
+    }
+
+    /// Every mistake in the script, as `line:column message`
+    fn mistakes(source: &str) -> Vec<String> {
+        let Err(errors) = Script::<f64>::parse(source, &()) else {
+            return vec![];
+        };
+
+        errors
+            .iter()
+            .map(|e| {
+                let before = &source[..e.span.start];
+                let line = before.matches('\n').count() + 1;
+                let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+                format!("{}:{} {}", line, column, e.node)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loops_and_functions() {
+        let source = "\
+# the sum of the first 10 Fibonacci numbers
+fn fib(n)
+    if n < 2
+        return n
+    end
+    return fib(n - 1) + fib(n - 2)
+end
+
+let total = 0
+let i = 0
+while i < 10
+    total = total + fib(i)  # fib(9) is 34
+    i = i + 1
+end
+total
+fib(20)";
+        assert_eq!(run(source), Ok(vec!["88".into(), "6765".into()]));
+
+        // every expression statement prints, even in a loop
+        let source = "let i = 0\nwhile i < 3\n    i * 10\n    i = i + 1\nend";
+        assert_eq!(run(source), Ok(vec!["0".into(), "10".into(), "20".into()]));
+    }
+
+    #[test]
+    fn any_whitespace() {
+        let source = "let i = 0\nwhile i < 2\n\ti\n\ti = i + 1\nend";
+        assert_eq!(run(source), Ok(vec!["0".into(), "1".into()]));
+
+        let source = "let i = 0\r\nwhile i < 2\r\n    i # counts\r\n    i = i + 1\r\nend\r\n";
+        assert_eq!(run(source), Ok(vec!["0".into(), "1".into()]));
+    }
+
+    #[test]
+    fn branches() {
+        let source = "\
+fn sign(x)
+    if x < 0
+        return -1
+    else if x == 0
+        return 0
+    else if x != x
+        return x
+    else
+        return 1
+    end
+end
+sign(-3)
+sign(0)
+sign(7)";
+        assert_eq!(run(source), Ok(vec!["-1".into(), "0".into(), "1".into()]));
+
+        // a `return` in a loop leaves the whole function, functions call
+        // each other whatever their order
+        let source = "\
+fn first_square_over(n)
+    let i = 0
+    while i >= 0
+        if square(i) > n
+            return i
+        end
+        i = i + 1
+    end
+end
+fn square(x)
+    return x * x
+end
+first_square_over(50)";
+        assert_eq!(run(source), Ok(vec!["8".into()]));
+    }
+
+    #[test]
+    fn scopes() {
+        // a block sees the variables around it, what it binds is gone
+        // after its `end`
+        let source = "\
+let x = 1
+if x > 0
+    let y = 10
+    x = x + y
+    let x = 100
+    x
+end
+x
+y";
+        assert_eq!(
+            run(source),
+            Err("error: unknown variable `y`\n --> 9:1\n  |\n9 | y\n  | ^".into())
+        );
+        let mut env = Environment::new();
+        assert!(run_in(source, &mut env).is_err());
+        assert_eq!(env.get("x"), Some(Value::Number(11.0)));
+        assert_eq!(env.get("y"), None);
+
+        // a function sees the top of the script, not its caller
+        let source = "\
+let scale = 2
+fn scaled(x)
+    scale = scale + 1
+    return x * scale
+end
+fn local()
+    return secret
+end
+scaled(5)
+scale
+if scale > 0
+    let secret = 1
+    local()
+end";
+        let error = run(source).unwrap_err();
+        assert!(
+            error.starts_with("error: unknown variable `secret`\n --> 7:12"),
+            "{}",
+            error
+        );
+
+        let mut env = Environment::new();
+        run_in(&source[..source.find("if scale").unwrap()], &mut env).unwrap();
+        assert_eq!(env.get("scale"), Some(Value::Number(3.0)));
+
+        // assigning needs a variable to assign to
+        let error = run("fn f()\n    z = 1\n    return z\nend\nf()").unwrap_err();
+        assert!(
+            error.starts_with("error: unknown variable `z`\n --> 2:5"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn numeric_backends() {
+        let source = "\
+fn harmonic(n)
+    let sum = 0
+    while n > 0
+        sum = sum + 1 / n
+        n = n - 1
+    end
+    return sum
+end
+harmonic(4)";
+
+        let script = Script::<Rational>::parse(source, &()).unwrap();
+        let mut env = Environment::new();
+        let mut printed = vec![];
+        script
+            .run(&mut env, |v| printed.push(v.to_string()))
+            .unwrap();
+
+        assert_eq!(printed, ["25/12"]);
+    }
+
+    #[test]
+    fn reports_every_mistake() {
+        let source = "\
+let x = 1 +
+if x > 1 1
+    y = 2
+else 3
+end
+end
+fn f(a, a)
+end
+while x
+    return 1
+end
+fn max(a, b)
+    return a
+end
+fn g(n)
+    fn h()
+        return 1
+    end
+sqrt(1, 2) + g()
+fn g(m)";
+
+        assert_eq!(
+            mistakes(source),
+            [
+                "1:11 missing operand",
+                "2:10 expected an operator before this",
+                "4:6 expected the end of the line",
+                "6:1 `end` without a block to close",
+                "7:1 expected `fn <name>(<parameters>)`",
+                "10:5 `return` outside of a function",
+                "12:4 `max` is already defined",
+                "15:1 expected an `end` for this block",
+                "16:5 functions are defined outside of any block",
+                "19:1 `sqrt` takes 1 argument but 2 were given",
+                "19:14 `g` takes 1 argument but 0 were given",
+                "20:1 functions are defined outside of any block",
+                "20:1 expected an `end` for this block",
+            ]
+        );
+
+        assert_eq!(mistakes("if\nend"), ["1:1 missing operand"]);
+        assert_eq!(mistakes("if 1 > 0\nelse if\nend"), ["2:1 missing operand"]);
+        assert_eq!(
+            mistakes("while 1 >\nx = \nend"),
+            ["1:9 missing operand", "2:3 missing operand"]
+        );
+        assert_eq!(
+            mistakes("if 1 > 0\nelse\nelse\nend"),
+            ["3:1 `else` without an `if` before it"]
+        );
+        assert_eq!(
+            mistakes("fn f(x\nend"),
+            [
+                "1:1 expected `fn <name>(<parameters>)`",
+                "1:5 mismatched parenthesis"
+            ]
+        );
+        assert_eq!(
+            mistakes("\n  # nothing but comments\n\n"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            run("let n = 3\nwhile n\n    n = n - 1\nend"),
+            Err("error: expected a boolean, found a number\n --> 2:7\n  |\n2 | while n\n  |       ^".into())
+        );
+
+        let error =
+            run("fn f(x)\n    if x > 0\n        return x\n    end\nend\nf(1) + f(-1)").unwrap_err();
+        assert!(
+            error.starts_with("error: `f` ended without returning a value\n --> 6:8"),
+            "{}",
+            error
+        );
+
+        // an error in a function points into its body
+        let error = run("fn inverse(x)\n    return 1 / x\nend\ninverse(0)").unwrap_err();
+        assert!(
+            error.starts_with("error: division by zero\n --> 2:16"),
+            "{}",
+            error
+        );
+
+        let error = run("fn forever(n)\n    return forever(n + 1)\nend\nforever(0)").unwrap_err();
+        assert!(
+            error.starts_with("error: too many nested calls\n --> 2:12"),
+            "{}",
+            error
+        );
+
+        let error = run("let n = 0\nwhile n >= 0\n    n = n + 1\nend").unwrap_err();
+        assert!(
+            error.starts_with("error: too many loop iterations\n --> 2:7"),
+            "{}",
+            error
+        );
+
+        // what was printed before the error stays printed
+        let script = Script::<f64>::parse("1\n2 / (1 > 2)\n3", &()).unwrap();
+        let mut printed = vec![];
+        let result = script.run(&mut Environment::new(), |v| printed.push(v));
+        assert!(result.is_err());
+        assert_eq!(printed, [Value::Number(1.0)]);
+    }
+}
@@ -0,0 +1,679 @@
+/*
+# Simplification
+
+Rewrites an `Expr` into a smaller one that computes the same thing, bottom
+up:
+
+- constant sub expressions are folded: `x + 2 * 3` is `x + 6`, `1 < 2` is
+  `true`;
+- identities are applied: `x * 1`, `x + 0`, `x - 0`, `x / 1` and `x ^ 1` are
+  `x`, `--x` is `x`, `x ^ 0` and `1 ^ x` are `1`, `c && true` and
+  `c || false` are `c`;
+- like terms are collected when the variables are finite: `2 * x + y - x +
+  1 + 2` is `x + y + 3`;
+- a conditional with a constant condition is the branch it picks.
+
+The rewrites keep the value of the expression, NaN included, and don't make
+errors disappear: `x * 0` is `NaN` when `x` is, and `sqrt(-1) * 0` stays as
+it is. What the variables may hold is up to the caller, see `Inputs`. The
+variables are expected to hold numbers though, `(a > b) * 1` isn't touched
+but `a * 1` is `a`.
+
+A folded number is only written out when the literal is the number: up to
+`2 ^ 53` every integer is, `2 ^ 1000` stays as it is rather than become a
+literal of 302 digits whose last 285 are made up.
+
+Collecting terms reorders additions, so with floats a result may differ in
+its last digits. With infinite or huge inputs it could do worse: `2 * x -
+x` is NaN for an infinite `x` and `y + y - y` overflows for `y = 1e308`,
+neither is `x` or `y`. That's why terms are only collected for
+`Inputs::Finite`.
+*/
+
+use crate::ast::{Expr, ExprKind};
+use crate::bytecode::{Program, Vm};
+use crate::token::{Operator, Span};
+use crate::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What the simplifier may assume about the variables.
+pub enum Inputs {
+    /// Any number, NaN and infinities included: `x * 0` and `x - x` stay,
+    /// they are NaN when `x` is infinite
+    Any,
+    /// Finite numbers only, like the columns of a data set, and small enough
+    /// that adding them up doesn't overflow: `x * 0` and `x - x` are `0`, and
+    /// like terms are collected
+    Finite,
+}
+
+impl Expr {
+    /// Simplify the tree, see `simplify.rs`.
+    pub fn simplify(self, inputs: Inputs) -> Expr {
+        Simplifier { inputs }.expr(self)
+    }
+}
+
+struct Simplifier {
+    inputs: Inputs,
+}
+
+impl Simplifier {
+    fn expr(&self, expr: Expr) -> Expr {
+        let Expr { kind, span } = expr;
+
+        // the operands first
+        let kind = match kind {
+            ExprKind::Call { name, args } => ExprKind::Call {
+                name,
+                args: args.into_iter().map(|arg| self.expr(arg)).collect(),
+            },
+            ExprKind::Unary { op, operand } => ExprKind::Unary {
+                op,
+                operand: Box::new(self.expr(*operand)),
+            },
+            ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+                op,
+                lhs: Box::new(self.expr(*lhs)),
+                rhs: Box::new(self.expr(*rhs)),
+            },
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => ExprKind::Conditional {
+                cond: Box::new(self.expr(*cond)),
+                then: Box::new(self.expr(*then)),
+                otherwise: Box::new(self.expr(*otherwise)),
+            },
+            leaf => leaf,
+        };
+        let expr = Expr::new(kind, span);
+
+        match constant(&expr) {
+            Some(Value::Number(num)) if literal(num) => Expr::new(ExprKind::Number(num), span),
+            Some(Value::Bool(b)) => boolean(b, span),
+            _ => self.rewrite(expr),
+        }
+    }
+
+    fn rewrite(&self, expr: Expr) -> Expr {
+        let span = expr.span;
+
+        match expr.kind {
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => match constant(&cond) {
+                Some(Value::Bool(true)) => *then,
+                Some(Value::Bool(false)) => *otherwise,
+                _ => Expr::new(
+                    ExprKind::Conditional {
+                        cond,
+                        then,
+                        otherwise,
+                    },
+                    span,
+                ),
+            },
+            ExprKind::Unary {
+                op: Operator::Pos,
+                operand,
+            } if numeric(&operand) => *operand,
+            // the right side of `&&` and `||` is only evaluated when the left
+            // one doesn't decide, and whatever it is has to be a boolean
+            ExprKind::Binary {
+                op: op @ (Operator::And | Operator::Or),
+                lhs,
+                rhs,
+            } => {
+                // what doesn't decide: `true` for `&&`, `false` for `||`
+                let neutral = op == Operator::And;
+
+                match (constant(&lhs), constant(&rhs)) {
+                    (Some(Value::Bool(l)), _) if l != neutral => boolean(l, span),
+                    (Some(Value::Bool(_)), _) if logical(&rhs) => *rhs,
+                    (_, Some(Value::Bool(r))) if r == neutral && logical(&lhs) => *lhs,
+                    _ => binary(op, *lhs, *rhs, span),
+                }
+            }
+            ExprKind::Binary {
+                op: Operator::Div,
+                lhs,
+                rhs,
+            } if is(&rhs, 1.0) && numeric(&lhs) => *lhs,
+            ExprKind::Binary {
+                op: Operator::Pow,
+                lhs,
+                rhs,
+            } => {
+                if is(&rhs, 1.0) && numeric(&lhs) {
+                    *lhs
+                } else if (is(&rhs, 0.0) && safe(&lhs)) || (is(&lhs, 1.0) && safe(&rhs)) {
+                    // even `NaN ^ 0` and `1 ^ NaN` are `1`
+                    Expr::new(ExprKind::Number(1.0), span)
+                } else {
+                    Expr::new(
+                        ExprKind::Binary {
+                            op: Operator::Pow,
+                            lhs,
+                            rhs,
+                        },
+                        span,
+                    )
+                }
+            }
+            kind => {
+                let expr = Expr::new(kind, span);
+
+                match &expr.kind {
+                    ExprKind::Binary {
+                        op: Operator::Add | Operator::Sub,
+                        ..
+                    } => self.sum(expr),
+                    ExprKind::Binary {
+                        op: Operator::Mul, ..
+                    }
+                    | ExprKind::Unary {
+                        op: Operator::Neg, ..
+                    } => match term(&expr) {
+                        (coefficient, Some(factor)) => self.term(coefficient, factor, span),
+                        (_, None) => expr,
+                    },
+                    _ => expr,
+                }
+            }
+        }
+    }
+
+    /// Collect the terms of a sum, adding up the coefficients of the ones
+    /// that are the same and the constants. Only for `Inputs::Finite`, for
+    /// `Inputs::Any` the terms stay in their order, a `0` is dropped and
+    /// `u + u` is `2 * u`, which is the same float whatever `u` is.
+    fn sum(&self, expr: Expr) -> Expr {
+        let span = expr.span;
+
+        if self.inputs == Inputs::Any {
+            return match expr.kind {
+                ExprKind::Binary { op, lhs, rhs } if numeric(&lhs) && numeric(&rhs) => {
+                    if is(&rhs, 0.0) {
+                        *lhs
+                    } else if op == Operator::Add && same(&lhs, &rhs) {
+                        self.term(2.0, *lhs, span)
+                    } else if is(&lhs, 0.0) && op == Operator::Add {
+                        *rhs
+                    } else if is(&lhs, 0.0) {
+                        self.term(-1.0, *rhs, span)
+                    } else {
+                        binary(op, *lhs, *rhs, span)
+                    }
+                }
+                kind => Expr::new(kind, span),
+            };
+        }
+
+        let mut terms = vec![];
+        collect(&expr, 1.0, &mut terms);
+
+        let mut constant = 0.0;
+        let mut like: Vec<(f64, Expr)> = vec![];
+
+        for (coefficient, factor) in terms {
+            match factor {
+                None => constant += coefficient,
+                Some(factor) => match like.iter_mut().find(|(_, f)| same(f, &factor)) {
+                    Some((sum, _)) => *sum += coefficient,
+                    None => like.push((coefficient, factor)),
+                },
+            }
+        }
+
+        if !literal(constant) || like.iter().any(|&(c, _)| !literal(c)) {
+            return expr;
+        }
+
+        // terms that cancel out are only dropped if they can't be NaN
+        like.retain(|(c, factor)| *c != 0.0 || !self.finite(factor));
+
+        let mut sum: Option<Expr> = None;
+
+        for (coefficient, factor) in like {
+            sum = Some(match sum {
+                None => self.term(coefficient, factor, span),
+                Some(sum) => {
+                    let (op, coefficient) = match coefficient < 0.0 {
+                        true => (Operator::Sub, -coefficient),
+                        false => (Operator::Add, coefficient),
+                    };
+                    binary(op, sum, self.term(coefficient, factor, span), span)
+                }
+            });
+        }
+
+        let number = |num| Expr::new(ExprKind::Number(num), span);
+
+        match sum {
+            None => number(constant),
+            Some(sum) if constant > 0.0 => binary(Operator::Add, sum, number(constant), span),
+            Some(sum) if constant < 0.0 => binary(Operator::Sub, sum, number(-constant), span),
+            Some(sum) => sum,
+        }
+    }
+
+    /// `coefficient * factor`, written the shortest way.
+    fn term(&self, coefficient: f64, factor: Expr, span: Span) -> Expr {
+        if coefficient == 1.0 {
+            factor
+        } else if coefficient == -1.0 {
+            Expr::new(
+                ExprKind::Unary {
+                    op: Operator::Neg,
+                    operand: Box::new(factor),
+                },
+                span,
+            )
+        } else if coefficient == 0.0 && self.finite(&factor) {
+            Expr::new(ExprKind::Number(0.0), span)
+        } else {
+            let coefficient = Expr::new(ExprKind::Number(coefficient), span);
+            binary(Operator::Mul, coefficient, factor, span)
+        }
+    }
+
+    /// The expression is always a finite number, so multiplying it by `0`
+    /// gives `0`.
+    fn finite(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Number(num) => num.is_finite(),
+            ExprKind::Variable(name) => self.inputs == Inputs::Finite && !is_boolean(name),
+            ExprKind::Unary {
+                op: Operator::Neg | Operator::Pos,
+                operand,
+            } => self.finite(operand),
+            _ => false,
+        }
+    }
+}
+
+/// The value of an expression without variables (but `true` and `false`),
+/// if it can be computed. This runs the expression on the VM so folding
+/// agrees with evaluation.
+fn constant(expr: &Expr) -> Option<Value> {
+    let program = Program::<f64>::compile(&expr.rpn(), &()).ok()?;
+
+    if !program.slots().iter().all(|name| is_boolean(name)) {
+        return None;
+    }
+
+    Vm::new().run(&program, |_| None).ok()
+}
+
+/// The number is written exactly by a literal, and can replace what it was
+/// computed from.
+fn literal(num: f64) -> bool {
+    // every integer up to 2 ^ 53 is a float, beyond that the digits a float
+    // is printed with are only the closest ones
+    num.abs() <= 9_007_199_254_740_992.0
+}
+
+fn boolean(b: bool, span: Span) -> Expr {
+    Expr::new(ExprKind::Variable(b.to_string()), span)
+}
+
+fn is_boolean(name: &str) -> bool {
+    name == "true" || name == "false"
+}
+
+/// Add the terms of a sum to `terms`, as their coefficient and what the
+/// coefficient multiplies (nothing for a constant).
+fn collect(expr: &Expr, sign: f64, terms: &mut Vec<(f64, Option<Expr>)>) {
+    match &expr.kind {
+        ExprKind::Binary {
+            op: op @ (Operator::Add | Operator::Sub),
+            lhs,
+            rhs,
+        } if numeric(lhs) && numeric(rhs) => {
+            collect(lhs, sign, terms);
+            let sign = if *op == Operator::Sub { -sign } else { sign };
+            collect(rhs, sign, terms);
+        }
+        _ => {
+            let (coefficient, factor) = term(expr);
+            terms.push((sign * coefficient, factor));
+        }
+    }
+}
+
+/// Split a product into its constant coefficient and the rest: `-(2 * x) * 3`
+/// is `-6` times `x`.
+fn term(expr: &Expr) -> (f64, Option<Expr>) {
+    let split = match &expr.kind {
+        ExprKind::Number(num) => (*num, None),
+        ExprKind::Unary {
+            op: Operator::Neg,
+            operand,
+        } if numeric(operand) => {
+            let (coefficient, factor) = term(operand);
+            (-coefficient, factor)
+        }
+        ExprKind::Binary {
+            op: Operator::Mul,
+            lhs,
+            rhs,
+        } if numeric(lhs) && numeric(rhs) => {
+            let (l, lhs) = term(lhs);
+            let (r, rhs) = term(rhs);
+
+            let factor = match (lhs, rhs) {
+                (Some(lhs), Some(rhs)) => Some(binary(Operator::Mul, lhs, rhs, expr.span)),
+                (lhs, rhs) => lhs.or(rhs),
+            };
+            (l * r, factor)
+        }
+        _ => (1.0, Some(expr.clone())),
+    };
+
+    match literal(split.0) {
+        true => split,
+        false => (1.0, Some(expr.clone())),
+    }
+}
+
+fn binary(op: Operator, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+    Expr::new(
+        ExprKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        span,
+    )
+}
+
+fn is(expr: &Expr, num: f64) -> bool {
+    matches!(expr.kind, ExprKind::Number(n) if n == num)
+}
+
+/// The expression computes a number, not a boolean. Variables are expected
+/// to hold numbers, but `true` and `false`.
+fn numeric(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Variable(name) => !is_boolean(name),
+        ExprKind::Number(_) | ExprKind::Quantity { .. } | ExprKind::Call { .. } => true,
+        ExprKind::Unary { op, .. } => *op != Operator::Not,
+        ExprKind::Binary { op, .. } => !matches!(
+            op,
+            Operator::Eq
+                | Operator::Ne
+                | Operator::Lt
+                | Operator::Le
+                | Operator::Gt
+                | Operator::Ge
+                | Operator::And
+                | Operator::Or
+        ),
+        ExprKind::Conditional {
+            then, otherwise, ..
+        } => numeric(then) && numeric(otherwise),
+    }
+}
+
+/// The expression can only compute a boolean, whatever the variables hold.
+fn logical(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Variable(name) => is_boolean(name),
+        ExprKind::Unary { op, .. } => *op == Operator::Not,
+        ExprKind::Binary { op, .. } => matches!(
+            op,
+            Operator::Eq
+                | Operator::Ne
+                | Operator::Lt
+                | Operator::Le
+                | Operator::Gt
+                | Operator::Ge
+                | Operator::And
+                | Operator::Or
+        ),
+        ExprKind::Conditional {
+            then, otherwise, ..
+        } => logical(then) && logical(otherwise),
+        _ => false,
+    }
+}
+
+/// The expression can't fail, so it can be dropped.
+fn safe(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_) => true,
+        ExprKind::Variable(name) => !is_boolean(name),
+        ExprKind::Unary {
+            op: Operator::Neg | Operator::Pos,
+            operand,
+        } => safe(operand),
+        _ => false,
+    }
+}
+
+/// The two trees are the same, wherever they come from.
+fn same(a: &Expr, b: &Expr) -> bool {
+    let all =
+        |a: &[Expr], b: &[Expr]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b));
+
+    match (&a.kind, &b.kind) {
+        (ExprKind::Number(a), ExprKind::Number(b)) => a == b,
+        (
+            ExprKind::Quantity { value, unit },
+            ExprKind::Quantity {
+                value: other,
+                unit: other_unit,
+            },
+        ) => value == other && unit == other_unit,
+        (ExprKind::Variable(a), ExprKind::Variable(b)) => a == b,
+        (
+            ExprKind::Call { name, args },
+            ExprKind::Call {
+                name: other,
+                args: others,
+            },
+        ) => name == other && all(args, others),
+        (
+            ExprKind::Unary { op, operand },
+            ExprKind::Unary {
+                op: other,
+                operand: others,
+            },
+        ) => op == other && same(operand, others),
+        (
+            ExprKind::Binary { op, lhs, rhs },
+            ExprKind::Binary {
+                op: other,
+                lhs: l,
+                rhs: r,
+            },
+        ) => op == other && same(lhs, l) && same(rhs, r),
+        (
+            ExprKind::Conditional {
+                cond,
+                then,
+                otherwise,
+            },
+            ExprKind::Conditional {
+                cond: c,
+                then: t,
+                otherwise: o,
+            },
+        ) => same(cond, c) && same(then, t) && same(otherwise, o),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Inputs;
+    use crate::bytecode::{Program, Vm};
+    use crate::{Calculator, Value};
+
+    fn simplify(expr: &str, inputs: Inputs) -> String {
+        let tree = Calculator::parse(Calculator::tokenize(expr).unwrap()).unwrap();
+        tree.simplify(inputs).to_string()
+    }
+
+    #[test]
+    fn folds_constants() {
+        for (expr, simplified) in [
+            ("1 + 2 * 3", "7"),
+            ("2 * 3 + x", "(+ 6 x)"),
+            ("max(1, 4, 2) * y", "(* 4 y)"),
+            ("x + sqrt(16)", "(+ x 4)"),
+            ("1 < 2 ? x : y", "x"),
+            ("1 > 2 || 3 == 4 ? x : y + 0", "y"),
+            ("x > 2 ? 1 + 1 : 3", "(? (> x 2) 2 3)"),
+            ("1 < 2", "true"),
+            ("!(1 < 2) || 3 > 4", "false"),
+            ("true ? x : y", "x"),
+            // booleans decide `&&` and `||`, or are dropped from them
+            ("x < 1 && 1 < 2", "(< x 1)"),
+            ("2 > 1 && x < 1", "(< x 1)"),
+            ("x < 1 || 1 > 2", "(< x 1)"),
+            ("1 > 2 && 1 / x > 2", "false"),
+            ("1 < 2 || 1 / x > 2", "true"),
+            ("x < 1 && 1 > 2", "(&& (< x 1) false)"),
+            // `x` must be a boolean, whatever `&&` says about it
+            ("x && 1 < 2", "(&& x true)"),
+            // only numbers a literal is exact for are folded
+            ("2 ^ 53", "9007199254740992"),
+            ("x + 2 ^ 1000", "(+ x (^ 2 1000))"),
+            ("1e10 * 1e10", "(* 10000000000 10000000000)"),
+            // errors and NaN are left for evaluation to report
+            ("x + 1 / 0", "(+ x (/ 1 0))"),
+            ("sqrt(-1) + 1", "(+ (sqrt -1) 1)"),
+        ] {
+            assert_eq!(simplify(expr, Inputs::Any), simplified, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn identities() {
+        for (expr, simplified) in [
+            ("x * 1", "x"),
+            ("1 * x", "x"),
+            ("x + 0", "x"),
+            ("0 + x", "x"),
+            ("x - 0", "x"),
+            ("0 - x", "(- x)"),
+            ("x / 1", "x"),
+            ("x ^ 1", "x"),
+            ("x ^ 0", "1"),
+            ("1 ^ x", "1"),
+            ("--x", "x"),
+            ("+x", "x"),
+            ("-(2 * x) * 3", "(* -6 x)"),
+            ("x * (y * 1)", "(* x y)"),
+            // booleans can't be multiplied, that error must stay
+            ("(x > y) * 1", "(* (> x y) 1)"),
+            ("(x > y) + 0", "(+ (> x y) 0)"),
+        ] {
+            assert_eq!(simplify(expr, Inputs::Any), simplified, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn nan_semantics() {
+        // `x` may be NaN, `NaN * 0` is NaN
+        assert_eq!(simplify("x * 0", Inputs::Any), "(* 0 x)");
+        assert_eq!(simplify("x - x", Inputs::Any), "(- x x)");
+        assert_eq!(simplify("x * 0", Inputs::Finite), "0");
+        assert_eq!(simplify("0 * -x", Inputs::Finite), "0");
+        assert_eq!(simplify("x - x + y", Inputs::Finite), "y");
+
+        // `sqrt(x)` may be NaN whatever `x` is, `1 / x` may be an error
+        assert_eq!(simplify("sqrt(x) * 0", Inputs::Finite), "(* 0 (sqrt x))");
+        assert_eq!(simplify("(1 / x) ^ 0", Inputs::Finite), "(^ (/ 1 x) 0)");
+    }
+
+    #[test]
+    fn collects_like_terms() {
+        for (expr, simplified) in [
+            ("x + x", "(* 2 x)"),
+            ("2 * x + 3 * x", "(* 5 x)"),
+            ("2 * x + y - x + 1 + 2", "(+ (+ x y) 3)"),
+            ("x - 2 * y - 1", "(- (- x (* 2 y)) 1)"),
+            ("-x + 3 * x", "(* 2 x)"),
+            ("max(a, b) * 2 + max(a, b)", "(* 3 (max a b))"),
+            ("x * y + y * x", "(+ (* x y) (* y x))"),
+            ("1 - x - 1", "(- x)"),
+        ] {
+            assert_eq!(simplify(expr, Inputs::Finite), simplified, "{}", expr);
+        }
+
+        // infinite or huge inputs don't add up in any order
+        for (expr, simplified) in [
+            ("x + x", "(* 2 x)"),
+            ("2 * x - x", "(- (* 2 x) x)"),
+            ("y + y - y", "(- (* 2 y) y)"),
+            ("1 + x + 2", "(+ (+ 1 x) 2)"),
+            ("0 - x + 0", "(- x)"),
+        ] {
+            assert_eq!(simplify(expr, Inputs::Any), simplified, "{}", expr);
+        }
+    }
+
+    /// The simplified expression computes the same as the original one, for
+    /// values of the variables including NaN, infinities and numbers that
+    /// overflow when added up.
+    #[test]
+    fn keeps_values() {
+        let run = |tree: &crate::ast::Expr, row: &[f64]| {
+            let program = Program::<f64>::compile(&tree.rpn(), &()).unwrap();
+            let load = |slot: usize| {
+                let i = ["x", "y", "z"]
+                    .iter()
+                    .position(|&v| v == program.slots()[slot])?;
+                Some(Value::Number(row[i]))
+            };
+            Vm::new().run(&program, load).map_err(|e| e.node)
+        };
+
+        let rows = [
+            [0.0, 1.0, 2.0],
+            [-1.5, 3.0, 0.5],
+            [f64::NAN, 2.0, -4.0],
+            [f64::INFINITY, 1e308, -1e308],
+            [1e308, f64::NEG_INFINITY, 1e308],
+        ];
+
+        for expr in [
+            "x * 1 + 0 * y - z ^ 0",
+            "2 * x + y - x + 1 + 2 - z",
+            "(x - x) * 0 + y / 1",
+            "1 < 2 ? sqrt(x) * 0 : y",
+            "-(2 * x) * 3 + 6 * x",
+            "x ^ 1 + 1 ^ y + (x > y) * 1",
+            "z + 1 / (y - y) * 0",
+            "max(x, y) + max(x, y) - 2 * max(x, y)",
+            "2 * x - x",
+            "y + y - y",
+            "x + y - x + z",
+            "x < y && 1 < 2 || z > 1 && 2 < 1",
+        ] {
+            let tree = Calculator::parse(Calculator::tokenize(expr).unwrap()).unwrap();
+            let simplified = tree.clone().simplify(Inputs::Any);
+
+            // simplifying again changes nothing
+            assert_eq!(
+                simplified.clone().simplify(Inputs::Any).to_string(),
+                simplified.to_string()
+            );
+
+            for row in rows {
+                match (run(&tree, &row), run(&simplified, &row)) {
+                    (Ok(Value::Number(a)), Ok(Value::Number(b))) if a.is_nan() => {
+                        assert!(b.is_nan(), "{} at {:?}: NaN, {}", expr, row, b)
+                    }
+                    (a, b) => assert_eq!(a, b, "{} at {:?}", expr, row),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,821 @@
+/*
+# Solver
+
+Finds the value of a variable that makes an expression zero, numerically. Two
+forms are understood, each on a line of its own:
+
+- `solve(<lhs> = <rhs>, <x>[, <guess>])` looks for a root of `lhs - rhs`
+  starting from `guess` (or the value `x` already has, or `1`). It uses
+  Newton's method when the expression can be differentiated (see
+  `derivative.rs`) and falls back to the secant method when it can't, or when
+  Newton gives up;
+- `root(<expr>, <x>, <a>, <b>)` bisects between `a` and `b`, where `expr`
+  must change sign. Slower, but it can't miss.
+
+All of them stop once a step is smaller than `Tolerance::step` (relative to
+`x`) or the expression is closer to `0` than `Tolerance::residual`. When they
+don't get there, the `SolveError` says why: no sign change, a flat slope,
+running out of iterations...
+
+A residual of `0` proves nothing when the terms are so big that rounding
+swallowed what they differ by: past `2 ^ 53`, `x - (x + 1)` is `0`. Newton
+and the secant only take a root if the expression still changes right next
+to it, the bisection has its sign change to show for it.
+
+- See: https://en.wikipedia.org/wiki/Newton%27s_method
+- See: https://en.wikipedia.org/wiki/Secant_method
+- See: https://en.wikipedia.org/wiki/Bisection_method
+*/
+
+use std::fmt;
+
+use crate::ast::{Expr, ExprKind};
+use crate::bytecode::{Program, Vm};
+use crate::diagnostic::Errors;
+use crate::env::Environment;
+use crate::token::{Operator, Span, Spanned, Token};
+use crate::{Calculator, Error, EvalError, Value};
+
+const SOLVE: &str = "solve(<lhs> = <rhs>, <variable>[, <guess>])";
+const ROOT: &str = "root(<expression>, <variable>, <a>, <b>)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Bisection,
+    Newton,
+    Secant,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Bisection => write!(f, "bisection"),
+            Method::Newton => write!(f, "newton"),
+            Method::Secant => write!(f, "secant"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// When to stop looking.
+pub struct Tolerance {
+    /// A step smaller than this, relative to `x` (or absolute below `1`),
+    /// means we've converged
+    pub step: f64,
+    /// A value of the expression at least this close to `0` is a root
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            step: 1e-12,
+            residual: 1e-12,
+            iterations: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub root: f64,
+    /// The value of the expression at `root`
+    pub residual: f64,
+    pub method: Method,
+    pub iterations: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Why no root was found. They are reported as `Spanned<SolveError>`,
+/// pointing at the part of the expression that failed to evaluate or at the
+/// whole `solve` or `root` call.
+pub enum SolveError {
+    /// The expression has the same sign at both ends of the bracket, there
+    /// may be no root in between
+    NoSignChange { a: f64, fa: f64, b: f64, fb: f64 },
+    /// The slope is `0` (or not a number), the next step goes nowhere
+    FlatSlope { method: Method, x: f64 },
+    /// The steps ran away to infinity
+    Diverged { method: Method, x: f64 },
+    NoConvergence {
+        method: Method,
+        iterations: usize,
+        x: f64,
+        residual: f64,
+    },
+    /// The expression can't be evaluated at `x` (or the guess or the bounds
+    /// can't be evaluated at all)
+    Eval { error: EvalError, x: Option<f64> },
+    /// The expression doesn't use the variable, no value of it is a root
+    MissingVariable(String),
+    /// The expression is `0` at `x` but doesn't change around it either, all
+    /// that's left of it there is rounding
+    LostPrecision { method: Method, x: f64 },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::NoSignChange { a, fa, b, fb } => write!(
+                f,
+                "no sign change between f({}) = {} and f({}) = {}",
+                show(*a),
+                show(*fa),
+                show(*b),
+                show(*fb)
+            ),
+            SolveError::FlatSlope { method, x } => {
+                write!(f, "{} stopped on a flat slope at {}", method, show(*x))
+            }
+            SolveError::Diverged { method, x } => {
+                write!(f, "{} diverged after {}", method, show(*x))
+            }
+            SolveError::NoConvergence {
+                method,
+                iterations,
+                x,
+                residual,
+            } => write!(
+                f,
+                "{} didn't converge in {}, f({}) = {}",
+                method,
+                count(*iterations),
+                show(*x),
+                show(*residual)
+            ),
+            SolveError::Eval { error, x: Some(x) } => write!(f, "{} at {}", error, show(*x)),
+            SolveError::Eval { error, x: None } => write!(f, "{}", error),
+            SolveError::MissingVariable(var) => {
+                write!(f, "the expression doesn't depend on `{}`", var)
+            }
+            SolveError::LostPrecision { method, x } => write!(
+                f,
+                "{} lost the expression to rounding at {}",
+                method,
+                show(*x)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// `x` as the solver reports it, in scientific notation when it's too big for
+/// all of its digits to be written.
+pub fn show(x: f64) -> String {
+    match x.abs() >= 1e16 {
+        true => format!("{:e}", x),
+        false => x.to_string(),
+    }
+}
+
+/// `1 iteration`, `5 iterations`
+pub fn count(iterations: usize) -> String {
+    match iterations {
+        1 => "1 iteration".to_string(),
+        n => format!("{} iterations", n),
+    }
+}
+
+/// Where to start looking for a root.
+#[derive(Debug, Clone)]
+pub enum Search {
+    /// Newton or secant, from a guess if one was given (in RPN)
+    From(Option<Vec<Spanned<Token>>>),
+    /// Bisection between two bounds (in RPN)
+    Between(Vec<Spanned<Token>>, Vec<Spanned<Token>>),
+}
+
+#[derive(Debug, Clone)]
+/// A `solve` or `root` line, parsed.
+pub struct Problem {
+    /// `lhs - rhs` for `solve`, the expression itself for `root`
+    pub expr: Expr,
+    pub var: String,
+    pub search: Search,
+    /// The whole call
+    pub span: Span,
+}
+
+impl Problem {
+    /// Recognize a line that is a call to `solve` or `root`. `None` means the
+    /// line is something else, to parse as a statement.
+    pub fn parse(tokens: &[Spanned<Token>]) -> Option<Result<Problem, Errors>> {
+        let usage = match tokens.first().map(|t| &t.node) {
+            Some(Token::Func(name)) if name == "solve" => SOLVE,
+            Some(Token::Func(name)) if name == "root" => ROOT,
+            _ => return None,
+        };
+
+        let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+        let invalid = || Errors::from(Spanned::new(Error::InvalidProblem(usage), span));
+
+        let Some(args) = arguments(&tokens[1..]) else {
+            return Some(Err(invalid()));
+        };
+
+        Some(Self::build(usage, args, span).and_then(|problem| problem.ok_or_else(invalid)))
+    }
+
+    /// `None` when the arguments don't match the usage.
+    fn build(
+        usage: &str,
+        mut args: Vec<Vec<Spanned<Token>>>,
+        span: Span,
+    ) -> Result<Option<Problem>, Errors> {
+        let var = match args.get(1).map(|arg| arg.as_slice()) {
+            Some(
+                [Spanned {
+                    node: Token::Ident(var),
+                    ..
+                }],
+            ) => var.clone(),
+            _ => return Ok(None),
+        };
+
+        let (expr, search) = match (usage, args.len()) {
+            (SOLVE, 2 | 3) => {
+                let guess = match args.len() {
+                    3 => Some(rpn(args.pop().unwrap_or_default())?),
+                    _ => None,
+                };
+
+                let mut lhs = args.swap_remove(0);
+                let Some(assign) = lhs.iter().position(|t| t.node == Token::Assign) else {
+                    return Ok(None);
+                };
+                let rhs = lhs.split_off(assign + 1);
+                lhs.pop();
+
+                let (lhs, rhs) = (tree(lhs)?, tree(rhs)?);
+                let span = lhs.span.to(rhs.span);
+                let kind = ExprKind::Binary {
+                    op: Operator::Sub,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                };
+
+                (Expr::new(kind, span), Search::From(guess))
+            }
+            (ROOT, 4) => {
+                let b = rpn(args.pop().unwrap_or_default())?;
+                let a = rpn(args.pop().unwrap_or_default())?;
+
+                (tree(args.swap_remove(0))?, Search::Between(a, b))
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Problem {
+            expr,
+            var,
+            search,
+            span,
+        }))
+    }
+
+    /// Solve with the variables of `env`, the variable solved for excepted.
+    pub fn solve(
+        &self,
+        env: &Environment<Value>,
+        tolerance: &Tolerance,
+    ) -> Result<Solution, Spanned<SolveError>> {
+        let equation = Equation::new(&self.expr, &self.var, env, self.span)?;
+
+        match &self.search {
+            Search::Between(a, b) => {
+                let (a, b) = (constant(a, env)?, constant(b, env)?);
+                equation.bisection(a, b, tolerance)
+            }
+            Search::From(guess) => {
+                let start = match guess {
+                    Some(guess) => constant(guess, env)?,
+                    None => match env.get(&self.var) {
+                        Some(Value::Number(x)) if x.is_finite() => x,
+                        _ => 1.0,
+                    },
+                };
+
+                // the secant is only the last resort, its error is the one
+                // that's reported
+                match equation.derivative {
+                    Some(_) => equation
+                        .newton(start, tolerance)
+                        .or_else(|_| equation.secant(start, tolerance)),
+                    None => equation.secant(start, tolerance),
+                }
+            }
+        }
+    }
+}
+
+/// Split the tokens of `(a, b, ...)` into the tokens of each argument. `None`
+/// if the brackets don't surround everything, or an argument is empty.
+fn arguments(tokens: &[Spanned<Token>]) -> Option<Vec<Vec<Spanned<Token>>>> {
+    let (first, inside) = tokens.split_first()?;
+    let (last, inside) = inside.split_last()?;
+
+    if first.node != Token::Bracket('(') || last.node != Token::Bracket(')') {
+        return None;
+    }
+
+    let mut args = vec![vec![]];
+    let mut depth = 0;
+
+    for token in inside {
+        match token.node {
+            Token::Bracket('(') => depth += 1,
+            // the opening bracket is closed before the end
+            Token::Bracket(')') if depth == 0 => return None,
+            Token::Bracket(')') => depth -= 1,
+            Token::Comma if depth == 0 => {
+                args.push(vec![]);
+                continue;
+            }
+            _ => {}
+        }
+
+        args.last_mut()?.push(token.clone());
+    }
+
+    match args.iter().any(|arg| arg.is_empty()) {
+        true => None,
+        false => Some(args),
+    }
+}
+
+fn rpn(tokens: Vec<Spanned<Token>>) -> Result<Vec<Spanned<Token>>, Errors> {
+    if let Some(assign) = tokens.iter().find(|t| t.node == Token::Assign) {
+        return Err(Spanned::new(Error::InvalidAssignment, assign.span).into());
+    }
+
+    Calculator::expression(tokens)
+}
+
+fn tree(tokens: Vec<Spanned<Token>>) -> Result<Expr, Errors> {
+    Ok(Expr::from_rpn(rpn(tokens)?)?)
+}
+
+/// The value of a guess or a bound.
+fn constant(rpn: &[Spanned<Token>], env: &Environment<Value>) -> Result<f64, Spanned<SolveError>> {
+    let eval = |error: Spanned<EvalError>| error.map(|error| SolveError::Eval { error, x: None });
+
+    let value = Calculator::evaluate(rpn, env).map_err(eval)?;
+
+    let span = rpn
+        .iter()
+        .map(|t| t.span)
+        .reduce(Span::to)
+        .unwrap_or(Span::new(0, 0));
+    value.number().map_err(|e| eval(Spanned::new(e, span)))
+}
+
+/// An expression compiled once to be evaluated for many values of `var`,
+/// along with its derivative when it has one.
+pub struct Equation<'a> {
+    program: Program<f64>,
+    derivative: Option<Program<f64>>,
+    var: &'a str,
+    env: &'a Environment<Value>,
+    /// Where the failures that aren't in the expression are reported
+    span: Span,
+}
+
+impl<'a> Equation<'a> {
+    pub fn new(
+        expr: &Expr,
+        var: &'a str,
+        env: &'a Environment<Value>,
+        span: Span,
+    ) -> Result<Self, Spanned<SolveError>> {
+        let compile = |expr: &Expr| Program::compile(&expr.rpn(), &());
+
+        let program = compile(expr)
+            .map_err(|error| error.map(|error| SolveError::Eval { error, x: None }))?;
+
+        if !program.slots().iter().any(|name| name == var) {
+            let error = SolveError::MissingVariable(var.to_string());
+            return Err(Spanned::new(error, span));
+        }
+
+        let derivative = expr.derivative(var).ok().and_then(|d| compile(&d).ok());
+
+        Ok(Equation {
+            program,
+            derivative,
+            var,
+            env,
+            span,
+        })
+    }
+
+    fn fail(&self, error: SolveError) -> Spanned<SolveError> {
+        Spanned::new(error, self.span)
+    }
+
+    /// The value of `program` for `x`. NaN is an error, there's no way to
+    /// tell which side of the root it's on.
+    fn eval(&self, program: &Program<f64>, x: f64) -> Result<f64, Spanned<SolveError>> {
+        let load = |slot: usize| match program.slots()[slot].as_str() {
+            name if name == self.var => Some(Value::Number(x)),
+            name => self.env.get(name),
+        };
+
+        let at = |error| SolveError::Eval { error, x: Some(x) };
+
+        match Vm::new()
+            .run(program, load)
+            .and_then(|value| value.number().map_err(|e| Spanned::new(e, self.span)))
+        {
+            Ok(value) if value.is_nan() => Err(self.fail(at(EvalError::NotANumber))),
+            Ok(value) => Ok(value),
+            Err(error) => Err(error.map(at)),
+        }
+    }
+
+    fn f(&self, x: f64) -> Result<f64, Spanned<SolveError>> {
+        self.eval(&self.program, x)
+    }
+
+    fn converged(&self, step: f64, x: f64, tolerance: &Tolerance) -> bool {
+        step.abs() <= tolerance.step * x.abs().max(1.0)
+    }
+
+    /// The root newton or secant stopped at, if the expression still
+    /// changes a step away from it: where it doesn't, rounding may have
+    /// swallowed everything but a `0` (see `solver.rs`).
+    fn root(
+        &self,
+        method: Method,
+        x: f64,
+        residual: f64,
+        iterations: usize,
+        tolerance: &Tolerance,
+    ) -> Result<Solution, Spanned<SolveError>> {
+        let step = tolerance.step * x.abs().max(1.0);
+
+        if self.f(x - step)? == residual && self.f(x + step)? == residual {
+            return Err(self.fail(SolveError::LostPrecision { method, x }));
+        }
+
+        Ok(Solution {
+            root: x,
+            residual,
+            method,
+            iterations,
+        })
+    }
+
+    /// Halve `[a, b]` until it's small enough, keeping the half where the
+    /// sign changes.
+    pub fn bisection(
+        &self,
+        mut a: f64,
+        mut b: f64,
+        tolerance: &Tolerance,
+    ) -> Result<Solution, Spanned<SolveError>> {
+        let solution = |root, residual, iterations| Solution {
+            root,
+            residual,
+            method: Method::Bisection,
+            iterations,
+        };
+
+        let (fa, fb) = (self.f(a)?, self.f(b)?);
+
+        if fa == 0.0 || fb == 0.0 {
+            let (root, residual) = if fa == 0.0 { (a, fa) } else { (b, fb) };
+            return Ok(solution(root, residual, 0));
+        }
+
+        if fa.signum() == fb.signum() {
+            return Err(self.fail(SolveError::NoSignChange { a, fa, b, fb }));
+        }
+
+        let mut fa = fa;
+
+        for i in 1..=tolerance.iterations {
+            let m = a + (b - a) / 2.0;
+            let fm = self.f(m)?;
+
+            if fm.abs() <= tolerance.residual || self.converged(b - a, m, tolerance) {
+                return Ok(solution(m, fm, i));
+            }
+
+            if fm.signum() == fa.signum() {
+                (a, fa) = (m, fm);
+            } else {
+                b = m;
+            }
+        }
+
+        let x = a + (b - a) / 2.0;
+        Err(self.fail(SolveError::NoConvergence {
+            method: Method::Bisection,
+            iterations: tolerance.iterations,
+            x,
+            residual: self.f(x)?,
+        }))
+    }
+
+    /// Follow the tangent: `x - f(x) / f'(x)`. Without a derivative this is a
+    /// flat slope right away.
+    pub fn newton(
+        &self,
+        mut x: f64,
+        tolerance: &Tolerance,
+    ) -> Result<Solution, Spanned<SolveError>> {
+        let method = Method::Newton;
+        let derivative = self.derivative.as_ref();
+
+        for i in 0..tolerance.iterations {
+            let fx = self.f(x)?;
+
+            if fx.abs() <= tolerance.residual {
+                return self.root(method, x, fx, i, tolerance);
+            }
+
+            let slope = match derivative {
+                Some(derivative) => self.eval(derivative, x)?,
+                None => 0.0,
+            };
+
+            if slope == 0.0 || !slope.is_finite() {
+                return Err(self.fail(SolveError::FlatSlope { method, x }));
+            }
+
+            let step = fx / slope;
+            let next = x - step;
+
+            if !next.is_finite() {
+                return Err(self.fail(SolveError::Diverged { method, x }));
+            }
+
+            x = next;
+
+            if self.converged(step, x, tolerance) {
+                return self.root(method, x, self.f(x)?, i + 1, tolerance);
+            }
+        }
+
+        Err(self.fail(SolveError::NoConvergence {
+            method,
+            iterations: tolerance.iterations,
+            x,
+            residual: self.f(x)?,
+        }))
+    }
+
+    /// Newton with the slope measured between the last two points instead of
+    /// computed, the second point starts right next to `x`.
+    pub fn secant(&self, x: f64, tolerance: &Tolerance) -> Result<Solution, Spanned<SolveError>> {
+        let method = Method::Secant;
+
+        let (mut x0, mut x1) = (x, x + 1e-4 * x.abs().max(1.0));
+        let (mut f0, mut f1) = (self.f(x0)?, self.f(x1)?);
+
+        for i in 0..tolerance.iterations {
+            if f1.abs() <= tolerance.residual {
+                return self.root(method, x1, f1, i, tolerance);
+            }
+
+            let slope = (f1 - f0) / (x1 - x0);
+
+            if slope == 0.0 || !slope.is_finite() {
+                return Err(self.fail(SolveError::FlatSlope { method, x: x1 }));
+            }
+
+            let step = f1 / slope;
+            let next = x1 - step;
+
+            if !next.is_finite() {
+                return Err(self.fail(SolveError::Diverged { method, x: x1 }));
+            }
+
+            (x0, f0) = (x1, f1);
+            x1 = next;
+            f1 = self.f(x1)?;
+
+            if self.converged(step, x1, tolerance) {
+                return self.root(method, x1, f1, i + 1, tolerance);
+            }
+        }
+
+        Err(self.fail(SolveError::NoConvergence {
+            method,
+            iterations: tolerance.iterations,
+            x: x1,
+            residual: f1,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Equation, Method, Problem, SolveError, Tolerance};
+    use crate::env::Environment;
+    use crate::token::Span;
+    use crate::{Calculator, Error, EvalError, Value};
+
+    fn problem(line: &str) -> Result<Problem, Error> {
+        let tokens = Calculator::tokenize(line).unwrap();
+        let problem = Problem::parse(&tokens).unwrap();
+        problem.map_err(|e| e.into_iter().next().unwrap().node)
+    }
+
+    fn solve(line: &str, env: &Environment<Value>) -> Result<(f64, Method), SolveError> {
+        problem(line)
+            .unwrap()
+            .solve(env, &Tolerance::default())
+            .map(|s| (s.root, s.method))
+            .map_err(|e| e.node)
+    }
+
+    #[test]
+    fn finds_roots() {
+        let mut env = Environment::new();
+        env.set("rate", Value::Number(0.05));
+
+        let (root, method) = solve("solve(x ^ 2 = 2, x)", &env).unwrap();
+        assert!((root - 2f64.sqrt()).abs() < 1e-12);
+        assert_eq!(method, Method::Newton);
+
+        // the other root, from a guess
+        let (root, _) = solve("solve(x ^ 2 = 2, x, -3)", &env).unwrap();
+        assert!((root + 2f64.sqrt()).abs() < 1e-12);
+
+        let (root, method) = solve("root(cos(x) - x, x, 0, 1)", &env).unwrap();
+        assert!((root - 0.7390851332151607).abs() < 1e-11);
+        assert_eq!(method, Method::Bisection);
+
+        // inverting a formula: how many years to double at 5%?
+        let (years, _) = solve("solve(1000 * (1 + rate) ^ n = 2000, n)", &env).unwrap();
+        assert!((years - 2f64.ln() / 1.05f64.ln()).abs() < 1e-9);
+
+        // the tangent is flat at the guess, so it's the secant
+        let (root, method) = solve("solve(x ^ 2 = 4, x, 0)", &env).unwrap();
+        assert!((root.abs() - 2.0).abs() < 1e-12);
+        assert_eq!(method, Method::Secant);
+    }
+
+    #[test]
+    fn starts_from_the_variable() {
+        let mut env = Environment::new();
+        env.set("x", Value::Number(-1.0));
+
+        let (root, _) = solve("solve(x ^ 2 = 2, x)", &env).unwrap();
+        assert!((root + 2f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn methods() {
+        let env = Environment::new();
+        let expr = Calculator::parse(Calculator::tokenize("x ^ 3 - 2 * x - 5").unwrap()).unwrap();
+        let equation = Equation::new(&expr, "x", &env, Span::new(0, 0)).unwrap();
+        let tolerance = Tolerance::default();
+
+        let root = 2.0945514815423265;
+        for solution in [
+            equation.newton(2.0, &tolerance),
+            equation.secant(2.0, &tolerance),
+            equation.bisection(2.0, 3.0, &tolerance),
+        ] {
+            let solution = solution.unwrap();
+            assert!((solution.root - root).abs() < 1e-11, "{:?}", solution);
+        }
+
+        // newton needs a lot fewer iterations than bisection
+        let newton = equation.newton(2.0, &tolerance).unwrap();
+        let bisection = equation.bisection(2.0, 3.0, &tolerance).unwrap();
+        assert!(newton.iterations < 10 && bisection.iterations > 30);
+
+        let tolerance = Tolerance {
+            iterations: 5,
+            ..tolerance
+        };
+        assert!(matches!(
+            equation.bisection(2.0, 3.0, &tolerance).map_err(|e| e.node),
+            Err(SolveError::NoConvergence {
+                method: Method::Bisection,
+                iterations: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn failures() {
+        let env = Environment::new();
+
+        assert!(matches!(
+            solve("root(x ^ 2 + 1, x, -1, 1)", &env),
+            Err(SolveError::NoSignChange { a, b, .. }) if a == -1.0 && b == 1.0
+        ));
+        assert_eq!(
+            solve("root(sqrt(x) - 1, x, -4, 4)", &env),
+            Err(SolveError::Eval {
+                error: EvalError::NotANumber,
+                x: Some(-4.0)
+            })
+        );
+        assert_eq!(
+            solve("root(x - 1, x, 1 / 0, 4)", &env),
+            Err(SolveError::Eval {
+                error: EvalError::DivisionByZero,
+                x: None
+            })
+        );
+        assert!(solve("solve(x ^ 2 = -1, x, 0)", &env).is_err());
+        assert_eq!(
+            solve("solve(x ^ 2 = 4, y)", &env),
+            Err(SolveError::MissingVariable("y".into()))
+        );
+
+        // far enough `x + 1` is `x`, but that doesn't make it a root
+        assert!(matches!(
+            solve("solve(x = x + 1, x)", &env),
+            Err(SolveError::LostPrecision {
+                method: Method::Secant,
+                ..
+            })
+        ));
+        assert_eq!(
+            SolveError::LostPrecision {
+                method: Method::Secant,
+                x: -2028240960364942800000000000.0
+            }
+            .to_string(),
+            "secant lost the expression to rounding at -2.0282409603649428e27"
+        );
+
+        // errors point inside the expression
+        let tokens = Calculator::tokenize("solve(y = 1 / (x - 1), x)").unwrap();
+        let error = Problem::parse(&tokens)
+            .unwrap()
+            .unwrap()
+            .solve(&env, &Tolerance::default())
+            .unwrap_err();
+        assert_eq!(error.span, Span::new(6, 7));
+        assert_eq!(
+            error.node,
+            SolveError::Eval {
+                error: EvalError::UnknownVariable("y".into()),
+                x: Some(1.0)
+            }
+        );
+    }
+
+    #[test]
+    fn flat_slope() {
+        let env = Environment::new();
+        let expr = Calculator::parse(Calculator::tokenize("x ^ 2 + 1").unwrap()).unwrap();
+        let equation = Equation::new(&expr, "x", &env, Span::new(0, 0)).unwrap();
+
+        assert_eq!(
+            equation
+                .newton(0.0, &Tolerance::default())
+                .map_err(|e| e.node),
+            Err(SolveError::FlatSlope {
+                method: Method::Newton,
+                x: 0.0
+            })
+        );
+    }
+
+    #[test]
+    fn parses_problems() {
+        let tokens = Calculator::tokenize("1 + solve(x = 1, x)").unwrap();
+        assert!(Problem::parse(&tokens).is_none());
+
+        for line in [
+            "solve(x ^ 2, x)",
+            "solve(x = 1, 2)",
+            "solve(x = 1)",
+            "solve(x = 1, x, 1, 2)",
+            "root(x, x, 1)",
+            "root(x, x + 1, 1, 2)",
+            "solve(x = 1, x) + 1",
+            "solve(x = 1, , x)",
+        ] {
+            assert!(
+                matches!(problem(line), Err(Error::InvalidProblem(_))),
+                "{}",
+                line
+            );
+        }
+
+        assert!(matches!(
+            problem("solve(x = 1 = 2, x)"),
+            Err(Error::InvalidAssignment)
+        ));
+        assert!(matches!(
+            problem("root(x = 1, x, 1, 2)"),
+            Err(Error::InvalidAssignment)
+        ));
+
+        let problem = problem("solve(2 * x = max(x, 3), x, 4)").unwrap();
+        assert_eq!(problem.expr.to_string(), "(- (* 2 x) (max x 3))");
+        assert_eq!(problem.var, "x");
+    }
+}
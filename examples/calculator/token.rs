@@ -0,0 +1,265 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Define our possible valid operations
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Exponentiation `^`, the only right associative binary operator
+    /// (`2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`)
+    Pow,
+    /// Prefix `-` (unary minus), e.g. `-3` or `2 * -(1 + 1)`
+    Neg,
+    /// Prefix `+` (unary plus), e.g. `+3`
+    Pos,
+    /// Prefix `!`, the logical negation
+    Not,
+    /// Prefix `~`, flips all the bits
+    BitNot,
+    /// Postfix `!`, the factorial: `3!` is `fact(3)`
+    Fact,
+    /// Postfix `%`, hundredths: `50%` is `0.5`
+    Percent,
+    BitAnd,
+    BitOr,
+    /// Written `xor`, as `^` is the exponentiation
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `&&`, the right hand side doesn't matter when the left one is false
+    And,
+    /// `||`, the right hand side doesn't matter when the left one is true
+    Or,
+    /// The conditional `cond ? then : otherwise`, the only operator with
+    /// three operands. It's written as two tokens (`?` and `:`) which
+    /// `Calculator::expression` joins into this one.
+    Cond,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// Which side an operator groups from when it's chained with operators of
+/// the same precedence (`8 - 3 - 2` is `(8 - 3) - 2` because `-` is `Left`).
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+impl Operator {
+    /// The binding power of the operator. Higher binds tighter.
+    ///
+    /// | operator              | precedence | associativity |
+    /// |-----------------------|------------|---------------|
+    /// | `? :`                 | 1          | right         |
+    /// | `\|\|`                | 2          | left          |
+    /// | `&&`                  | 3          | left          |
+    /// | `\|`                  | 4          | left          |
+    /// | `xor`                 | 5          | left          |
+    /// | `&`                   | 6          | left          |
+    /// | `==` `!=`             | 7          | left          |
+    /// | `<` `<=` `>` `>=`     | 8          | left          |
+    /// | `<<` `>>`             | 9          | left          |
+    /// | `+` `-`               | 10         | left          |
+    /// | `*` `/`               | 11         | left          |
+    /// | unary `-` `+` `!` `~` | 12         | right         |
+    /// | `^`                   | 13         | right         |
+    /// | postfix `!` `%`       | 14         | left          |
+    ///
+    /// Exponentiation binds tighter than unary minus, so `-2 ^ 2` is `-(2 ^ 2)`,
+    /// and postfix operators tighter than anything, so `2 ^ 3!` is `2 ^ 6`.
+    /// The rest follows C, so `a > 1 && b > 1 ? x : y` needs no brackets (and
+    /// `x & 0xf == 0` needs some, like it does in C).
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Cond => 1,
+            Operator::Or => 2,
+            Operator::And => 3,
+            Operator::BitOr => 4,
+            Operator::BitXor => 5,
+            Operator::BitAnd => 6,
+            Operator::Eq | Operator::Ne => 7,
+            Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge => 8,
+            Operator::Shl | Operator::Shr => 9,
+            Operator::Add | Operator::Sub => 10,
+            Operator::Mul | Operator::Div => 11,
+            Operator::Neg | Operator::Pos | Operator::Not | Operator::BitNot => 12,
+            Operator::Pow => 13,
+            Operator::Fact | Operator::Percent => 14,
+        }
+    }
+
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            Operator::Pow | Operator::Cond => Associativity::Right,
+            op if op.is_prefix() => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+
+    /// Prefix operators take a single operand that comes after them.
+    pub fn is_prefix(&self) -> bool {
+        matches!(
+            self,
+            Operator::Neg | Operator::Pos | Operator::Not | Operator::BitNot
+        )
+    }
+
+    /// Postfix operators take a single operand that comes before them.
+    pub fn is_postfix(&self) -> bool {
+        matches!(self, Operator::Fact | Operator::Percent)
+    }
+
+    /// Operators with a single operand, prefix or postfix.
+    pub fn is_unary(&self) -> bool {
+        self.is_prefix() || self.is_postfix()
+    }
+
+    /// Whether a comparison operator holds for operands that compare as
+    /// `ordering`. Unordered operands (a NaN) are only ever `!=`.
+    pub fn compares(&self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Operator::Eq => ordering == Some(Ordering::Equal),
+            Operator::Ne => ordering != Some(Ordering::Equal),
+            Operator::Lt => ordering == Some(Ordering::Less),
+            Operator::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Operator::Gt => ordering == Some(Ordering::Greater),
+            Operator::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            _ => false,
+        }
+    }
+
+    /// Whether `self`, sitting on top of the operator stack, has to be moved
+    /// to the output queue before `incoming` is pushed.
+    pub fn pops_before(&self, incoming: &Operator) -> bool {
+        match incoming.associativity() {
+            Associativity::Left => self.precedence() >= incoming.precedence(),
+            Associativity::Right => self.precedence() > incoming.precedence(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Define our possible valid Token(s)
+pub enum Token {
+    /// A numeric literal, as written (`3.14`, `1e-3`)
+    Number(String),
+    /// A variable name such as `x` or `rate_2`
+    Ident(String),
+    Op(Operator),
+    Bracket(char),
+    /// The `=` in `let x = 5`
+    Assign,
+    /// A name followed by `(`, as in `sqrt(2)`
+    Func(String),
+    /// The `,` between the arguments of a function call
+    Comma,
+    /// The `?` of a conditional, `Calculator::expression` turns it (and its
+    /// `:`) into an `Operator::Cond`
+    Question,
+    /// The `:` of a conditional
+    Colon,
+    /// `to <unit>` at the end of a line, see `units.rs`. It follows its
+    /// operand in RPN.
+    Convert(String),
+    /// A function call in RPN: the name and how many arguments it was given.
+    /// This one is produced by `Calculator::expression`, not by the tokenizer.
+    Call(String, usize),
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add | Operator::Pos => "+",
+            Operator::Sub | Operator::Neg => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Pow => "^",
+            Operator::Not | Operator::Fact => "!",
+            Operator::Percent => "%",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Cond => "?:",
+            Operator::BitNot => "~",
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::BitXor => "xor",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Tokens print the way they are written, except for the ones that only
+/// exist in RPN: prefix operators print as `neg`/`pos` (to tell them apart
+/// from `-` and `+`), the postfix `!` as `fact` (to tell it apart from the
+/// logical not) and calls as `name/arguments`.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(name) | Token::Ident(name) | Token::Func(name) => write!(f, "{}", name),
+            Token::Op(Operator::Neg) => write!(f, "neg"),
+            Token::Op(Operator::Pos) => write!(f, "pos"),
+            Token::Op(Operator::Fact) => write!(f, "fact"),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Bracket(c) => write!(f, "{}", c),
+            Token::Assign => write!(f, "="),
+            Token::Comma => write!(f, ","),
+            Token::Question => write!(f, "?"),
+            Token::Colon => write!(f, ":"),
+            Token::Convert(unit) => write!(f, "to {}", unit),
+            Token::Call(name, count) => write!(f, "{}/{}", name, count),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A byte range (`start..end`) into the source the tokenizer was given.
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Any value paired with the part of the source it came from.
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+
+    /// Change the node, keeping the span.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned::new(f(self.node), self.span)
+    }
+}
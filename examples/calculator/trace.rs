@@ -0,0 +1,266 @@
+/*
+# Tracing
+
+The tokenizer, the shunting yard and the VM tell an `Observer` what they do,
+step by step, instead of printing it. Nothing is printed unless someone
+listens: `()` is the observer that ignores everything, and it's the one the
+plain `Calculator::tokenize`, `Calculator::expression` and `Vm::run` use.
+
+`Steps` records every step and renders them as tables, one per stage. For
+`1 + 2 * 3`:
+
+```text
+tokens  1 + 2 * 3
+
+token  moves                             output     operators
+1      output 1                          1
++      push +                            1          +
+2      output 2                          1 2        +
+*      push *                            1 2        + *
+3      output 3                          1 2 3      + *
+end    pop *, output *, pop +, output +  1 2 3 * +
+
+pc  instruction  stack
+0   const 1      1
+1   const 2      1, 2
+2   const 3      1, 2, 3
+3   binary *     1, 6
+4   binary +     7
+```
+*/
+
+use std::fmt;
+
+use crate::bytecode::Program;
+use crate::token::{Spanned, Token};
+use crate::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A step of the tokenizer or of the shunting yard
+pub enum Event<'a> {
+    /// The tokenizer produced a token
+    Token(&'a Spanned<Token>),
+    /// The shunting yard takes the next token
+    Read(&'a Spanned<Token>),
+    /// The shunting yard ran out of tokens, what is left on the operator
+    /// stack goes to the output
+    Flush,
+    /// A token goes on the operator stack
+    Push(&'a Spanned<Token>),
+    /// A token leaves the operator stack, to the output (see `Output`) or
+    /// dropped like an opening bracket
+    Pop(&'a Spanned<Token>),
+    /// A token is added to the output queue, the RPN
+    Output(&'a Spanned<Token>),
+}
+
+/// Receives the steps of reading and running an expression. Both methods do
+/// nothing by default, implement the ones you care about.
+pub trait Observer {
+    fn event(&mut self, _event: Event<'_>) {}
+
+    /// The VM ran the instruction at `pc` of `program`, leaving `stack`.
+    fn step<N: fmt::Display>(&mut self, _program: &Program<N>, _pc: usize, _stack: &[Value<N>]) {}
+}
+
+/// Doesn't look
+impl Observer for () {}
+
+#[derive(Debug, Default)]
+/// Records the steps, its `Display` renders them as tables.
+pub struct Steps {
+    tokens: Vec<String>,
+    /// One row per token read by the shunting yard: the token, the moves it
+    /// caused, and the output and the operator stack after them
+    yard: Vec<[String; 4]>,
+    output: Vec<String>,
+    operators: Vec<String>,
+    /// One row per instruction run: where, which, and the stack after it
+    vm: Vec<[String; 3]>,
+}
+
+impl Steps {
+    pub fn new() -> Self {
+        Steps::default()
+    }
+
+    /// Record a move of the shunting yard in the row of the current token.
+    fn moved(&mut self, action: &str, token: &Spanned<Token>) {
+        if let Some(row) = self.yard.last_mut() {
+            if !row[1].is_empty() {
+                row[1].push_str(", ");
+            }
+            row[1].push_str(&format!("{} {}", action, token.node));
+            row[2] = self.output.join(" ");
+            row[3] = self.operators.join(" ");
+        }
+    }
+}
+
+impl Observer for Steps {
+    fn event(&mut self, event: Event<'_>) {
+        match event {
+            Event::Token(token) => self.tokens.push(token.node.to_string()),
+            Event::Read(token) => self.yard.push([
+                token.node.to_string(),
+                String::new(),
+                self.output.join(" "),
+                self.operators.join(" "),
+            ]),
+            Event::Flush => self.yard.push([
+                "end".to_string(),
+                String::new(),
+                self.output.join(" "),
+                self.operators.join(" "),
+            ]),
+            Event::Push(token) => {
+                self.operators.push(token.node.to_string());
+                self.moved("push", token);
+            }
+            Event::Pop(token) => {
+                self.operators.pop();
+                self.moved("pop", token);
+            }
+            Event::Output(token) => {
+                self.output.push(token.node.to_string());
+                self.moved("output", token);
+            }
+        }
+    }
+
+    fn step<N: fmt::Display>(&mut self, program: &Program<N>, pc: usize, stack: &[Value<N>]) {
+        let stack: Vec<_> = stack.iter().map(|v| v.to_string()).collect();
+        self.vm.push([
+            pc.to_string(),
+            program.line(pc).to_string(),
+            stack.join(", "),
+        ]);
+    }
+}
+
+impl fmt::Display for Steps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tables = vec![];
+
+        if !self.tokens.is_empty() {
+            tables.push(format!("tokens  {}", self.tokens.join(" ")));
+        }
+        if !self.yard.is_empty() {
+            let header = ["token", "moves", "output", "operators"];
+            tables.push(table(header, &self.yard));
+        }
+        if !self.vm.is_empty() {
+            tables.push(table(["pc", "instruction", "stack"], &self.vm));
+        }
+
+        write!(f, "{}", tables.join("\n\n"))
+    }
+}
+
+/// Columns as wide as their widest cell, two spaces apart.
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: [&str; N]| {
+        let cells: Vec<_> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    };
+
+    let mut lines = vec![line(header)];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.each_ref().map(|c| c.as_str()))),
+    );
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Observer, Steps};
+    use crate::bytecode::{Program, Vm};
+    use crate::Calculator;
+
+    /// Every stage of `expr`, in float mode without variables
+    fn trace(expr: &str) -> String {
+        let mut steps = Steps::new();
+
+        let tokens = Calculator::tokenize_with(expr, &mut steps).unwrap();
+        let rpn = Calculator::expression_with(tokens, &mut steps).unwrap();
+        let program = Program::<f64>::compile(&rpn, &()).unwrap();
+        Vm::new().run_with(&program, |_| None, &mut steps).unwrap();
+
+        steps.to_string()
+    }
+
+    #[test]
+    fn renders_every_stage() {
+        assert_eq!(
+            trace("1 + 2 * 3"),
+            "\
+tokens  1 + 2 * 3
+
+token  moves                             output     operators
+1      output 1                          1
++      push +                            1          +
+2      output 2                          1 2        +
+*      push *                            1 2        + *
+3      output 3                          1 2 3      + *
+end    pop *, output *, pop +, output +  1 2 3 * +
+
+pc  instruction  stack
+0   const 1      1
+1   const 2      1, 2
+2   const 3      1, 2, 3
+3   binary *     1, 6
+4   binary +     7"
+        );
+    }
+
+    #[test]
+    fn brackets_calls_and_jumps() {
+        let steps = trace("max(1, (2 - 3)) > 0 ? -1 : 2");
+
+        assert!(steps.contains("\n)      pop -, output -, pop (  "));
+        assert!(steps.contains("\n)      pop (, pop max, output max/2  "));
+        // the `?` waits for its `:` which turns it into the conditional
+        assert!(steps.contains("\n:      pop neg, output neg, pop ?, push ?:  "));
+        // the condition holds, so the jump skips the `2`
+        assert!(steps.contains("\n7   jump-if-false 10\n8   const -1"));
+        assert!(steps.ends_with("\n9   jump 11           -1"));
+    }
+
+    #[test]
+    fn observes_the_moves() {
+        #[derive(Default)]
+        struct Moves(Vec<String>);
+
+        impl Observer for Moves {
+            fn event(&mut self, event: Event<'_>) {
+                match event {
+                    Event::Push(token) => self.0.push(format!("push {}", token.node)),
+                    Event::Pop(token) => self.0.push(format!("pop {}", token.node)),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut moves = Moves::default();
+        let tokens = Calculator::tokenize("2 ^ 3 ^ 2 - 1").unwrap();
+        Calculator::expression_with(tokens, &mut moves).unwrap();
+
+        // `^` is right associative, the second one doesn't pop the first
+        assert_eq!(
+            moves.0,
+            ["push ^", "push ^", "pop ^", "pop ^", "push -", "pop -"]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+/*
+# Units of measure
+
+A literal can be followed by a unit: `3 m`, `20 km/h`, `9.81 m/s^2`. In units
+mode numbers are `Quantity`s, a value in SI base units along with its
+`Dimension`, the powers of the base units it's made of. `20 km/h` is
+`5.555... m/s`, a length over a time.
+
+Quantities of different dimensions can be multiplied and divided, but not
+added, subtracted or compared: `3 m + 2 s` is an error. The functions that
+make no sense with units (`sin`, `exp`...) only take plain numbers.
+
+A line ending with `to <unit>` shows its result in that unit: `60 mph to
+m/s` is `26.8224 m/s`.
+
+Only units proportional to the SI ones are supported, no degrees Celsius.
+
+- See: https://en.wikipedia.org/wiki/Dimensional_analysis
+*/
+
+use std::fmt;
+
+use crate::EvalError;
+
+/// The SI base units, in the order of the powers of a `Dimension`
+const BASE: [&str; 7] = ["kg", "m", "s", "A", "K", "mol", "cd"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The power of each base unit: `m/s^2` is `[0, 1, -2, 0, 0, 0, 0]`.
+pub struct Dimension([i8; 7]);
+
+/// Derived units used to print a dimension, when one matches exactly
+const DERIVED: &[(&str, Dimension)] = &[
+    ("N", Dimension([1, 1, -2, 0, 0, 0, 0])),
+    ("J", Dimension([1, 2, -2, 0, 0, 0, 0])),
+    ("W", Dimension([1, 2, -3, 0, 0, 0, 0])),
+    ("Pa", Dimension([1, -1, -2, 0, 0, 0, 0])),
+    ("Hz", Dimension([0, 0, -1, 0, 0, 0, 0])),
+    ("C", Dimension([0, 0, 1, 1, 0, 0, 0])),
+    ("V", Dimension([1, 2, -3, -1, 0, 0, 0])),
+];
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension([0; 7]);
+
+    fn base(i: usize) -> Dimension {
+        let mut powers = [0; 7];
+        powers[i] = 1;
+        Dimension(powers)
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Dimension::NONE
+    }
+
+    /// The dimension of a product.
+    pub fn mul(self, rhs: Dimension) -> Option<Dimension> {
+        self.zip(rhs, |l, r| l.checked_add(r))
+    }
+
+    /// The dimension of a quotient.
+    pub fn div(self, rhs: Dimension) -> Option<Dimension> {
+        self.zip(rhs, |l, r| l.checked_sub(r))
+    }
+
+    /// The dimension of a power, `None` if a power isn't an integer anymore
+    /// (`m ^ 0.5`).
+    pub fn pow(self, exponent: f64) -> Option<Dimension> {
+        let mut powers = [0; 7];
+
+        for (power, &base) in powers.iter_mut().zip(&self.0) {
+            let scaled = base as f64 * exponent;
+
+            if scaled.fract() != 0.0 || scaled.abs() > i8::MAX as f64 {
+                return None;
+            }
+
+            *power = scaled as i8;
+        }
+
+        Some(Dimension(powers))
+    }
+
+    fn zip(self, rhs: Dimension, f: impl Fn(i8, i8) -> Option<i8>) -> Option<Dimension> {
+        let mut powers = [0; 7];
+
+        for (i, power) in powers.iter_mut().enumerate() {
+            *power = f(self.0[i], rhs.0[i])?;
+        }
+
+        Some(Dimension(powers))
+    }
+}
+
+/// Prints a derived unit if one matches (`N`), else the base units with
+/// positive powers then the ones with negative powers: `kg*m^2/s^3/A`.
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = DERIVED.iter().find(|(_, d)| d == self) {
+            return write!(f, "{}", name);
+        }
+
+        let factor = |name: &str, power: i8| match power {
+            1 => name.to_string(),
+            _ => format!("{}^{}", name, power),
+        };
+
+        let above: Vec<_> = (0..7)
+            .filter(|&i| self.0[i] > 0)
+            .map(|i| factor(BASE[i], self.0[i]))
+            .collect();
+        let below: Vec<_> = (0..7)
+            .filter(|&i| self.0[i] < 0)
+            .map(|i| format!("/{}", factor(BASE[i], -self.0[i])))
+            .collect();
+
+        match above.is_empty() {
+            true => write!(f, "1{}", below.concat()),
+            false => write!(f, "{}{}", above.join("*"), below.concat()),
+        }
+    }
+}
+
+/// A unit as written, how many SI units it is worth and its dimension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    pub factor: f64,
+    pub dimension: Dimension,
+}
+
+/// The known units: name, value in SI units and dimension
+fn units() -> Vec<(&'static str, f64, Dimension)> {
+    let [kg, m, s, a, k, mol, cd] = [0, 1, 2, 3, 4, 5, 6].map(Dimension::base);
+    let per = |l: Dimension, r: Dimension| l.div(r).unwrap();
+    let times = |l: Dimension, r: Dimension| l.mul(r).unwrap();
+
+    let speed = per(m, s);
+    let force = per(times(kg, m), times(s, s));
+    let energy = times(force, m);
+    let power = per(energy, s);
+    let volume = times(times(m, m), m);
+
+    vec![
+        // length
+        ("m", 1.0, m),
+        ("km", 1e3, m),
+        ("cm", 1e-2, m),
+        ("mm", 1e-3, m),
+        ("um", 1e-6, m),
+        ("nm", 1e-9, m),
+        ("in", 0.0254, m),
+        ("ft", 0.3048, m),
+        ("yd", 0.9144, m),
+        ("mi", 1609.344, m),
+        // mass
+        ("kg", 1.0, kg),
+        ("g", 1e-3, kg),
+        ("mg", 1e-6, kg),
+        ("lb", 0.45359237, kg),
+        ("oz", 0.028349523125, kg),
+        // time
+        ("s", 1.0, s),
+        ("ms", 1e-3, s),
+        ("us", 1e-6, s),
+        ("ns", 1e-9, s),
+        ("min", 60.0, s),
+        ("h", 3600.0, s),
+        ("day", 86400.0, s),
+        // the other base units
+        ("A", 1.0, a),
+        ("K", 1.0, k),
+        ("mol", 1.0, mol),
+        ("cd", 1.0, cd),
+        // derived
+        ("mph", 0.44704, speed),
+        ("knot", 1852.0 / 3600.0, speed),
+        ("L", 1e-3, volume),
+        ("mL", 1e-6, volume),
+        ("Hz", 1.0, per(Dimension::NONE, s)),
+        ("N", 1.0, force),
+        ("J", 1.0, energy),
+        ("kJ", 1e3, energy),
+        ("cal", 4.184, energy),
+        ("kcal", 4184.0, energy),
+        ("kWh", 3.6e6, energy),
+        ("W", 1.0, power),
+        ("kW", 1e3, power),
+        ("Pa", 1.0, per(force, times(m, m))),
+        ("kPa", 1e3, per(force, times(m, m))),
+        ("bar", 1e5, per(force, times(m, m))),
+        ("atm", 101325.0, per(force, times(m, m))),
+        ("C", 1.0, times(a, s)),
+        ("V", 1.0, per(power, a)),
+    ]
+}
+
+fn lookup(name: &str) -> Option<(f64, Dimension)> {
+    units()
+        .into_iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, factor, dimension)| (factor, dimension))
+}
+
+/// Parse a unit: names of units, each with an optional integer power,
+/// joined by `*` and `/` and read left to right. `kg*m/s^2` is a newton.
+pub fn parse(text: &str) -> Result<Unit, EvalError> {
+    let unknown = || EvalError::UnknownUnit(text.to_string());
+
+    let mut unit = Unit {
+        name: text.to_string(),
+        factor: 1.0,
+        dimension: Dimension::NONE,
+    };
+    let mut rest = text;
+    let mut divide = false;
+
+    loop {
+        let end = rest.find(['*', '/']).unwrap_or(rest.len());
+        let (factor, power) = match rest[..end].split_once('^') {
+            Some((name, power)) => (name, power.parse::<i8>().map_err(|_| unknown())?),
+            None => (&rest[..end], 1),
+        };
+
+        let (value, dimension) = lookup(factor).ok_or_else(unknown)?;
+        let dimension = dimension.pow(power as f64).ok_or_else(unknown)?;
+        let value = value.powi(power as i32);
+
+        (unit.factor, unit.dimension) = match divide {
+            true => (
+                unit.factor / value,
+                unit.dimension.div(dimension).ok_or_else(unknown)?,
+            ),
+            false => (
+                unit.factor * value,
+                unit.dimension.mul(dimension).ok_or_else(unknown)?,
+            ),
+        };
+
+        match rest[end..].chars().next() {
+            Some(op) => {
+                divide = op == '/';
+                rest = &rest[end + 1..];
+            }
+            None => return Ok(unit),
+        }
+    }
+}
+
+/// The length of the unit at the start of `text`, `0` if there's none. Only
+/// known units count, and a name followed by `(` is a function call, so in
+/// `2 min(3, 4)` there's no unit.
+pub fn scan(text: &str) -> usize {
+    let mut end = 0;
+
+    loop {
+        let rest = &text[end..];
+        let name = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+
+        if lookup(&rest[..name]).is_none() || rest[name..].starts_with('(') {
+            return end;
+        }
+
+        let mut next = end + name;
+
+        // an integer power, `m^2` or `s^-1`
+        if let Some(power) = text[next..].strip_prefix('^') {
+            let digits = power.strip_prefix('-').unwrap_or(power);
+            let count = digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len());
+
+            if count > 0 {
+                next += 1 + (power.len() - digits.len()) + count;
+            }
+        }
+
+        end = next;
+
+        // `*` or `/` go on with the unit only if a unit follows right away
+        match text[end..].chars().next() {
+            Some('*' | '/') if lookup_prefix(&text[end + 1..]) => end += 1,
+            _ => return end,
+        }
+    }
+}
+
+/// Does `text` start with a known unit?
+fn lookup_prefix(text: &str) -> bool {
+    let name = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+
+    lookup(&text[..name]).is_some() && !text[name..].starts_with('(')
+}
+
+/// Split a literal from the tokenizer into its number and its unit, if it
+/// has one: `"20 km/h"` is `("20", Some("km/h"))`.
+pub fn split(literal: &str) -> (&str, Option<&str>) {
+    match literal.split_once(' ') {
+        Some((number, unit)) => (number, Some(unit)),
+        None => (literal, None),
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A number with a dimension, the number of units mode. The value is in SI
+/// units, `unit` is only how it's shown, after a `to` conversion.
+pub struct Quantity {
+    value: f64,
+    dimension: Dimension,
+    unit: Option<(String, f64)>,
+}
+
+impl Quantity {
+    pub fn new(value: f64, dimension: Dimension) -> Self {
+        Quantity {
+            value,
+            dimension,
+            unit: None,
+        }
+    }
+
+    /// A plain number
+    pub fn number(value: f64) -> Self {
+        Quantity::new(value, Dimension::NONE)
+    }
+
+    /// The value in SI units
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.dimension
+    }
+
+    /// The same quantity, shown in `unit`.
+    pub fn to(&self, unit: &Unit) -> Result<Quantity, EvalError> {
+        self.same_dimension(unit.dimension)?;
+
+        Ok(Quantity {
+            unit: Some((unit.name.clone(), unit.factor)),
+            ..self.clone()
+        })
+    }
+
+    /// Quantities can only be added, subtracted or compared when they have
+    /// the same dimension.
+    pub fn same_dimension(&self, dimension: Dimension) -> Result<(), EvalError> {
+        match self.dimension == dimension {
+            true => Ok(()),
+            false => Err(EvalError::IncompatibleUnits(
+                self.dimension.to_string(),
+                dimension.to_string(),
+            )),
+        }
+    }
+
+    /// Only plain numbers are accepted by `sin`, `exp`, as exponents...
+    pub fn plain(&self) -> Result<f64, EvalError> {
+        match self.dimension.is_none() {
+            true => Ok(self.value),
+            false => Err(EvalError::NotDimensionless(self.dimension.to_string())),
+        }
+    }
+}
+
+/// Quantities are equal when they are the same amount, whatever the unit they
+/// are shown in.
+impl PartialEq for Quantity {
+    fn eq(&self, other: &Self) -> bool {
+        self.dimension == other.dimension && self.value == other.value
+    }
+}
+
+impl PartialOrd for Quantity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self.dimension == other.dimension {
+            true => self.value.partial_cmp(&other.value),
+            false => None,
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some((name, factor)) => write!(f, "{} {}", self.value / factor, name),
+            None if self.dimension.is_none() => write!(f, "{}", self.value),
+            None => write!(f, "{} {}", self.value, self.dimension),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, scan, split, Dimension, Quantity};
+    use crate::EvalError;
+
+    #[test]
+    fn parses_units() {
+        let newton = parse("kg*m/s^2").unwrap();
+        assert_eq!(newton.factor, 1.0);
+        assert_eq!(newton.dimension.to_string(), "N");
+
+        let speed = parse("km/h").unwrap();
+        assert!((speed.factor - 1000.0 / 3600.0).abs() < 1e-15);
+        assert_eq!(speed.dimension.to_string(), "m/s");
+
+        assert_eq!(
+            parse("m^3").unwrap().dimension,
+            parse("L").unwrap().dimension
+        );
+        assert_eq!(parse("s^-1").unwrap().dimension.to_string(), "Hz");
+        assert_eq!(parse("kg*m^2/s^3/A").unwrap().dimension.to_string(), "V");
+        assert_eq!(parse("m/s/kg").unwrap().dimension.to_string(), "m/kg/s");
+
+        for bad in ["furlong", "m/", "m^x", "*m", "m^1000"] {
+            assert_eq!(
+                parse(bad),
+                Err(EvalError::UnknownUnit(bad.into())),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn scans_units() {
+        for (text, unit) in [
+            ("m", "m"),
+            ("km/h + 1", "km/h"),
+            ("m/s^2)", "m/s^2"),
+            ("kg*m/s^-2", "kg*m/s^-2"),
+            ("m*2", "m"),
+            ("m*x", "m"),
+            ("m^", "m"),
+            ("min(3, 4)", ""),
+            ("x", ""),
+            ("to m", ""),
+        ] {
+            assert_eq!(&text[..scan(text)], unit, "{}", text);
+        }
+
+        assert_eq!(split("20 km/h"), ("20", Some("km/h")));
+        assert_eq!(split("20"), ("20", None));
+    }
+
+    #[test]
+    fn quantities() {
+        let speed = Quantity::new(10.0, parse("m/s").unwrap().dimension);
+        assert_eq!(speed.to_string(), "10 m/s");
+        assert_eq!(
+            speed.to(&parse("km/h").unwrap()).unwrap().to_string(),
+            "36 km/h"
+        );
+        assert_eq!(
+            speed.to(&parse("kg").unwrap()),
+            Err(EvalError::IncompatibleUnits("m/s".into(), "kg".into()))
+        );
+
+        assert_eq!(Quantity::number(2.5).to_string(), "2.5");
+        assert_eq!(
+            Quantity::new(2.0, Dimension::NONE.div(Dimension::base(2)).unwrap()).to_string(),
+            "2 Hz"
+        );
+        assert_eq!(
+            speed.plain(),
+            Err(EvalError::NotDimensionless("m/s".into()))
+        );
+    }
+}
@@ -0,0 +1,397 @@
+/*
+# Fixed width integers
+
+The numbers of "programmer mode": integers of a given width (`i8` to `i64`,
+`u8` to `u64`) like the ones a register holds, so that the bitwise operators
+and the shifts behave the way they do in C or Rust.
+
+A result that doesn't fit in the width either wraps around (keeping its low
+bits, so `255 + 1` is `0` for an `u8`) or is reported as an overflow,
+depending on the `Overflow` of the type.
+
+Values are kept as an `i128` holding the number itself (`-1` for an `i8`
+with all its bits set). That's wide enough to compute most operations on
+64 bits integers exactly and then check whether the result fits.
+*/
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::EvalError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What happens to results that don't fit in the width
+pub enum Overflow {
+    /// Keep the low bits, like the `wrapping_*` methods of the primitive integers
+    Wrap,
+    /// Report an `EvalError::Overflow`, like the `checked_*` methods
+    Check,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Wrap => write!(f, "wrap"),
+            Overflow::Check => write!(f, "check"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An integer type such as `u8` or `i32`, along with how it overflows
+pub struct WordType {
+    pub bits: u32,
+    pub signed: bool,
+    pub overflow: Overflow,
+}
+
+/// `i64`, checking for overflows
+impl Default for WordType {
+    fn default() -> Self {
+        WordType {
+            bits: 64,
+            signed: true,
+            overflow: Overflow::Check,
+        }
+    }
+}
+
+impl WordType {
+    /// The type called `name` (`i8`, `u32`...), if there is one.
+    pub fn named(name: &str, overflow: Overflow) -> Option<WordType> {
+        let signed = match name.get(..1)? {
+            "i" => true,
+            "u" => false,
+            _ => return None,
+        };
+
+        let bits = match &name[1..] {
+            "8" => 8,
+            "16" => 16,
+            "32" => 32,
+            "64" => 64,
+            _ => return None,
+        };
+
+        Some(WordType {
+            bits,
+            signed,
+            overflow,
+        })
+    }
+
+    pub fn min(&self) -> i128 {
+        if self.signed {
+            -(1 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(&self) -> i128 {
+        if self.signed {
+            (1 << (self.bits - 1)) - 1
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    /// The low `bits` bits of `value`, read as this type.
+    fn truncate(&self, value: i128) -> i128 {
+        let low = value & ((1 << self.bits) - 1);
+
+        if low > self.max() {
+            low - (1 << self.bits)
+        } else {
+            low
+        }
+    }
+
+    /// `value` as this type, wrapped or refused when it doesn't fit.
+    pub fn word(&self, value: i128) -> Result<Word, EvalError> {
+        let value = match self.overflow {
+            _ if (self.min()..=self.max()).contains(&value) => value,
+            Overflow::Wrap => self.truncate(value),
+            Overflow::Check => return Err(EvalError::Overflow),
+        };
+
+        Ok(Word { value, ty: *self })
+    }
+
+    /// A bit pattern (from a `0xff` like literal) read as this type, so an
+    /// `i8` `0xff` is `-1`. Patterns wider than the type are an overflow.
+    pub fn pattern(&self, pattern: i128) -> Result<Word, EvalError> {
+        if pattern >> self.bits != 0 && self.overflow == Overflow::Check {
+            return Err(EvalError::Overflow);
+        }
+
+        Ok(Word {
+            value: self.truncate(pattern),
+            ty: *self,
+        })
+    }
+
+    /// The result of an `i128` operation: `exact` when it didn't overflow the
+    /// `i128` itself, and `wrapped` modulo 2^128 (which has the right low bits).
+    fn result(&self, exact: Option<i128>, wrapped: i128) -> Result<Word, EvalError> {
+        match (exact, self.overflow) {
+            (Some(value), _) => self.word(value),
+            (None, Overflow::Wrap) => self.word(wrapped),
+            (None, Overflow::Check) => Err(EvalError::Overflow),
+        }
+    }
+
+    /// The number of bits a shift moves by. Unlike `wrapping_shl`, shifting
+    /// by the width or more doesn't wrap around the width, it moves every
+    /// bit out: `1 << 8` is `0` for an `u8` and `-16 >> 8` is `-1` for an
+    /// `i8`. That's at most 64 bits, an `i128` can be shifted by that.
+    fn shift(&self, amount: &Word) -> Result<u32, EvalError> {
+        match amount.value {
+            ..0 => Err(EvalError::NegativeShift),
+            amount => Ok(amount.min(self.bits as i128) as u32),
+        }
+    }
+}
+
+/// Prints the type the way Rust does, `u8` or `i64`.
+impl fmt::Display for WordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", if self.signed { "i" } else { "u" }, self.bits)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A fixed width integer. Both operands of an operation are expected to be
+/// of the same type, the result has the type of the left one.
+pub struct Word {
+    value: i128,
+    ty: WordType,
+}
+
+impl Word {
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+
+    pub fn ty(&self) -> WordType {
+        self.ty
+    }
+
+    /// The bits of the value in two's complement (`0xff` for an `i8` `-1`).
+    pub fn pattern(&self) -> u64 {
+        (self.value & ((1 << self.ty.bits) - 1)) as u64
+    }
+
+    pub fn add(&self, rhs: &Word) -> Result<Word, EvalError> {
+        let (l, r) = (self.value, rhs.value);
+        self.ty.result(l.checked_add(r), l.wrapping_add(r))
+    }
+
+    pub fn sub(&self, rhs: &Word) -> Result<Word, EvalError> {
+        let (l, r) = (self.value, rhs.value);
+        self.ty.result(l.checked_sub(r), l.wrapping_sub(r))
+    }
+
+    pub fn mul(&self, rhs: &Word) -> Result<Word, EvalError> {
+        let (l, r) = (self.value, rhs.value);
+        self.ty.result(l.checked_mul(r), l.wrapping_mul(r))
+    }
+
+    /// Division truncating towards zero.
+    pub fn div(&self, rhs: &Word) -> Result<Word, EvalError> {
+        if rhs.value == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+
+        // `MIN / -1` is the only overflow, and it can't happen on an `i128`
+        self.ty.word(self.value / rhs.value)
+    }
+
+    /// Negative exponents have no integer result.
+    pub fn pow(&self, rhs: &Word) -> Result<Word, EvalError> {
+        if rhs.value < 0 {
+            return Err(EvalError::NotAnInteger);
+        }
+
+        let exponent = u32::try_from(rhs.value).map_err(|_| EvalError::Overflow)?;
+        let base = self.value;
+        self.ty
+            .result(base.checked_pow(exponent), base.wrapping_pow(exponent))
+    }
+
+    pub fn neg(&self) -> Result<Word, EvalError> {
+        self.ty.word(-self.value)
+    }
+
+    pub fn not(&self) -> Word {
+        Word {
+            value: self.ty.truncate(!self.value),
+            ty: self.ty,
+        }
+    }
+
+    pub fn and(&self, rhs: &Word) -> Word {
+        Word {
+            value: self.ty.truncate(self.value & rhs.value),
+            ty: self.ty,
+        }
+    }
+
+    pub fn or(&self, rhs: &Word) -> Word {
+        Word {
+            value: self.ty.truncate(self.value | rhs.value),
+            ty: self.ty,
+        }
+    }
+
+    pub fn xor(&self, rhs: &Word) -> Word {
+        Word {
+            value: self.ty.truncate(self.value ^ rhs.value),
+            ty: self.ty,
+        }
+    }
+
+    /// Shifting left drops the bits that go past the width, it never
+    /// overflows (`checked_shl` doesn't either).
+    pub fn shl(&self, rhs: &Word) -> Result<Word, EvalError> {
+        let amount = self.ty.shift(rhs)?;
+
+        Ok(Word {
+            value: self.ty.truncate(self.value << amount),
+            ty: self.ty,
+        })
+    }
+
+    /// Arithmetic shift for the signed types, logical for the unsigned ones.
+    pub fn shr(&self, rhs: &Word) -> Result<Word, EvalError> {
+        let amount = self.ty.shift(rhs)?;
+
+        Ok(Word {
+            value: self.value >> amount,
+            ty: self.ty,
+        })
+    }
+}
+
+impl PartialEq for Word {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl PartialOrd for Word {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Overflow, WordType};
+    use crate::EvalError;
+
+    fn ty(name: &str, overflow: Overflow) -> WordType {
+        WordType::named(name, overflow).unwrap()
+    }
+
+    #[test]
+    fn types() {
+        let u8 = ty("u8", Overflow::Check);
+        assert_eq!((u8.min(), u8.max()), (0, 255));
+
+        let i64 = ty("i64", Overflow::Check);
+        assert_eq!((i64.min(), i64.max()), (i64::MIN as i128, i64::MAX as i128));
+        assert_eq!(i64, WordType::default());
+        assert_eq!(ty("u32", Overflow::Wrap).to_string(), "u32");
+
+        for name in ["", "u", "i7", "u128", "f32", "x8"] {
+            assert_eq!(WordType::named(name, Overflow::Wrap), None);
+        }
+    }
+
+    #[test]
+    fn wrapping() {
+        let u8 = ty("u8", Overflow::Wrap);
+        let i8 = ty("i8", Overflow::Wrap);
+        let u64 = ty("u64", Overflow::Wrap);
+
+        let n = |ty: WordType, value| ty.word(value).unwrap();
+
+        assert_eq!(n(u8, 255).add(&n(u8, 1)).unwrap().value(), 0);
+        assert_eq!(n(u8, 0).sub(&n(u8, 1)).unwrap().value(), 255);
+        assert_eq!(n(i8, 127).add(&n(i8, 1)).unwrap().value(), -128);
+        assert_eq!(n(i8, -128).div(&n(i8, -1)).unwrap().value(), -128);
+        assert_eq!(n(i8, -128).neg().unwrap().value(), -128);
+        assert_eq!(n(u8, 3).pow(&n(u8, 5)).unwrap().value(), 243);
+        assert_eq!(n(u8, 3).pow(&n(u8, 6)).unwrap().value(), 729 % 256);
+        assert_eq!(n(u8, 300).value(), 44);
+
+        // u64::MAX * u64::MAX overflows even the i128 it is computed in
+        let max = n(u64, u64::MAX as i128);
+        assert_eq!(max.mul(&max).unwrap().value(), 1);
+        assert_eq!(max.pow(&n(u64, 3)).unwrap().value(), u64::MAX as i128);
+    }
+
+    #[test]
+    fn checking() {
+        let u8 = ty("u8", Overflow::Check);
+        let i8 = ty("i8", Overflow::Check);
+
+        let n = |ty: WordType, value| ty.word(value).unwrap();
+
+        assert_eq!(n(u8, 255).add(&n(u8, 1)), Err(EvalError::Overflow));
+        assert_eq!(n(u8, 0).sub(&n(u8, 1)), Err(EvalError::Overflow));
+        assert_eq!(n(u8, 1).neg(), Err(EvalError::Overflow));
+        assert_eq!(n(i8, -128).div(&n(i8, -1)), Err(EvalError::Overflow));
+        assert_eq!(n(i8, 2).pow(&n(i8, 7)), Err(EvalError::Overflow));
+        assert_eq!(n(i8, 2).pow(&n(i8, -1)), Err(EvalError::NotAnInteger));
+        assert_eq!(n(i8, 2).div(&n(i8, 0)), Err(EvalError::DivisionByZero));
+        assert_eq!(u8.word(256), Err(EvalError::Overflow));
+        assert_eq!(n(i8, -7).div(&n(i8, 2)).unwrap().value(), -3);
+    }
+
+    #[test]
+    fn bits() {
+        let u8 = ty("u8", Overflow::Check);
+        let i8 = ty("i8", Overflow::Check);
+        let wrap = ty("u8", Overflow::Wrap);
+
+        let n = |ty: WordType, value| ty.word(value).unwrap();
+
+        assert_eq!(n(u8, 0b1100).and(&n(u8, 0b1010)).value(), 0b1000);
+        assert_eq!(n(u8, 0b1100).or(&n(u8, 0b1010)).value(), 0b1110);
+        assert_eq!(n(u8, 0b1100).xor(&n(u8, 0b1010)).value(), 0b0110);
+        assert_eq!(n(u8, 0).not().value(), 255);
+        assert_eq!(n(i8, 0).not().value(), -1);
+        assert_eq!(n(i8, -1).pattern(), 0xff);
+
+        assert_eq!(n(u8, 0xff).shl(&n(u8, 4)).unwrap().value(), 0xf0);
+        assert_eq!(n(u8, 0xf0).shr(&n(u8, 4)).unwrap().value(), 0x0f);
+        assert_eq!(n(i8, -16).shr(&n(i8, 2)).unwrap().value(), -4);
+        // by the width or more every bit is out, whatever the overflow mode
+        for ty in [u8, wrap] {
+            assert_eq!(n(ty, 1).shl(&n(ty, 8)).unwrap().value(), 0);
+            assert_eq!(n(ty, 0xff).shl(&n(ty, 200)).unwrap().value(), 0);
+            assert_eq!(n(ty, 0xff).shr(&n(ty, 8)).unwrap().value(), 0);
+        }
+        assert_eq!(n(i8, -16).shr(&n(i8, 8)).unwrap().value(), -1);
+        assert_eq!(n(i8, 16).shr(&n(i8, 100)).unwrap().value(), 0);
+        assert_eq!(n(i8, 1).shl(&n(i8, 8)).unwrap().value(), 0);
+
+        let signed = ty("i8", Overflow::Wrap);
+        for ty in [i8, signed] {
+            assert_eq!(n(ty, 1).shl(&n(ty, -1)), Err(EvalError::NegativeShift));
+            assert_eq!(n(ty, 1).shr(&n(ty, -8)), Err(EvalError::NegativeShift));
+        }
+
+        assert_eq!(i8.pattern(0xff).unwrap().value(), -1);
+        assert_eq!(u8.pattern(0x1ff), Err(EvalError::Overflow));
+        assert_eq!(wrap.pattern(0x1ff).unwrap().value(), 0xff);
+    }
+}